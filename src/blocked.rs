@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::parse::ThreadStopStatus;
+use crate::threads::{Thread, ThreadState};
+use crate::types::ThreadId;
+
+/// Why a thread was blocked, with the stop statuses grouped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlockReason {
+    MVar,
+    MVarRead,
+    BlackHole,
+    Stm,
+    Delay,
+    /// A foreign call, safe or interruptible
+    ForeignCall,
+    ThrowTo,
+    Other,
+}
+
+impl BlockReason {
+    pub fn of(status: ThreadStopStatus) -> BlockReason {
+        use ThreadStopStatus::*;
+
+        match status {
+            BlockedOnMVar => BlockReason::MVar,
+            BlockedOnMVarRead => BlockReason::MVarRead,
+            BlockedOnBlackHole { .. } => BlockReason::BlackHole,
+            BlockedOnSTM => BlockReason::Stm,
+            BlockedOnDelay => BlockReason::Delay,
+            ForeignCall
+            | BlockedOnCCall
+            | BlockedOnCCallInterruptible
+            | BlockedOnCCallNoUnblockExc => BlockReason::ForeignCall,
+            BlockedOnMsgThrowTo { .. } => BlockReason::ThrowTo,
+            _ => BlockReason::Other,
+        }
+    }
+}
+
+impl fmt::Display for BlockReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BlockReason::MVar => "mvar",
+            BlockReason::MVarRead => "mvar-read",
+            BlockReason::BlackHole => "blackhole",
+            BlockReason::Stm => "stm",
+            BlockReason::Delay => "delay",
+            BlockReason::ForeignCall => "foreign-call",
+            BlockReason::ThrowTo => "throw-to",
+            BlockReason::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Time spent blocked, see [`blocked_time`].
+#[derive(Debug, Clone, Default)]
+pub struct BlockedTime {
+    /// Time each thread was blocked, by reason
+    pub threads: BTreeMap<ThreadId, BTreeMap<BlockReason, Duration>>,
+    /// Time all threads together were blocked, by reason
+    pub total: BTreeMap<BlockReason, Duration>,
+    /// Who blocks whom: for every blocking thread, the time each thread was blocked by it
+    ///
+    /// Only blocking on a black hole and on throwTo have a known blocking thread.
+    pub blockers: BTreeMap<ThreadId, BTreeMap<ThreadId, Duration>>,
+}
+
+/// Add up the time the threads spent blocked.
+pub fn blocked_time(threads: &BTreeMap<ThreadId, Thread>) -> BlockedTime {
+    let mut blocked = BlockedTime::default();
+
+    for (threadid, thread) in threads {
+        for interval in &thread.intervals {
+            let ThreadState::Blocked(status) = interval.state else {
+                continue;
+            };
+            let duration = interval.end - interval.start;
+            let reason = BlockReason::of(status);
+
+            *blocked.threads.entry(*threadid).or_default().entry(reason).or_default() += duration;
+            *blocked.total.entry(reason).or_default() += duration;
+            if let Some(blocker) = status.blocked_on() {
+                *blocked.blockers.entry(blocker).or_default().entry(*threadid).or_default() += duration;
+            }
+        }
+    }
+
+    blocked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threads::StateInterval;
+    use crate::types::{CapNo, Timestamp};
+
+    fn thread(intervals: &[(u64, u64, ThreadState)]) -> Thread {
+        Thread {
+            intervals: intervals.iter()
+                .map(|(start, end, state)| StateInterval { start: Timestamp(*start), end: Timestamp(*end), state: *state })
+                .collect(),
+            ..Thread::default()
+        }
+    }
+
+    #[test]
+    fn by_reason_and_blocker() {
+        use ThreadStopStatus::*;
+
+        let blocker = Some(ThreadId(2));
+        let threads = BTreeMap::from([
+            (ThreadId(1), thread(&[
+                (0, 10, ThreadState::Blocked(BlockedOnBlackHole { owner: blocker })),
+                (10, 20, ThreadState::Running(CapNo(0))),
+                (20, 25, ThreadState::Blocked(BlockedOnMVar)),
+                (25, 30, ThreadState::Blocked(BlockedOnCCall)),
+            ])),
+            (ThreadId(3), thread(&[
+                (5, 8, ThreadState::Blocked(BlockedOnMsgThrowTo { target: blocker })),
+                (8, 10, ThreadState::Runnable),
+                (10, 14, ThreadState::Blocked(ForeignCall)),
+            ])),
+        ]);
+
+        let blocked = blocked_time(&threads);
+        let nanos = Duration::from_nanos;
+        assert_eq!(blocked.threads[&ThreadId(1)], BTreeMap::from([
+            (BlockReason::MVar, nanos(5)),
+            (BlockReason::BlackHole, nanos(10)),
+            (BlockReason::ForeignCall, nanos(5)),
+        ]));
+        assert_eq!(blocked.total, BTreeMap::from([
+            (BlockReason::MVar, nanos(5)),
+            (BlockReason::BlackHole, nanos(10)),
+            (BlockReason::ForeignCall, nanos(9)),
+            (BlockReason::ThrowTo, nanos(3)),
+        ]));
+        assert_eq!(blocked.blockers, BTreeMap::from([
+            (ThreadId(2), BTreeMap::from([(ThreadId(1), nanos(10)), (ThreadId(3), nanos(3))])),
+        ]));
+    }
+}
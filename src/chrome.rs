@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::heap::HeapSeries;
+use crate::json;
+use crate::order::RawEvent;
+use crate::parse::{Context, EventlogParser};
+use crate::timeline::{Activity, Timeline};
+use crate::types::{CapNo, CapsetId, ThreadId, Timestamp};
+
+/// Microseconds, the time unit of the trace event format.
+fn micros(time: u64) -> String {
+    format!("{}.{:03}", time / 1000, time % 1000)
+}
+
+/// Builds a trace in the Chrome trace event format, for `chrome://tracing` and
+/// <https://ui.perfetto.dev>.
+///
+/// Every capability is a track with a slice for every thread it runs, named by the label of the
+/// thread, and for every GC. User markers are instant events on the track of their capability,
+/// and the heap numbers of every capset are counter tracks.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]) with [`ChromeTrace::add`],
+/// then write the trace with [`ChromeTrace::write`].
+#[derive(Default)]
+pub struct ChromeTrace {
+    timeline: Timeline,
+    heap: HeapSeries,
+    labels: HashMap<ThreadId, String>,
+    markers: Vec<(Timestamp, Option<CapNo>, String)>,
+    program: Option<String>,
+    pid: Option<u32>,
+}
+
+impl ChromeTrace {
+    pub fn new() -> Self {
+        ChromeTrace::default()
+    }
+
+    pub fn add(&mut self, event: &RawEvent) {
+        event.decode(&mut self.timeline);
+        event.decode(&mut self.heap);
+        event.decode(self);
+    }
+
+    /// Write the trace as JSON.
+    pub fn write<W: Write>(self, mut out: W) -> io::Result<W> {
+        let pid = self.pid.unwrap_or(1);
+        let timelines = self.timeline.finish();
+        let heap = self.heap.finish();
+
+        writeln!(out, "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")?;
+
+        let name = self.program.as_deref().unwrap_or("ghc");
+        write!(out, "{{\"ph\":\"M\",\"name\":\"process_name\",\"pid\":{pid},\"args\":{{\"name\":{}}}}}",
+            json::string(name))?;
+
+        for (capno, intervals) in &timelines {
+            write!(out, ",\n{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{pid},\"tid\":{},\"args\":{{\"name\":\"cap {}\"}}}}",
+                capno.0, capno.0)?;
+
+            for interval in intervals {
+                let (name, cat, args) = match interval.activity {
+                    Activity::Running(threadid) => {
+                        let name = match self.labels.get(&threadid) {
+                            Some(label) => label.clone(),
+                            None => format!("thread {}", threadid.0),
+                        };
+                        (name, "thread", format!("{{\"thread\":{}}}", threadid.0))
+                    },
+                    Activity::Gc => ("GC".to_string(), "gc", "{}".to_string()),
+                    Activity::Idle => continue,
+                };
+                write!(out, ",\n{{\"ph\":\"X\",\"name\":{},\"cat\":\"{cat}\",\"ts\":{},\"dur\":{},\"pid\":{pid},\"tid\":{},\"args\":{args}}}",
+                    json::string(&name),
+                    micros(interval.start.0),
+                    micros((interval.end - interval.start).as_nanos() as u64),
+                    capno.0)?;
+            }
+        }
+
+        for (time, capno, marker) in &self.markers {
+            match capno {
+                Some(capno) => write!(out, ",\n{{\"ph\":\"i\",\"s\":\"t\",\"name\":{},\"cat\":\"marker\",\"ts\":{},\"pid\":{pid},\"tid\":{}}}",
+                    json::string(marker), micros(time.0), capno.0)?,
+                None => write!(out, ",\n{{\"ph\":\"i\",\"s\":\"p\",\"name\":{},\"cat\":\"marker\",\"ts\":{},\"pid\":{pid}}}",
+                    json::string(marker), micros(time.0))?,
+            }
+        }
+
+        for (capset, samples) in &heap {
+            for sample in samples {
+                let mut args = Vec::new();
+                if let Some(size) = sample.size {
+                    args.push(format!("\"size\":{size}"));
+                }
+                if let Some(blocks) = sample.blocks {
+                    args.push(format!("\"blocks\":{blocks}"));
+                }
+                if let Some(live) = sample.live {
+                    args.push(format!("\"live\":{live}"));
+                }
+                if !args.is_empty() {
+                    write!(out, ",\n{{\"ph\":\"C\",\"name\":\"heap {capset}\",\"ts\":{},\"pid\":{pid},\"args\":{{{}}}}}",
+                        micros(sample.time.0), args.join(","))?;
+                }
+                if let Some(rate) = sample.allocation_rate {
+                    write!(out, ",\n{{\"ph\":\"C\",\"name\":\"allocation rate {capset}\",\"ts\":{},\"pid\":{pid},\"args\":{{\"bytes/s\":{:.0}}}}}",
+                        micros(sample.time.0), rate)?;
+                }
+            }
+        }
+
+        writeln!(out, "\n]}}")?;
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+impl EventlogParser for ChromeTrace {
+    fn event_thread_label(&mut self, _ctx: &Context, _threadid: ThreadId, _label: Vec<u8>) {
+        self.labels.insert(_threadid, String::from_utf8_lossy(&_label).into_owned());
+    }
+
+    fn event_user_marker(&mut self, _ctx: &Context, _marker: Vec<u8>) {
+        self.markers.push((_ctx.time, _ctx.capno, String::from_utf8_lossy(&_marker).into_owned()));
+    }
+
+    fn event_program_args(&mut self, _ctx: &Context, _capset: CapsetId, _args: Vec<u8>) {
+        let program = _args.split(|b| *b == 0).next().unwrap_or_default();
+        self.program.get_or_insert_with(|| String::from_utf8_lossy(program).into_owned());
+    }
+
+    fn event_osprocess_pid(&mut self, _ctx: &Context, _capset: CapsetId, _pid: u32) {
+        self.pid.get_or_insert(_pid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::order::TimeOrder;
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn trace() {
+        let eventlog = write(|w| {
+            w.event(30, Timestamp(0), &[&Payload::new().u32(0)[..], b"prog\0-N2\0"].concat())?;
+            w.event(32, Timestamp(0), &Payload::new().u32(0).u32(42))?;
+            w.begin_block(Timestamp(1000), Timestamp(4500), Some(CapNo(0)))?;
+            w.event(44, Timestamp(1000), &[&Payload::new().u32(1)[..], b"main \"loop\""].concat())?;
+            w.event(1, Timestamp(1000), &Payload::new().u32(1))?;
+            w.event(2, Timestamp(3500), &Payload::new().u32(1).u16(3).u32(0))?;
+            w.event(9, Timestamp(4000), &[])?;
+            w.event(10, Timestamp(4500), &[])?;
+            w.begin_block(Timestamp(2000), Timestamp(2500), Some(CapNo(1)))?;
+            w.event(1, Timestamp(2000), &Payload::new().u32(2))?;
+            w.event(2, Timestamp(2500), &Payload::new().u32(2).u16(3).u32(0))?;
+            w.end_block()?;
+            w.event(50, Timestamp(4500), &Payload::new().u32(0).u64(1 << 20))?;
+            Ok(())
+        });
+
+        let mut trace = ChromeTrace::new();
+        let mut order = TimeOrder::new(|event: RawEvent| trace.add(&event));
+        parse_reader(&eventlog[..], &mut order).unwrap();
+        order.finish();
+
+        let json = String::from_utf8(trace.write(Vec::new()).unwrap()).unwrap();
+        assert_eq!(json, r#"{"displayTimeUnit":"ns","traceEvents":[
+{"ph":"M","name":"process_name","pid":42,"args":{"name":"prog"}},
+{"ph":"M","name":"thread_name","pid":42,"tid":0,"args":{"name":"cap 0"}},
+{"ph":"X","name":"main \"loop\"","cat":"thread","ts":1.000,"dur":2.500,"pid":42,"tid":0,"args":{"thread":1}},
+{"ph":"X","name":"GC","cat":"gc","ts":4.000,"dur":0.500,"pid":42,"tid":0,"args":{}},
+{"ph":"M","name":"thread_name","pid":42,"tid":1,"args":{"name":"cap 1"}},
+{"ph":"X","name":"thread 2","cat":"thread","ts":2.000,"dur":0.500,"pid":42,"tid":1,"args":{"thread":2}},
+{"ph":"C","name":"heap cs:0","ts":4.500,"pid":42,"args":{"size":1048576}}
+]}
+"#);
+    }
+}
@@ -0,0 +1,358 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::builder::{ListBuilder, StringBuilder, UInt16Builder, UInt32Builder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::export::{Record, Value};
+
+/// The type of a column of a [`Table`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Text,
+    /// A cost centre stack
+    List,
+}
+
+/// A table of the events of one family, with a column for every field of those events.
+///
+/// Every table starts with the columns `time`, `cap` and `event`, the columns after those are
+/// named like the fields of [`Record`]s and are null for the events that do not have them.
+#[derive(Debug)]
+pub struct Table {
+    pub name: &'static str,
+    /// The names of the events in the table
+    pub events: &'static [&'static str],
+    pub columns: &'static [(&'static str, Type)],
+}
+
+pub const TABLES: &[Table] = &[
+    Table {
+        name: "threads",
+        events: &["thread_create", "thread_run", "thread_stop", "thread_label", "thread_runnable", "thread_migrate", "thread_wakeup"],
+        columns: &[("thread", Type::Int), ("status", Type::Text), ("blocked_on", Type::Int), ("to_cap", Type::Int), ("label", Type::Text)],
+    },
+    Table {
+        name: "gc",
+        events: &["request_seq_gc", "request_par_gc", "gc_start", "gc_end", "gc_work", "gc_idle", "gc_done", "gc_global_sync", "gc_stats_ghc"],
+        columns: &[
+            ("capset", Type::Int), ("gen", Type::Int), ("copied", Type::Int), ("slop", Type::Int), ("fragmentation", Type::Int),
+            ("threads", Type::Int), ("max_copied", Type::Int), ("total_copied", Type::Int), ("balanced_copied", Type::Int),
+        ],
+    },
+    Table {
+        name: "heap",
+        events: &["heap_info_ghc", "heap_allocated", "heap_size", "heap_live", "blocks_size", "mem_return"],
+        columns: &[
+            ("capset", Type::Int), ("gen", Type::Int), ("max_heap", Type::Int), ("alloc_size", Type::Int), ("mblock_size", Type::Int),
+            ("block_size", Type::Int), ("allocated", Type::Int), ("size", Type::Int), ("live", Type::Int), ("blocks", Type::Int),
+            ("mblocks", Type::Int), ("retain", Type::Int), ("return", Type::Int),
+        ],
+    },
+    Table {
+        name: "messages",
+        events: &["user_msg", "user_marker"],
+        columns: &[("message", Type::Text)],
+    },
+    Table {
+        name: "capabilities",
+        events: &[
+            "cap_create", "cap_delete", "cap_disable", "cap_enable",
+            "capset_create", "capset_delete", "capset_assign_cap", "capset_remove_cap",
+            "task_create", "task_migrate", "task_delete",
+        ],
+        columns: &[
+            ("capno", Type::Int), ("capset", Type::Int), ("capset_type", Type::Int), ("task", Type::Int),
+            ("kernel_thread", Type::Int), ("from_cap", Type::Int), ("to_cap", Type::Int),
+        ],
+    },
+    Table {
+        name: "sparks",
+        events: &["spark_counters"],
+        columns: &[
+            ("created", Type::Int), ("dud", Type::Int), ("overflowed", Type::Int), ("converted", Type::Int),
+            ("gcd", Type::Int), ("fizzled", Type::Int), ("remaining", Type::Int),
+        ],
+    },
+    Table {
+        name: "process",
+        events: &["rts_identifier", "wall_clock_time", "osprocess_pid", "osprocess_ppid", "program_args", "program_env"],
+        columns: &[
+            ("capset", Type::Int), ("name", Type::Text), ("sec", Type::Int), ("nsec", Type::Int),
+            ("pid", Type::Int), ("ppid", Type::Int), ("args", Type::Text), ("env", Type::Text),
+        ],
+    },
+    Table {
+        name: "profiling",
+        events: &[
+            "heap_prof_begin", "heap_prof_cost_centre", "heap_prof_sample_begin", "heap_prof_sample_cost_centre",
+            "heap_prof_sample_string", "heap_prof_sample_end", "heap_bio_prof_sample_begin",
+            "prof_begin", "prof_sample_cost_centre",
+        ],
+        columns: &[
+            ("profile", Type::Int), ("sampling_period", Type::Int), ("breakdown", Type::Int),
+            ("ccid", Type::Int), ("label", Type::Text), ("module", Type::Text), ("srcloc", Type::Text), ("flags", Type::Int),
+            ("era", Type::Int), ("sample_time", Type::Int), ("residency", Type::Int), ("stack", Type::List),
+            ("capno", Type::Int), ("ticks", Type::Int), ("tick_interval", Type::Int),
+        ],
+    },
+];
+
+enum Column {
+    Int(UInt64Builder),
+    Text(StringBuilder),
+    List(ListBuilder<UInt32Builder>),
+}
+
+/// Builds Arrow [`RecordBatch`]es of the records of one [`Table`].
+pub struct Batches {
+    pub table: &'static Table,
+    schema: SchemaRef,
+    time: UInt64Builder,
+    cap: UInt16Builder,
+    event: StringBuilder,
+    columns: Vec<Column>,
+    rows: usize,
+}
+
+impl Batches {
+    pub fn new(table: &'static Table) -> Self {
+        let mut fields = vec![
+            Field::new("time", DataType::UInt64, false),
+            Field::new("cap", DataType::UInt16, true),
+            Field::new("event", DataType::Utf8, false),
+        ];
+        let mut columns = Vec::new();
+        for (name, type_) in table.columns {
+            let (data_type, column) = match type_ {
+                Type::Int => (DataType::UInt64, Column::Int(UInt64Builder::new())),
+                Type::Text => (DataType::Utf8, Column::Text(StringBuilder::new())),
+                Type::List => {
+                    let item = Field::new("item", DataType::UInt32, true);
+                    (DataType::List(Arc::new(item)), Column::List(ListBuilder::new(UInt32Builder::new())))
+                },
+            };
+            fields.push(Field::new(*name, data_type, true));
+            columns.push(column);
+        }
+
+        Batches {
+            table,
+            schema: Arc::new(Schema::new(fields)),
+            time: UInt64Builder::new(),
+            cap: UInt16Builder::new(),
+            event: StringBuilder::new(),
+            columns,
+            rows: 0,
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Number of records added since the last batch.
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Add a record of one of the events of the table.
+    pub fn append(&mut self, record: &Record) {
+        self.time.append_value(record.ctx.time.0);
+        self.cap.append_option(record.ctx.capno.map(|capno| capno.0));
+        self.event.append_value(record.event);
+
+        for ((name, _), column) in self.table.columns.iter().zip(&mut self.columns) {
+            let value = record.fields.iter().find(|(field, _)| field == name).map(|(_, value)| value);
+            match (column, value) {
+                (Column::Int(column), Some(Value::Int(value))) => column.append_value(*value),
+                (Column::Text(column), Some(Value::Text(value))) => column.append_value(value),
+                (Column::List(column), Some(Value::List(values))) => {
+                    column.values().append_slice(values);
+                    column.append(true);
+                },
+                (Column::Int(column), _) => column.append_null(),
+                (Column::Text(column), _) => column.append_null(),
+                (Column::List(column), _) => column.append(false),
+            }
+        }
+        self.rows += 1;
+    }
+
+    /// The records added since the last batch.
+    pub fn finish(&mut self) -> RecordBatch {
+        let mut arrays: Vec<ArrayRef> = vec![
+            Arc::new(self.time.finish()),
+            Arc::new(self.cap.finish()),
+            Arc::new(self.event.finish()),
+        ];
+        for column in &mut self.columns {
+            arrays.push(match column {
+                Column::Int(column) => Arc::new(column.finish()),
+                Column::Text(column) => Arc::new(column.finish()),
+                Column::List(column) => Arc::new(column.finish()),
+            });
+        }
+        self.rows = 0;
+        RecordBatch::try_new(self.schema.clone(), arrays).unwrap()
+    }
+}
+
+/// Rows of a table kept in memory before they are written.
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// Writes the records of every [`Table`] to a Parquet file of its own, `<name>.parquet` in a
+/// directory.
+///
+/// Records are written in batches, so only a batch per table is kept in memory. Records of
+/// events that are not in a table are left out. Call [`ParquetExport::finish`] after the last
+/// record.
+pub struct ParquetExport {
+    tables: Vec<(Batches, ArrowWriter<File>)>,
+    error: Option<io::Error>,
+}
+
+impl ParquetExport {
+    /// Create the files of the tables in `dir`, which has to exist.
+    pub fn create<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let mut tables = Vec::new();
+        for table in TABLES {
+            let batches = Batches::new(table);
+            let file = File::create(dir.as_ref().join(format!("{}.parquet", table.name)))?;
+            let writer = ArrowWriter::try_new(file, batches.schema(), Some(props.clone()))?;
+            tables.push((batches, writer));
+        }
+        Ok(ParquetExport { tables, error: None })
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        if self.error.is_some() {
+            return;
+        }
+        let Some((batches, writer)) = self.tables.iter_mut()
+            .find(|(batches, _)| batches.table.events.contains(&record.event))
+        else {
+            return;
+        };
+        batches.append(record);
+        if batches.len() >= BATCH_ROWS {
+            if let Err(err) = writer.write(&batches.finish()) {
+                self.error = Some(err.into());
+            }
+        }
+    }
+
+    /// Write the remaining records and close the files, returning the first error encountered.
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        for (mut batches, mut writer) in self.tables {
+            if !batches.is_empty() {
+                writer.write(&batches.finish())?;
+            }
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{UInt16Type, UInt32Type, UInt64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::encode::tests::{write, Payload};
+    use crate::export::Records;
+    use crate::parse::parse_reader;
+    use crate::types::{CapNo, Timestamp};
+
+    fn eventlog() -> Vec<u8> {
+        write(|w| {
+            w.begin_block(Timestamp(10), Timestamp(30), Some(CapNo(1)))?;
+            w.event(1, Timestamp(10), &Payload::new().u32(5))?;
+            // ThreadFinished
+            w.event(2, Timestamp(20), &Payload::new().u32(5).u16(5).u32(0))?;
+            w.event(19, Timestamp(25), &Payload::new().bytes(b"hello"))?;
+            w.event(167, Timestamp(30), &Payload::new().u32(1).u64(2).u8(2).u32(3).u32(1))?;
+            w.begin_block(Timestamp(40), Timestamp(40), None)?;
+            w.event(49, Timestamp(40), &Payload::new().u32(0).u64(4096))
+        })
+    }
+
+    #[test]
+    fn batches() {
+        let threads = TABLES.iter().find(|table| table.name == "threads").unwrap();
+        let mut batches = Batches::new(threads);
+        let mut records = Records::new(|record| if threads.events.contains(&record.event) {
+            batches.append(&record);
+        });
+        parse_reader(&eventlog()[..], &mut records).unwrap();
+        assert_eq!(batches.len(), 2);
+
+        let batch = batches.finish();
+        assert!(batches.is_empty());
+        assert_eq!(batch.schema(), batches.schema());
+        let column = |name| batch.column_by_name(name).unwrap();
+        assert_eq!(column("time").as_primitive::<UInt64Type>().values(), &[10, 20]);
+        assert_eq!(column("cap").as_primitive::<UInt16Type>().values(), &[1, 1]);
+        let events: Vec<_> = column("event").as_string::<i32>().iter().flatten().collect();
+        assert_eq!(events, ["thread_run", "thread_stop"]);
+        assert_eq!(column("thread").as_primitive::<UInt64Type>().values(), &[5, 5]);
+        let status: Vec<_> = column("status").as_string::<i32>().iter().collect();
+        assert_eq!(status, [None, Some("ThreadFinished")]);
+        assert_eq!(column("to_cap").null_count(), 2);
+    }
+
+    #[test]
+    fn parquet_files() {
+        let dir = std::env::temp_dir().join(format!("ev-columnar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut export = ParquetExport::create(&dir).unwrap();
+        let mut records = Records::new(|record| export.add(&record));
+        parse_reader(&eventlog()[..], &mut records).unwrap();
+        export.finish().unwrap();
+
+        let read = |name: &str| {
+            let file = File::open(dir.join(format!("{name}.parquet"))).unwrap();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+            reader.collect::<Result<Vec<RecordBatch>, _>>().unwrap()
+        };
+        for table in TABLES {
+            let rows: usize = read(table.name).iter().map(RecordBatch::num_rows).sum();
+            let expected = match table.name {
+                "threads" => 2,
+                "messages" | "profiling" | "heap" => 1,
+                _ => 0,
+            };
+            assert_eq!(rows, expected, "rows of {}", table.name);
+        }
+
+        let heap = &read("heap")[0];
+        assert_eq!(heap.column_by_name("cap").unwrap().null_count(), 1);
+        assert_eq!(heap.column_by_name("allocated").unwrap().as_primitive::<UInt64Type>().values(), &[4096]);
+        let profiling = &read("profiling")[0];
+        let stack = profiling.column_by_name("stack").unwrap().as_list::<i32>().value(0);
+        assert_eq!(stack.as_primitive::<UInt32Type>().values(), &[3, 1]);
+        let messages = &read("messages")[0];
+        assert_eq!(messages.column_by_name("message").unwrap().as_string::<i32>().value(0), "hello");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::parse::{EventSize, EventType, BLOCK_MARKER};
+use crate::types::{CapNo, Timestamp};
+
+/// Size of a whole BLOCK_MARKER event, including the event header.
+///
+/// The block size of a BLOCK_MARKER counts the marker itself as well as the events following it.
+pub const BLOCK_MARKER_SIZE: u32 = 2 + 8 + 4 + 8 + 2;
+
+struct Block {
+    time: Timestamp,
+    time_end: Timestamp,
+    capno: Option<CapNo>,
+    events: Vec<u8>,
+}
+
+/// Writes an eventlog.
+///
+/// Call [`EventlogWriter::header`] first, then write the events, then [`EventlogWriter::finish`].
+///
+/// Events written between [`EventlogWriter::begin_block`] and [`EventlogWriter::end_block`] are
+/// buffered and written out behind a BLOCK_MARKER with the size of the block filled in. Blocks
+/// without events are left out.
+pub struct EventlogWriter<W: Write> {
+    out: W,
+    sizes: HashMap<u16, EventSize>,
+    block: Option<Block>,
+}
+
+impl<W: Write> EventlogWriter<W> {
+    pub fn new(out: W) -> Self {
+        EventlogWriter {
+            out,
+            sizes: HashMap::new(),
+            block: None,
+        }
+    }
+
+    /// Write the header with the provided event types, and start the data section.
+    pub fn header(&mut self, event_types: &[(u16, EventType)]) -> io::Result<()> {
+        self.out.write_all(b"hdrb")?;
+        self.out.write_all(b"hetb")?;
+
+        for (id, et) in event_types {
+            let size: i16 = match et.size {
+                EventSize::Variable => -1,
+                EventSize::Fixed(size) => size as i16,
+            };
+
+            self.out.write_all(b"etb\0")?;
+            self.out.write_all(&id.to_be_bytes())?;
+            self.out.write_all(&size.to_be_bytes())?;
+            self.out.write_all(&(et.descr.len() as u32).to_be_bytes())?;
+            self.out.write_all(et.descr.as_bytes())?;
+            self.out.write_all(&(et.extra.len() as u32).to_be_bytes())?;
+            self.out.write_all(&et.extra)?;
+            self.out.write_all(b"ete\0")?;
+
+            self.sizes.insert(*id, et.size);
+        }
+
+        self.out.write_all(b"hete")?;
+        self.out.write_all(b"hdre")?;
+        self.out.write_all(b"datb")?;
+
+        Ok(())
+    }
+
+    /// Start a new block of events tied to `capno`, ending the current block if there is one.
+    ///
+    /// The end time of the block is moved forward if later events are written to it.
+    pub fn begin_block(&mut self, time: Timestamp, time_end: Timestamp, capno: Option<CapNo>) -> io::Result<()> {
+        self.end_block()?;
+        self.block = Some(Block {
+            time,
+            time_end,
+            capno,
+            events: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// End the current block, if there is one.
+    pub fn end_block(&mut self) -> io::Result<()> {
+        let Some(block) = self.block.take() else {
+            return Ok(());
+        };
+
+        if block.events.is_empty() {
+            return Ok(());
+        }
+
+        let block_size = u32::try_from(block.events.len())
+            .ok()
+            .and_then(|size| size.checked_add(BLOCK_MARKER_SIZE))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "block too large"))?;
+
+        self.out.write_all(&BLOCK_MARKER.to_be_bytes())?;
+        self.out.write_all(&block.time.0.to_be_bytes())?;
+        self.out.write_all(&block_size.to_be_bytes())?;
+        self.out.write_all(&block.time_end.0.to_be_bytes())?;
+        self.out.write_all(&block.capno.map_or(!0, |capno| capno.0).to_be_bytes())?;
+        self.out.write_all(&block.events)?;
+
+        Ok(())
+    }
+
+    /// Write an event. `payload` is the event without its header.
+    ///
+    /// BLOCK_MARKER events should not be written with this, use [`EventlogWriter::begin_block`].
+    pub fn event(&mut self, id: u16, time: Timestamp, payload: &[u8]) -> io::Result<()> {
+        let size = self.sizes.get(&id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                format!("event type {id} not in header")))?;
+
+        let mut buf = Vec::with_capacity(2 + 8 + 2 + payload.len());
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&time.0.to_be_bytes());
+        match *size {
+            EventSize::Variable => {
+                let len = u16::try_from(payload.len())
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("payload of event {id} too large")))?;
+                buf.extend_from_slice(&len.to_be_bytes());
+            },
+            EventSize::Fixed(len) => {
+                if payload.len() != len as usize {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("event {id} has size {} but should have size {len}", payload.len())));
+                }
+            },
+        }
+        buf.extend_from_slice(payload);
+
+        match &mut self.block {
+            Some(block) => {
+                block.time_end = block.time_end.max(time);
+                block.events.extend_from_slice(&buf);
+            },
+            None => {
+                self.out.write_all(&buf)?;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// End the data section and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_block()?;
+        self.out.write_all(&0xffffu16.to_be_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parse::{parse_reader, Context, EventlogParser};
+
+    /// The event types of the eventlogs written by the tests, every event type the tests use
+    /// except USER_MARKER, which changes the STOP_THREAD numbering.
+    pub(crate) fn event_types() -> Vec<(u16, EventType)> {
+        [
+            (0, EventSize::Fixed(4)),   // CREATE_THREAD
+            (1, EventSize::Fixed(4)),   // RUN_THREAD
+            (2, EventSize::Fixed(10)),  // STOP_THREAD
+            (3, EventSize::Fixed(4)),   // THREAD_RUNNABLE
+            (4, EventSize::Fixed(6)),   // MIGRATE_THREAD
+            (8, EventSize::Fixed(6)),   // THREAD_WAKEUP
+            (9, EventSize::Fixed(0)),   // GC_START
+            (10, EventSize::Fixed(0)),  // GC_END
+            (11, EventSize::Fixed(0)),  // REQUEST_SEQ_GC
+            (12, EventSize::Fixed(0)),  // REQUEST_PAR_GC
+            (BLOCK_MARKER, EventSize::Fixed(14)),
+            (19, EventSize::Variable),  // USER_MSG
+            (20, EventSize::Fixed(0)),  // GC_IDLE
+            (21, EventSize::Fixed(0)),  // GC_WORK
+            (22, EventSize::Fixed(0)),  // GC_DONE
+            (25, EventSize::Fixed(6)),  // CAPSET_CREATE
+            (30, EventSize::Variable),  // PROGRAM_ARGS
+            (32, EventSize::Fixed(8)),  // OSPROCESS_PID
+            (34, EventSize::Fixed(56)), // SPARK_COUNTERS
+            (43, EventSize::Fixed(16)), // WALL_CLOCK_TIME
+            (44, EventSize::Variable),  // THREAD_LABEL
+            (45, EventSize::Fixed(2)),  // CAP_CREATE
+            (46, EventSize::Fixed(2)),  // CAP_DELETE
+            (47, EventSize::Fixed(2)),  // CAP_DISABLE
+            (48, EventSize::Fixed(2)),  // CAP_ENABLE
+            (49, EventSize::Fixed(12)), // HEAP_ALLOCATED
+            (50, EventSize::Fixed(12)), // HEAP_SIZE
+            (51, EventSize::Fixed(12)), // HEAP_LIVE
+            (53, EventSize::Fixed(58)), // GC_STATS_GHC
+            (54, EventSize::Fixed(0)),  // GC_GLOBAL_SYNC
+            (91, EventSize::Fixed(12)), // BLOCKS_SIZE
+            (160, EventSize::Variable), // HEAP_PROF_BEGIN
+            (161, EventSize::Variable), // HEAP_PROF_COST_CENTRE
+            (162, EventSize::Fixed(8)), // HEAP_PROF_SAMPLE_BEGIN
+            (163, EventSize::Variable), // HEAP_PROF_SAMPLE_COST_CENTRE
+            (164, EventSize::Variable), // HEAP_PROF_SAMPLE_STRING
+            (165, EventSize::Fixed(8)), // HEAP_PROF_SAMPLE_END
+            (167, EventSize::Variable), // PROF_SAMPLE_COST_CENTRE
+            (168, EventSize::Fixed(8)), // PROF_BEGIN
+        ].into_iter()
+            .map(|(id, size)| (id, EventType { size, descr: format!("event {id}"), extra: Vec::new() }))
+            .collect()
+    }
+
+    /// Write an eventlog with [`event_types`].
+    pub(crate) fn write(events: impl FnOnce(&mut EventlogWriter<Vec<u8>>) -> io::Result<()>) -> Vec<u8> {
+        let mut writer = EventlogWriter::new(Vec::new());
+        writer.header(&event_types()).unwrap();
+        events(&mut writer).unwrap();
+        writer.finish().unwrap()
+    }
+
+    /// Builds the payload of an event, numbers are big endian and strings NUL terminated.
+    #[derive(Debug, Default)]
+    pub(crate) struct Payload(Vec<u8>);
+
+    impl Payload {
+        pub(crate) fn new() -> Self {
+            Payload::default()
+        }
+
+        pub(crate) fn u8(mut self, n: u8) -> Self {
+            self.0.push(n);
+            self
+        }
+
+        pub(crate) fn u16(mut self, n: u16) -> Self {
+            self.0.extend(n.to_be_bytes());
+            self
+        }
+
+        pub(crate) fn u32(mut self, n: u32) -> Self {
+            self.0.extend(n.to_be_bytes());
+            self
+        }
+
+        pub(crate) fn u64(mut self, n: u64) -> Self {
+            self.0.extend(n.to_be_bytes());
+            self
+        }
+
+        pub(crate) fn string(mut self, string: &str) -> Self {
+            self.0.extend(string.as_bytes());
+            self.0.push(0);
+            self
+        }
+
+        /// Bytes as they are, for the strings GHC does not NUL terminate.
+        pub(crate) fn bytes(mut self, bytes: &[u8]) -> Self {
+            self.0.extend(bytes);
+            self
+        }
+    }
+
+    impl std::ops::Deref for Payload {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    /// The block markers and events of a parsed eventlog.
+    #[derive(Debug, Default)]
+    pub(crate) struct Events {
+        /// Time, block size, end time and capability of each block
+        pub(crate) blocks: Vec<(Timestamp, u32, Timestamp, Option<CapNo>)>,
+        /// Id, time, capability and payload of each event other than BLOCK_MARKER
+        pub(crate) events: Vec<(u16, Timestamp, Option<CapNo>, Vec<u8>)>,
+    }
+
+    impl EventlogParser for Events {
+        fn event_end(&mut self, ctx: &Context, payload: &[u8]) {
+            if ctx.id != BLOCK_MARKER {
+                self.events.push((ctx.id, ctx.time, ctx.capno, payload.to_vec()));
+            }
+        }
+
+        fn event_block_marker(&mut self, ctx: &Context, block_size: u32, time_end: Timestamp, capno: Option<CapNo>) {
+            self.blocks.push((ctx.time, block_size, time_end, capno));
+        }
+    }
+
+    pub(crate) fn parse(eventlog: &[u8]) -> Events {
+        let mut events = Events::default();
+        parse_reader(eventlog, &mut events).unwrap();
+        events
+    }
+
+    #[test]
+    fn round_trip() {
+        let eventlog = write(|w| {
+            w.event(25, Timestamp(0), &[0, 0, 0, 1, 0, 2])?;
+            w.begin_block(Timestamp(10), Timestamp(10), Some(CapNo(0)))?;
+            w.event(0, Timestamp(12), &[0, 0, 0, 1])?;
+            w.event(19, Timestamp(25), b"hi")?;
+            // ends the block of capability 0
+            w.begin_block(Timestamp(15), Timestamp(40), Some(CapNo(1)))?;
+            w.event(0, Timestamp(16), &[0, 0, 0, 2])?;
+            w.end_block()?;
+            w.event(19, Timestamp(50), b"outside")?;
+            // left out, it has no events
+            w.begin_block(Timestamp(60), Timestamp(60), Some(CapNo(0)))?;
+            Ok(())
+        });
+
+        let events = parse(&eventlog);
+        assert_eq!(events.blocks, [
+            (Timestamp(10), BLOCK_MARKER_SIZE + 14 + 14, Timestamp(25), Some(CapNo(0))),
+            (Timestamp(15), BLOCK_MARKER_SIZE + 14, Timestamp(40), Some(CapNo(1))),
+        ]);
+        assert_eq!(events.events, [
+            (25, Timestamp(0), None, vec![0, 0, 0, 1, 0, 2]),
+            (0, Timestamp(12), Some(CapNo(0)), vec![0, 0, 0, 1]),
+            (19, Timestamp(25), Some(CapNo(0)), b"hi".to_vec()),
+            (0, Timestamp(16), Some(CapNo(1)), vec![0, 0, 0, 2]),
+            (19, Timestamp(50), None, b"outside".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn rejects_bad_events() {
+        let mut writer = EventlogWriter::new(Vec::new());
+        writer.header(&event_types()).unwrap();
+        assert!(writer.event(0, Timestamp(0), &[0, 0, 1]).is_err());
+        assert!(writer.event(99, Timestamp(0), &[]).is_err());
+    }
+}
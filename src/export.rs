@@ -0,0 +1,501 @@
+use std::io::{self, Write};
+
+use crate::json;
+use crate::parse::{Context, EventlogParser, SparkCounters, ThreadStopStatus};
+use crate::types::{CapNo, CapsetId, TaskId, ThreadId, Timestamp};
+
+/// The fields of every event, in the order of the CSV columns. Every field of a [`Record`] is
+/// one of these, and a field has the same meaning in every event it is in.
+pub const FIELDS: &[&str] = &[
+    "thread", "status", "blocked_on", "label", "capno", "from_cap", "to_cap",
+    "task", "kernel_thread",
+    "capset", "capset_type", "name", "sec", "nsec", "pid", "ppid", "args", "env",
+    "created", "dud", "overflowed", "converted", "gcd", "fizzled", "remaining",
+    "gen", "copied", "slop", "fragmentation", "threads", "max_copied", "total_copied", "balanced_copied",
+    "max_heap", "alloc_size", "mblock_size", "block_size",
+    "allocated", "size", "live", "blocks", "mblocks", "retain", "return",
+    "message",
+    "profile", "sampling_period", "breakdown", "module_filter", "closure_filter", "type_filter",
+    "cost_centre_filter", "cost_centre_stack_filter", "retainer_filter", "biography_filter",
+    "ccid", "module", "srcloc", "flags", "era", "sample_time", "residency", "stack", "ticks", "tick_interval",
+    "block_length", "time_end",
+    "payload",
+];
+
+/// The value of a field of a [`Record`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(u64),
+    Text(String),
+    /// A cost centre stack
+    List(Vec<u32>),
+}
+
+impl Value {
+    /// The value as JSON.
+    pub fn json(&self) -> String {
+        match self {
+            Value::Int(value) => value.to_string(),
+            Value::Text(value) => json::string(value),
+            Value::List(values) => {
+                let values: Vec<String> = values.iter().map(u32::to_string).collect();
+                format!("[{}]", values.join(","))
+            },
+        }
+    }
+}
+
+/// Shown as is, the numbers of a list are separated by spaces.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Text(value) => write!(f, "{value}"),
+            Value::List(values) => {
+                let values: Vec<String> = values.iter().map(u32::to_string).collect();
+                write!(f, "{}", values.join(" "))
+            },
+        }
+    }
+}
+
+/// A decoded event with its fields.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub ctx: Context,
+    /// The name of the event, the name of its [`EventlogParser`] method without `event_`
+    pub event: &'static str,
+    pub fields: Vec<(&'static str, Value)>,
+}
+
+fn int(value: impl Into<u64>) -> Value {
+    Value::Int(value.into())
+}
+
+fn text(value: &[u8]) -> Value {
+    Value::Text(String::from_utf8_lossy(value).into_owned())
+}
+
+/// Program arguments and environment are NUL separated, shown separated by spaces.
+fn words(value: &[u8]) -> Value {
+    let words: Vec<_> = value.split(|b| *b == 0)
+        .filter(|word| !word.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    Value::Text(words.join(" "))
+}
+
+/// Turns every event into a [`Record`] and passes it to `sink`, in the order they are parsed.
+pub struct Records<F: FnMut(Record)> {
+    sink: F,
+}
+
+impl<F: FnMut(Record)> Records<F> {
+    pub fn new(sink: F) -> Self {
+        Records { sink }
+    }
+
+    fn record(&mut self, ctx: &Context, event: &'static str, fields: Vec<(&'static str, Value)>) {
+        debug_assert!(fields.iter().all(|(name, _)| FIELDS.contains(name)));
+        (self.sink)(Record { ctx: *ctx, event, fields });
+    }
+}
+
+impl<F: FnMut(Record)> EventlogParser for Records<F> {
+    fn event_unknown(&mut self, _ctx: &Context, _bytes: Vec<u8>) {
+        let payload: String = _bytes.iter().map(|b| format!("{b:02x}")).collect();
+        self.record(_ctx, "unknown", vec![("payload", Value::Text(payload))]);
+    }
+
+    fn event_block_marker(&mut self, _ctx: &Context, _block_size: u32, _time_end: Timestamp, _capno: Option<CapNo>) {
+        let mut fields = vec![("block_length", int(_block_size)), ("time_end", int(_time_end.0))];
+        if let Some(capno) = _capno {
+            fields.push(("capno", int(capno.0)));
+        }
+        self.record(_ctx, "block_marker", fields);
+    }
+
+    fn event_rts_identifier(&mut self, _ctx: &Context, _capset: CapsetId, _name: Vec<u8>) {
+        self.record(_ctx, "rts_identifier", vec![("capset", int(_capset.0)), ("name", text(&_name))]);
+    }
+    fn event_wall_clock_time(&mut self, _ctx: &Context, _capset: CapsetId, _sec: u64, _nsec: u32) {
+        self.record(_ctx, "wall_clock_time", vec![("capset", int(_capset.0)), ("sec", int(_sec)), ("nsec", int(_nsec))]);
+    }
+    fn event_osprocess_pid(&mut self, _ctx: &Context, _capset: CapsetId, _pid: u32) {
+        self.record(_ctx, "osprocess_pid", vec![("capset", int(_capset.0)), ("pid", int(_pid))]);
+    }
+    fn event_osprocess_ppid(&mut self, _ctx: &Context, _capset: CapsetId, _ppid: u32) {
+        self.record(_ctx, "osprocess_ppid", vec![("capset", int(_capset.0)), ("ppid", int(_ppid))]);
+    }
+    fn event_program_args(&mut self, _ctx: &Context, _capset: CapsetId, _args: Vec<u8>) {
+        self.record(_ctx, "program_args", vec![("capset", int(_capset.0)), ("args", words(&_args))]);
+    }
+    fn event_program_env(&mut self, _ctx: &Context, _capset: CapsetId, _env: Vec<u8>) {
+        self.record(_ctx, "program_env", vec![("capset", int(_capset.0)), ("env", words(&_env))]);
+    }
+
+    fn event_spark_counters(&mut self, _ctx: &Context, _counters: SparkCounters) {
+        self.record(_ctx, "spark_counters", vec![
+            ("created", int(_counters.created)),
+            ("dud", int(_counters.dud)),
+            ("overflowed", int(_counters.overflowed)),
+            ("converted", int(_counters.converted)),
+            ("gcd", int(_counters.gcd)),
+            ("fizzled", int(_counters.fizzled)),
+            ("remaining", int(_counters.remaining)),
+        ]);
+    }
+
+    fn event_thread_create(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.record(_ctx, "thread_create", vec![("thread", int(_threadid.0))]);
+    }
+    fn event_thread_run(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.record(_ctx, "thread_run", vec![("thread", int(_threadid.0))]);
+    }
+    fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, _status: ThreadStopStatus) {
+        let mut fields = vec![("thread", int(_threadid.0)), ("status", Value::Text(_status.to_string()))];
+        if let Some(blocker) = _status.blocked_on() {
+            fields.push(("blocked_on", int(blocker.0)));
+        }
+        self.record(_ctx, "thread_stop", fields);
+    }
+    fn event_thread_label(&mut self, _ctx: &Context, _threadid: ThreadId, _label: Vec<u8>) {
+        self.record(_ctx, "thread_label", vec![("thread", int(_threadid.0)), ("label", text(&_label))]);
+    }
+    fn event_thread_runnable(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.record(_ctx, "thread_runnable", vec![("thread", int(_threadid.0))]);
+    }
+    fn event_thread_migrate(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.record(_ctx, "thread_migrate", vec![("thread", int(_threadid.0)), ("to_cap", int(_capno.0))]);
+    }
+    fn event_thread_wakeup(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.record(_ctx, "thread_wakeup", vec![("thread", int(_threadid.0)), ("to_cap", int(_capno.0))]);
+    }
+
+    fn event_task_create(&mut self, _ctx: &Context, _taskid: TaskId, _capno: CapNo, _k_threadid: u64) {
+        self.record(_ctx, "task_create", vec![("task", int(_taskid.0)), ("capno", int(_capno.0)), ("kernel_thread", int(_k_threadid))]);
+    }
+    fn event_task_migrate(&mut self, _ctx: &Context, _taskid: TaskId, _from_capno: CapNo, _to_capno: CapNo) {
+        self.record(_ctx, "task_migrate", vec![("task", int(_taskid.0)), ("from_cap", int(_from_capno.0)), ("to_cap", int(_to_capno.0))]);
+    }
+    fn event_task_delete(&mut self, _ctx: &Context, _taskid: TaskId) {
+        self.record(_ctx, "task_delete", vec![("task", int(_taskid.0))]);
+    }
+
+    fn event_request_seq_gc(&mut self, _ctx: &Context) {
+        self.record(_ctx, "request_seq_gc", vec![]);
+    }
+    fn event_request_par_gc(&mut self, _ctx: &Context) {
+        self.record(_ctx, "request_par_gc", vec![]);
+    }
+
+    fn event_gc_start(&mut self, _ctx: &Context) {
+        self.record(_ctx, "gc_start", vec![]);
+    }
+    fn event_gc_end(&mut self, _ctx: &Context) {
+        self.record(_ctx, "gc_end", vec![]);
+    }
+    fn event_gc_work(&mut self, _ctx: &Context) {
+        self.record(_ctx, "gc_work", vec![]);
+    }
+    fn event_gc_idle(&mut self, _ctx: &Context) {
+        self.record(_ctx, "gc_idle", vec![]);
+    }
+    fn event_gc_done(&mut self, _ctx: &Context) {
+        self.record(_ctx, "gc_done", vec![]);
+    }
+    fn event_gc_global_sync(&mut self, _ctx: &Context) {
+        self.record(_ctx, "gc_global_sync", vec![]);
+    }
+
+    fn event_gc_stats_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _copied: u64, _slop: u64, _fragmentation: u64, _threads: u32, _max_copied: u64, _total_copied: u64, _balanced_copied: u64) {
+        self.record(_ctx, "gc_stats_ghc", vec![
+            ("capset", int(_capset.0)),
+            ("gen", int(_gen)),
+            ("copied", int(_copied)),
+            ("slop", int(_slop)),
+            ("fragmentation", int(_fragmentation)),
+            ("threads", int(_threads)),
+            ("max_copied", int(_max_copied)),
+            ("total_copied", int(_total_copied)),
+            ("balanced_copied", int(_balanced_copied)),
+        ]);
+    }
+
+    fn event_heap_info_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _max_heap: u64, _alloc_size: u64, _mblock_size: u64, _block_size: u64) {
+        self.record(_ctx, "heap_info_ghc", vec![
+            ("capset", int(_capset.0)),
+            ("gen", int(_gen)),
+            ("max_heap", int(_max_heap)),
+            ("alloc_size", int(_alloc_size)),
+            ("mblock_size", int(_mblock_size)),
+            ("block_size", int(_block_size)),
+        ]);
+    }
+
+    fn event_heap_allocated(&mut self, _ctx: &Context, _capset: CapsetId, _allocated_bytes: u64) {
+        self.record(_ctx, "heap_allocated", vec![("capset", int(_capset.0)), ("allocated", int(_allocated_bytes))]);
+    }
+    fn event_heap_size(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        self.record(_ctx, "heap_size", vec![("capset", int(_capset.0)), ("size", int(_size))]);
+    }
+    fn event_heap_live(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        self.record(_ctx, "heap_live", vec![("capset", int(_capset.0)), ("live", int(_size))]);
+    }
+    fn event_blocks_size(&mut self, _ctx: &Context, _capset: CapsetId, _blocks: u64) {
+        self.record(_ctx, "blocks_size", vec![("capset", int(_capset.0)), ("blocks", int(_blocks))]);
+    }
+    fn event_mem_return(&mut self, _ctx: &Context, _capset: CapsetId, _mblocks: u32, _retain: u32, _return_: u32) {
+        self.record(_ctx, "mem_return", vec![
+            ("capset", int(_capset.0)),
+            ("mblocks", int(_mblocks)),
+            ("retain", int(_retain)),
+            ("return", int(_return_)),
+        ]);
+    }
+
+    fn event_user_msg(&mut self, _ctx: &Context, _bytes: Vec<u8>) {
+        self.record(_ctx, "user_msg", vec![("message", text(&_bytes))]);
+    }
+    fn event_user_marker(&mut self, _ctx: &Context, _marker: Vec<u8>) {
+        self.record(_ctx, "user_marker", vec![("message", text(&_marker))]);
+    }
+
+    fn event_cap_create(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.record(_ctx, "cap_create", vec![("capno", int(_capno.0))]);
+    }
+    fn event_cap_delete(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.record(_ctx, "cap_delete", vec![("capno", int(_capno.0))]);
+    }
+    fn event_cap_disable(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.record(_ctx, "cap_disable", vec![("capno", int(_capno.0))]);
+    }
+    fn event_cap_enable(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.record(_ctx, "cap_enable", vec![("capno", int(_capno.0))]);
+    }
+
+    fn event_capset_create(&mut self, _ctx: &Context, _capset: CapsetId, _type_: u16) {
+        self.record(_ctx, "capset_create", vec![("capset", int(_capset.0)), ("capset_type", int(_type_))]);
+    }
+    fn event_capset_delete(&mut self, _ctx: &Context, _capset: CapsetId) {
+        self.record(_ctx, "capset_delete", vec![("capset", int(_capset.0))]);
+    }
+    fn event_capset_assign_cap(&mut self, _ctx: &Context, _capset: CapsetId, _capno: CapNo) {
+        self.record(_ctx, "capset_assign_cap", vec![("capset", int(_capset.0)), ("capno", int(_capno.0))]);
+    }
+    fn event_capset_remove_cap(&mut self, _ctx: &Context, _capset: CapsetId, _capno: CapNo) {
+        self.record(_ctx, "capset_remove_cap", vec![("capset", int(_capset.0)), ("capno", int(_capno.0))]);
+    }
+
+    fn event_heap_prof_begin(&mut self, _ctx: &Context, _profile: u8, _sampling_period: u64, _breakdown: u32, _module: Vec<u8>, _closure_descr: Vec<u8>, _type_descr: Vec<u8>, _cost_centre: Vec<u8>, _cost_centre_stack: Vec<u8>, _retainer: Vec<u8>, _biography: Vec<u8>) {
+        self.record(_ctx, "heap_prof_begin", vec![
+            ("profile", int(_profile)),
+            ("sampling_period", int(_sampling_period)),
+            ("breakdown", int(_breakdown)),
+            ("module_filter", text(&_module)),
+            ("closure_filter", text(&_closure_descr)),
+            ("type_filter", text(&_type_descr)),
+            ("cost_centre_filter", text(&_cost_centre)),
+            ("cost_centre_stack_filter", text(&_cost_centre_stack)),
+            ("retainer_filter", text(&_retainer)),
+            ("biography_filter", text(&_biography)),
+        ]);
+    }
+    fn event_heap_prof_cost_centre(&mut self, _ctx: &Context, _ccid: u32, _label: Vec<u8>, _module: Vec<u8>, _srcloc: Vec<u8>, _flags: u8) {
+        self.record(_ctx, "heap_prof_cost_centre", vec![
+            ("ccid", int(_ccid)),
+            ("label", text(&_label)),
+            ("module", text(&_module)),
+            ("srcloc", text(&_srcloc)),
+            ("flags", int(_flags)),
+        ]);
+    }
+    fn event_heap_prof_sample_begin(&mut self, _ctx: &Context, _era: u64) {
+        self.record(_ctx, "heap_prof_sample_begin", vec![("era", int(_era))]);
+    }
+    fn event_heap_prof_sample_cost_centre(&mut self, _ctx: &Context, _profile: u8, _residency: u64, _stack: Vec<u32>) {
+        self.record(_ctx, "heap_prof_sample_cost_centre", vec![
+            ("profile", int(_profile)),
+            ("residency", int(_residency)),
+            ("stack", Value::List(_stack)),
+        ]);
+    }
+    fn event_heap_prof_sample_string(&mut self, _ctx: &Context, _profile: u8, _residency: u64, _label: Vec<u8>) {
+        self.record(_ctx, "heap_prof_sample_string", vec![
+            ("profile", int(_profile)),
+            ("residency", int(_residency)),
+            ("label", text(&_label)),
+        ]);
+    }
+    fn event_heap_prof_sample_end(&mut self, _ctx: &Context, _era: u64) {
+        self.record(_ctx, "heap_prof_sample_end", vec![("era", int(_era))]);
+    }
+    fn event_heap_bio_prof_sample_begin(&mut self, _ctx: &Context, _era: u64, _time: Timestamp) {
+        self.record(_ctx, "heap_bio_prof_sample_begin", vec![("era", int(_era)), ("sample_time", int(_time.0))]);
+    }
+
+    fn event_prof_begin(&mut self, _ctx: &Context, _tick_interval: u64) {
+        self.record(_ctx, "prof_begin", vec![("tick_interval", int(_tick_interval))]);
+    }
+    fn event_prof_sample_cost_centre(&mut self, _ctx: &Context, _capno: CapNo, _ticks: u64, _stack: Vec<u32>) {
+        self.record(_ctx, "prof_sample_cost_centre", vec![
+            ("capno", int(_capno.0)),
+            ("ticks", int(_ticks)),
+            ("stack", Value::List(_stack)),
+        ]);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A header and then a row per record, with a column for every one of [`FIELDS`]
+    Csv,
+    /// A JSON object per line, with only the fields of the event
+    Jsonl,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(format!("unknown format {s}, expected csv or jsonl")),
+        }
+    }
+}
+
+/// A CSV value, quoted if it has to be.
+fn csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes [`Record`]s as CSV or JSON Lines.
+///
+/// Every record has its time in nanoseconds, the capability of the block it was in (empty or
+/// `null` if it was not in a block of a capability), its event type id and its event name.
+/// Call [`Export::finish`] after the last record.
+pub struct Export<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+    format: Format,
+}
+
+impl<W: Write> Export<W> {
+    pub fn new(out: W, format: Format) -> Self {
+        let mut export = Export {
+            out,
+            error: None,
+            format,
+        };
+        if format == Format::Csv {
+            let res = writeln!(export.out, "time,cap,id,event,{}", FIELDS.join(","));
+            export.check(res);
+        }
+        export
+    }
+
+    /// Finish writing, returning the first error encountered.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn check(&mut self, res: io::Result<()>) {
+        if let Err(err) = res {
+            self.error.get_or_insert(err);
+        }
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        if self.error.is_some() {
+            return;
+        }
+        let ctx = &record.ctx;
+        let res = match self.format {
+            Format::Csv => {
+                let mut columns = vec![String::new(); FIELDS.len()];
+                for (name, value) in &record.fields {
+                    let i = FIELDS.iter().position(|field| field == name).unwrap();
+                    columns[i] = csv(&value.to_string());
+                }
+                let cap = ctx.capno.map(|capno| capno.0.to_string()).unwrap_or_default();
+                writeln!(self.out, "{},{cap},{},{},{}", ctx.time.0, ctx.id, record.event, columns.join(","))
+            },
+            Format::Jsonl => {
+                let cap = ctx.capno.map(|capno| capno.0.to_string()).unwrap_or("null".to_string());
+                let mut line = format!("{{\"time\":{},\"cap\":{cap},\"id\":{},\"event\":\"{}\"", ctx.time.0, ctx.id, record.event);
+                for (name, value) in &record.fields {
+                    line.push_str(&format!(",\"{name}\":{}", value.json()));
+                }
+                writeln!(self.out, "{line}}}")
+            },
+        };
+        self.check(res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    fn export(format: Format) -> String {
+        let eventlog = write(|w| {
+            w.begin_block(Timestamp(1), Timestamp(1), None)?;
+            w.event(43, Timestamp(1), &Payload::new().u32(1).u64(2).u32(3))?;
+            w.begin_block(Timestamp(10), Timestamp(20), Some(CapNo(0)))?;
+            // BlockedOnBlackHole, owned by thread 7
+            w.event(2, Timestamp(10), &Payload::new().u32(5).u16(8).u32(7))?;
+            w.event(44, Timestamp(15), &Payload::new().u32(5).bytes(b"a,\"b\""))?;
+            w.event(30, Timestamp(20), &Payload::new().u32(1).string("ev").string("-o"))
+        });
+
+        let mut export = Export::new(Vec::new(), format);
+        let mut records = Records::new(|record| if record.event != "block_marker" {
+            export.add(&record);
+        });
+        parse_reader(&eventlog[..], &mut records).unwrap();
+        String::from_utf8(export.finish().unwrap()).unwrap()
+    }
+
+    /// A CSV row with the given columns of [`FIELDS`] and the others empty.
+    fn row(start: &str, fields: &[(&str, &str)]) -> String {
+        let columns: Vec<&str> = FIELDS.iter()
+            .map(|field| fields.iter().find(|(name, _)| name == field).map_or("", |(_, value)| value))
+            .collect();
+        format!("{start},{}", columns.join(","))
+    }
+
+    #[test]
+    fn csv() {
+        let csv = export(Format::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines, [
+            format!("time,cap,id,event,{}", FIELDS.join(",")),
+            row("1,,43,wall_clock_time", &[("capset", "1"), ("sec", "2"), ("nsec", "3")]),
+            row("10,0,2,thread_stop", &[("thread", "5"), ("status", "BlockedOnBlackHole"), ("blocked_on", "7")]),
+            row("15,0,44,thread_label", &[("thread", "5"), ("label", "\"a,\"\"b\"\"\"")]),
+            row("20,0,30,program_args", &[("capset", "1"), ("args", "ev -o")]),
+        ]);
+    }
+
+    #[test]
+    fn jsonl() {
+        assert_eq!(export(Format::Jsonl), concat!(
+            r#"{"time":1,"cap":null,"id":43,"event":"wall_clock_time","capset":1,"sec":2,"nsec":3}"#, "\n",
+            r#"{"time":10,"cap":0,"id":2,"event":"thread_stop","thread":5,"status":"BlockedOnBlackHole","blocked_on":7}"#, "\n",
+            r#"{"time":15,"cap":0,"id":44,"event":"thread_label","thread":5,"label":"a,\"b\""}"#, "\n",
+            r#"{"time":20,"cap":0,"id":30,"event":"program_args","capset":1,"args":"ev -o"}"#, "\n",
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::encode::EventlogWriter;
+use crate::parse::{Context, EventType, EventlogParser, ThreadStopStatus, BLOCK_MARKER};
+use crate::types::{CapNo, ThreadId, Timestamp};
+
+/// Event types that set up the capabilities and capsets and describe the process, without them
+/// tools like ThreadScope and eventlog2html can not load an eventlog.
+const SETUP_EVENTS: [u16; 15] = [
+    25, // CAPSET_CREATE
+    26, // CAPSET_DELETE
+    27, // CAPSET_ASSIGN_CAP
+    28, // CAPSET_REMOVE_CAP
+    29, // RTS_IDENTIFIER
+    30, // PROGRAM_ARGS
+    31, // PROGRAM_ENV
+    32, // OSPROCESS_PID
+    33, // OSPROCESS_PPID
+    43, // WALL_CLOCK_TIME
+    45, // CAP_CREATE
+    46, // CAP_DELETE
+    47, // CAP_DISABLE
+    48, // CAP_ENABLE
+    52, // HEAP_INFO_GHC
+];
+
+/// Which events to keep when filtering an eventlog.
+///
+/// Every criterion that is set has to match for an event to be kept. Events that are not tied to
+/// a capability are not affected by `caps`, and events that are not about a Haskell thread are not
+/// affected by `threads`. The events that set up the capabilities and capsets and the process
+/// information are always kept.
+#[derive(Debug, Default, Clone)]
+pub struct Selection {
+    /// Keep events at or after this time
+    pub from: Option<Timestamp>,
+    /// Keep events at or before this time
+    pub to: Option<Timestamp>,
+    /// Keep events with these event type ids
+    pub events: Option<HashSet<u16>>,
+    /// Keep events on these capabilities
+    pub caps: Option<HashSet<CapNo>>,
+    /// Keep events about these Haskell threads
+    pub threads: Option<HashSet<ThreadId>>,
+}
+
+impl Selection {
+    pub fn keeps(&self, id: u16, time: Timestamp, capno: Option<CapNo>, threadid: Option<ThreadId>) -> bool {
+        if SETUP_EVENTS.contains(&id) {
+            return true;
+        }
+        self.from.is_none_or(|from| time >= from)
+            && self.to.is_none_or(|to| time <= to)
+            && self.events.as_ref().is_none_or(|events| events.contains(&id))
+            && capno.is_none_or(|capno| self.caps.as_ref().is_none_or(|caps| caps.contains(&capno)))
+            && threadid.is_none_or(|threadid| self.threads.as_ref().is_none_or(|threads| threads.contains(&threadid)))
+    }
+}
+
+/// Writes the events of the parsed eventlog that match a [`Selection`] to a new eventlog.
+///
+/// The header is copied unchanged and the blocks are written with new sizes.
+pub struct Filter<W: Write> {
+    selection: Selection,
+    writer: EventlogWriter<W>,
+    error: Option<io::Error>,
+    /// Offset of the end of the current block
+    block_end: u64,
+    current_threadid: Option<ThreadId>,
+}
+
+impl<W: Write> Filter<W> {
+    pub fn new(selection: Selection, out: W) -> Self {
+        Filter {
+            selection,
+            writer: EventlogWriter::new(out),
+            error: None,
+            block_end: 0,
+            current_threadid: None,
+        }
+    }
+
+    /// Finish writing the eventlog, returning the first error encountered.
+    pub fn finish(self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.finish()
+    }
+
+    fn check(&mut self, res: io::Result<()>) {
+        if let Err(err) = res {
+            self.error.get_or_insert(err);
+        }
+    }
+}
+
+impl<W: Write> EventlogParser for Filter<W> {
+    fn header(&mut self, event_types: &[(u16, EventType)]) {
+        let res = self.writer.header(event_types);
+        self.check(res);
+    }
+
+    fn event_start(&mut self, _ctx: &Context) {
+        self.current_threadid = None;
+    }
+
+    fn event_end(&mut self, ctx: &Context, payload: &[u8]) {
+        if self.error.is_some() || ctx.id == BLOCK_MARKER {
+            return;
+        }
+
+        if ctx.offset >= self.block_end {
+            let res = self.writer.end_block();
+            self.check(res);
+        }
+
+        if self.selection.keeps(ctx.id, ctx.time, ctx.capno, self.current_threadid) {
+            let res = self.writer.event(ctx.id, ctx.time, payload);
+            self.check(res);
+        }
+    }
+
+    fn event_block_marker(&mut self, _ctx: &Context, _block_size: u32, _time_end: Timestamp, _capno: Option<CapNo>) {
+        let res = self.writer.begin_block(_ctx.time, _time_end, _capno);
+        self.check(res);
+        self.block_end = _ctx.offset + _block_size as u64;
+    }
+
+    fn event_thread_create(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.current_threadid = Some(_threadid);
+    }
+    fn event_thread_run(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.current_threadid = Some(_threadid);
+    }
+    fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, _status: ThreadStopStatus) {
+        self.current_threadid = Some(_threadid);
+    }
+    fn event_thread_label(&mut self, _ctx: &Context, _threadid: ThreadId, _label: Vec<u8>) {
+        self.current_threadid = Some(_threadid);
+    }
+    fn event_thread_runnable(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.current_threadid = Some(_threadid);
+    }
+    fn event_thread_migrate(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.current_threadid = Some(_threadid);
+    }
+    fn event_thread_wakeup(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.current_threadid = Some(_threadid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{parse, write};
+    use crate::encode::BLOCK_MARKER_SIZE;
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn regenerates_block_sizes() {
+        let eventlog = write(|w| {
+            w.event(25, Timestamp(0), &[0, 0, 0, 1, 0, 2])?;
+            w.begin_block(Timestamp(5), Timestamp(5), Some(CapNo(0)))?;
+            w.event(45, Timestamp(5), &[0, 0])?;
+            w.event(0, Timestamp(12), &[0, 0, 0, 1])?;
+            w.event(32, Timestamp(20), &[0, 0, 0, 1, 0, 0, 0x30, 0x39])?;
+            w.event(19, Timestamp(25), b"kept")?;
+            w.begin_block(Timestamp(15), Timestamp(40), Some(CapNo(1)))?;
+            w.event(0, Timestamp(16), &[0, 0, 0, 2])?;
+            w.end_block()?;
+            w.event(19, Timestamp(50), b"outside")?;
+            Ok(())
+        });
+
+        let selection = Selection {
+            events: Some(HashSet::from([19])),
+            ..Selection::default()
+        };
+        let mut filter = Filter::new(selection, Vec::new());
+        parse_reader(&eventlog[..], &mut filter).unwrap();
+        let filtered = parse(&filter.finish().unwrap());
+
+        // the block of capability 1 has no events left and is left out
+        assert_eq!(filtered.blocks, [
+            (Timestamp(5), BLOCK_MARKER_SIZE + 12 + 18 + 16, Timestamp(25), Some(CapNo(0))),
+        ]);
+        let events: Vec<_> = filtered.events.iter().map(|(id, time, capno, _)| (*id, *time, *capno)).collect();
+        assert_eq!(events, [
+            (25, Timestamp(0), None),
+            (45, Timestamp(5), Some(CapNo(0))),
+            (32, Timestamp(20), Some(CapNo(0))),
+            (19, Timestamp(25), Some(CapNo(0))),
+            (19, Timestamp(50), None),
+        ]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::parse::{Context, EventlogParser};
+use crate::types::{CapNo, CapsetId, Timestamp};
+
+/// The numbers of GC_STATS_GHC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcStats {
+    /// The oldest generation collected
+    pub gen: u16,
+    /// Bytes copied
+    pub copied: u64,
+    /// Bytes of slop in the blocks of the live data
+    pub slop: u64,
+    /// Bytes of fragmentation in the heap
+    pub fragmentation: u64,
+    /// Number of GC threads
+    pub threads: u32,
+    /// Bytes copied by the GC thread that copied the most
+    pub max_copied: u64,
+    /// Bytes copied by all GC threads together
+    pub total_copied: u64,
+    /// How evenly the copying was spread over the GC threads, as GHC works it out: `total_copied`
+    /// when every thread copied the same amount and 0 when one thread did all of it
+    pub balanced_copied: u64,
+}
+
+/// One garbage collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gc {
+    /// Time of the REQUEST_SEQ_GC or REQUEST_PAR_GC
+    pub requested: Option<Timestamp>,
+    /// Whether a parallel GC was requested
+    pub parallel: Option<bool>,
+    /// Time of the first GC_START
+    pub start: Timestamp,
+    /// Time of the last GC_END
+    pub end: Timestamp,
+    /// Time of the GC_GLOBAL_SYNC, when all capabilities had stopped
+    pub global_sync: Option<Timestamp>,
+    /// Capabilities that took part
+    pub caps: BTreeSet<Option<CapNo>>,
+    pub stats: Option<GcStats>,
+}
+
+impl Gc {
+    /// Time from the start to the end of the GC.
+    pub fn pause(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Time it took to stop all capabilities.
+    pub fn sync(&self) -> Option<Duration> {
+        self.global_sync.map(|sync| sync - self.start)
+    }
+
+    /// The work balance of a parallel GC, as `+RTS -s` shows it: 0 when one thread did all the
+    /// copying and 1 when every thread copied the same amount. `None` for a GC with one thread.
+    pub fn balance(&self) -> Option<f64> {
+        self.stats
+            .filter(|stats| stats.total_copied > 0 && stats.threads > 1)
+            .map(|stats| stats.balanced_copied as f64 / stats.total_copied as f64)
+    }
+}
+
+/// Percentiles of GC pauses, see [`summary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PauseSummary {
+    pub count: usize,
+    pub total: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Summarise the pauses of `gcs`, `None` if there are none.
+pub fn summary<'a, I: IntoIterator<Item = &'a Gc>>(gcs: I) -> Option<PauseSummary> {
+    let mut pauses: Vec<Duration> = gcs.into_iter().map(Gc::pause).collect();
+    if pauses.is_empty() {
+        return None;
+    }
+    pauses.sort();
+
+    // nearest rank
+    let percentile = |p: usize| pauses[(pauses.len() * p).div_ceil(100).max(1) - 1];
+
+    Some(PauseSummary {
+        count: pauses.len(),
+        total: pauses.iter().sum(),
+        p50: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+        max: pauses[pauses.len() - 1],
+    })
+}
+
+/// Collects the garbage collections of an eventlog.
+///
+/// A GC runs from the first GC_START until every capability that started it has reached
+/// GC_END, the GC_STATS_GHC that follows is added to it.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]), the capabilities write
+/// their GC events to their own blocks.
+///
+/// Call [`GcPauses::finish`] after the last event to get the collections.
+#[derive(Default)]
+pub struct GcPauses {
+    gcs: Vec<Gc>,
+    /// The GC in progress
+    current: Option<Gc>,
+    /// Capabilities of the GC in progress that have not ended it yet
+    running: BTreeSet<Option<CapNo>>,
+    /// A GC has been requested but has not started yet
+    request: Option<(Timestamp, bool)>,
+}
+
+impl GcPauses {
+    pub fn new() -> Self {
+        GcPauses::default()
+    }
+
+    /// Return the collections in time order, including one still in progress.
+    pub fn finish(mut self) -> Vec<Gc> {
+        self.gcs.extend(self.current.take());
+        self.gcs
+    }
+}
+
+impl EventlogParser for GcPauses {
+    fn event_request_seq_gc(&mut self, _ctx: &Context) {
+        self.request = Some((_ctx.time, false));
+    }
+    fn event_request_par_gc(&mut self, _ctx: &Context) {
+        self.request = Some((_ctx.time, true));
+    }
+
+    fn event_gc_start(&mut self, _ctx: &Context) {
+        let request = self.request.take();
+        let gc = self.current.get_or_insert_with(|| Gc {
+            requested: request.map(|(time, _)| time),
+            parallel: request.map(|(_, parallel)| parallel),
+            start: _ctx.time,
+            end: _ctx.time,
+            global_sync: None,
+            caps: BTreeSet::new(),
+            stats: None,
+        });
+        gc.caps.insert(_ctx.capno);
+        self.running.insert(_ctx.capno);
+    }
+
+    fn event_gc_global_sync(&mut self, _ctx: &Context) {
+        if let Some(gc) = &mut self.current {
+            gc.global_sync.get_or_insert(_ctx.time);
+        }
+    }
+
+    fn event_gc_end(&mut self, _ctx: &Context) {
+        let Some(gc) = &mut self.current else {
+            return;
+        };
+        gc.end = gc.end.max(_ctx.time);
+        self.running.remove(&_ctx.capno);
+        if self.running.is_empty() {
+            self.gcs.extend(self.current.take());
+        }
+    }
+
+    fn event_gc_stats_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _copied: u64, _slop: u64, _fragmentation: u64, _threads: u32, _max_copied: u64, _total_copied: u64, _balanced_copied: u64) {
+        let gc = match &mut self.current {
+            Some(gc) => gc,
+            None => match self.gcs.last_mut() {
+                Some(gc) if gc.stats.is_none() => gc,
+                _ => return,
+            },
+        };
+        gc.stats = Some(GcStats {
+            gen: _gen,
+            copied: _copied,
+            slop: _slop,
+            fragmentation: _fragmentation,
+            threads: _threads,
+            max_copied: _max_copied,
+            total_copied: _total_copied,
+            balanced_copied: _balanced_copied,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    fn gc_stats(threads: u32, total_copied: u64, balanced_copied: u64) -> Payload {
+        Payload::new().u32(0).u16(1).u64(total_copied).u64(0).u64(0)
+            .u32(threads).u64(total_copied).u64(total_copied).u64(balanced_copied)
+    }
+
+    #[test]
+    fn pauses() {
+        let eventlog = write(|w| {
+            w.begin_block(Timestamp(100), Timestamp(100), Some(CapNo(0)))?;
+            w.event(12, Timestamp(100), &[])?;
+            w.event(9, Timestamp(110), &[])?;
+            w.begin_block(Timestamp(115), Timestamp(115), Some(CapNo(1)))?;
+            w.event(9, Timestamp(115), &[])?;
+            w.begin_block(Timestamp(120), Timestamp(120), Some(CapNo(0)))?;
+            w.event(54, Timestamp(120), &[])?;
+            w.event(10, Timestamp(150), &[])?;
+            w.begin_block(Timestamp(160), Timestamp(160), Some(CapNo(1)))?;
+            w.event(10, Timestamp(160), &[])?;
+            w.end_block()?;
+            w.event(53, Timestamp(161), &gc_stats(2, 1000, 800))?;
+            w.begin_block(Timestamp(200), Timestamp(200), Some(CapNo(0)))?;
+            w.event(11, Timestamp(200), &[])?;
+            w.event(9, Timestamp(210), &[])?;
+            w.event(10, Timestamp(230), &[])?;
+            w.end_block()?;
+            w.event(53, Timestamp(231), &gc_stats(1, 500, 0))?;
+            Ok(())
+        });
+
+        let mut pauses = GcPauses::new();
+        parse_reader(&eventlog[..], &mut pauses).unwrap();
+        let gcs = pauses.finish();
+        assert_eq!(gcs.len(), 2);
+
+        let gc = &gcs[0];
+        assert_eq!(gc.requested, Some(Timestamp(100)));
+        assert_eq!(gc.parallel, Some(true));
+        assert_eq!(gc.caps, BTreeSet::from([Some(CapNo(0)), Some(CapNo(1))]));
+        assert_eq!(gc.pause(), Duration::from_nanos(50));
+        assert_eq!(gc.sync(), Some(Duration::from_nanos(10)));
+        assert_eq!(gc.stats.map(|stats| stats.gen), Some(1));
+        // GHC has already normalised the balanced bytes by the number of threads
+        assert_eq!(gc.balance(), Some(0.8));
+
+        let gc = &gcs[1];
+        assert_eq!(gc.parallel, Some(false));
+        assert_eq!(gc.pause(), Duration::from_nanos(20));
+        assert_eq!(gc.sync(), None);
+        assert_eq!(gc.balance(), None);
+    }
+
+    #[test]
+    fn percentiles() {
+        let gc = |pause: u64| Gc {
+            requested: None,
+            parallel: None,
+            start: Timestamp(1000),
+            end: Timestamp(1000 + pause),
+            global_sync: None,
+            caps: BTreeSet::new(),
+            stats: None,
+        };
+        let nanos = Duration::from_nanos;
+
+        assert_eq!(summary(&[]), None);
+
+        let gcs: Vec<Gc> = (1..=100).rev().map(gc).collect();
+        assert_eq!(summary(&gcs), Some(PauseSummary {
+            count: 100,
+            total: nanos(5050),
+            p50: nanos(50),
+            p90: nanos(90),
+            p99: nanos(99),
+            max: nanos(100),
+        }));
+
+        let gcs = [gc(30), gc(10), gc(20)];
+        assert_eq!(summary(&gcs), Some(PauseSummary {
+            count: 3,
+            total: nanos(60),
+            p50: nanos(20),
+            p90: nanos(30),
+            p99: nanos(30),
+            max: nanos(30),
+        }));
+    }
+}
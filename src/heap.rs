@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::parse::{Context, EventlogParser};
+use crate::svg::{Chart, Series};
+use crate::types::{CapNo, CapsetId, Timestamp};
+
+/// The numbers of MEM_RETURN, in megablocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemReturn {
+    /// Megablocks the RTS had allocated
+    pub current: u32,
+    /// Megablocks the RTS needed to keep
+    pub needed: u32,
+    /// Megablocks returned to the OS
+    pub returned: u32,
+}
+
+/// The heap numbers of one capset at one time, the numbers that were not reported at that time
+/// are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeapSample {
+    pub time: Timestamp,
+    /// Bytes allocated since the program started by all capabilities together, from
+    /// HEAP_ALLOCATED
+    pub allocated: Option<u64>,
+    /// Bytes allocated per second since the previous HEAP_ALLOCATED
+    pub allocation_rate: Option<f64>,
+    /// Bytes of heap, from HEAP_SIZE
+    pub size: Option<u64>,
+    /// Bytes of live data, from HEAP_LIVE
+    pub live: Option<u64>,
+    /// The live bytes are from a collection of the oldest generation
+    pub major_gc: bool,
+    /// Bytes in the blocks of the heap, from BLOCKS_SIZE; the heap size minus this is the
+    /// fragmentation of the heap
+    pub blocks: Option<u64>,
+    pub mem_return: Option<MemReturn>,
+}
+
+/// Collects the heap numbers of every capset over time.
+///
+/// GHC writes a HEAP_ALLOCATED for every capability, with the bytes allocated by that capability.
+/// The allocated bytes of a capset are the sum of the last HEAP_ALLOCATED of every capability.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]) to know which live bytes are
+/// from a major GC, the GC_STATS_GHC of a collection comes right before its HEAP_LIVE.
+///
+/// Call [`HeapSeries::finish`] after the last event to get the samples.
+#[derive(Default)]
+pub struct HeapSeries {
+    samples: BTreeMap<CapsetId, BTreeMap<Timestamp, HeapSample>>,
+    /// Number of generations of each capset, from HEAP_INFO_GHC
+    gens: HashMap<CapsetId, u16>,
+    /// The last GC of each capset collected the oldest generation
+    major: HashMap<CapsetId, bool>,
+    /// The last HEAP_ALLOCATED of every capability of each capset
+    allocated: HashMap<CapsetId, BTreeMap<Option<CapNo>, u64>>,
+}
+
+impl HeapSeries {
+    pub fn new() -> Self {
+        HeapSeries::default()
+    }
+
+    /// Return the samples of every capset in time order, with the allocation rates filled in.
+    pub fn finish(self) -> BTreeMap<CapsetId, Vec<HeapSample>> {
+        self.samples.into_iter()
+            .map(|(capset, samples)| {
+                let mut samples: Vec<HeapSample> = samples.into_values().collect();
+                let mut previous: Option<(Timestamp, u64)> = None;
+                for sample in samples.iter_mut() {
+                    let Some(allocated) = sample.allocated else {
+                        continue;
+                    };
+                    if let Some((time, before)) = previous {
+                        let elapsed = (sample.time - time).as_secs_f64();
+                        if elapsed > 0.0 {
+                            sample.allocation_rate = Some(allocated.saturating_sub(before) as f64 / elapsed);
+                        }
+                    }
+                    previous = Some((sample.time, allocated));
+                }
+                (capset, samples)
+            })
+            .collect()
+    }
+
+    fn sample(&mut self, capset: CapsetId, time: Timestamp) -> &mut HeapSample {
+        self.samples.entry(capset).or_default()
+            .entry(time)
+            .or_insert_with(|| HeapSample { time, ..HeapSample::default() })
+    }
+}
+
+impl EventlogParser for HeapSeries {
+    fn event_heap_info_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _max_heap: u64, _alloc_size: u64, _mblock_size: u64, _block_size: u64) {
+        self.gens.insert(_capset, _gen);
+    }
+
+    fn event_gc_stats_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _copied: u64, _slop: u64, _fragmentation: u64, _threads: u32, _max_copied: u64, _total_copied: u64, _balanced_copied: u64) {
+        let gens = self.gens.get(&_capset).copied().unwrap_or(2);
+        self.major.insert(_capset, _gen + 1 >= gens);
+    }
+
+    fn event_heap_allocated(&mut self, _ctx: &Context, _capset: CapsetId, _allocated_bytes: u64) {
+        let allocated = self.allocated.entry(_capset).or_default();
+        allocated.insert(_ctx.capno, _allocated_bytes);
+        let total = allocated.values().sum();
+        self.sample(_capset, _ctx.time).allocated = Some(total);
+    }
+    fn event_heap_size(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        self.sample(_capset, _ctx.time).size = Some(_size);
+    }
+    fn event_heap_live(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        let major_gc = self.major.get(&_capset).copied().unwrap_or(false);
+        let sample = self.sample(_capset, _ctx.time);
+        sample.live = Some(_size);
+        sample.major_gc = major_gc;
+    }
+    fn event_blocks_size(&mut self, _ctx: &Context, _capset: CapsetId, _blocks: u64) {
+        self.sample(_capset, _ctx.time).blocks = Some(_blocks);
+    }
+    fn event_mem_return(&mut self, _ctx: &Context, _capset: CapsetId, _mblocks: u32, _retain: u32, _return_: u32) {
+        self.sample(_capset, _ctx.time).mem_return = Some(MemReturn {
+            current: _mblocks,
+            needed: _retain,
+            returned: _return_,
+        });
+    }
+}
+
+/// Write the samples as CSV, one row per sample with empty fields for the missing numbers.
+pub fn write_csv<W: Write>(samples: &BTreeMap<CapsetId, Vec<HeapSample>>, mut out: W) -> io::Result<W> {
+    fn field<T: ToString>(value: Option<T>) -> String {
+        value.map_or(String::new(), |value| value.to_string())
+    }
+
+    writeln!(out, "capset,time_ns,allocated_bytes,allocation_rate_bytes_per_s,heap_size_bytes,heap_live_bytes,major_gc,blocks_size_bytes,mem_current_mblocks,mem_needed_mblocks,mem_returned_mblocks")?;
+    for (capset, samples) in samples {
+        for sample in samples {
+            writeln!(out, "{},{},{},{},{},{},{},{},{},{},{}",
+                capset.0,
+                sample.time.as_nanos(),
+                field(sample.allocated),
+                field(sample.allocation_rate.map(|rate| rate.round() as u64)),
+                field(sample.size),
+                field(sample.live),
+                sample.major_gc,
+                field(sample.blocks),
+                field(sample.mem_return.map(|mem| mem.current)),
+                field(sample.mem_return.map(|mem| mem.needed)),
+                field(sample.mem_return.map(|mem| mem.returned)),
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(out)
+}
+
+/// Plot the heap size, blocks size and live bytes of every capset over time.
+pub fn heap_svg(samples: &BTreeMap<CapsetId, Vec<HeapSample>>) -> String {
+    let names: Vec<(String, String, String)> = samples.keys()
+        .map(|capset| (format!("heap size {capset}"), format!("blocks size {capset}"), format!("live {capset}")))
+        .collect();
+
+    let points = |samples: &[HeapSample], value: fn(&HeapSample) -> Option<u64>| -> Vec<(f64, f64)> {
+        samples.iter()
+            .filter_map(|sample| value(sample).map(|v| (sample.time.as_nanos() as f64 / 1e9, v as f64)))
+            .collect()
+    };
+
+    let mut series = Vec::new();
+    for ((size, blocks, live), samples) in names.iter().zip(samples.values()) {
+        series.push(Series { name: size, points: points(samples, |sample| sample.size) });
+        series.push(Series { name: blocks, points: points(samples, |sample| sample.blocks) });
+        series.push(Series { name: live, points: points(samples, |sample| sample.live) });
+    }
+
+    Chart {
+        title: "Heap",
+        x_label: "time",
+        y_label: "size",
+        log_x: false,
+        y_range: None,
+        x_format: |s| format!("{s:.3}s"),
+        y_format: |bytes| format!("{:.1}MB", bytes / (1024.0 * 1024.0)),
+        series,
+    }.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn allocated_by_all_capabilities() {
+        let second = 1_000_000_000;
+        let eventlog = write(|w| {
+            for (time, capno, allocated) in [(1, 0, 1000), (1, 1, 500), (2, 0, 3000), (2, 1, 1500), (3, 1, 2500)] {
+                w.begin_block(Timestamp(time * second), Timestamp(time * second), Some(CapNo(capno)))?;
+                w.event(49, Timestamp(time * second), &Payload::new().u32(0).u64(allocated))?;
+            }
+            Ok(())
+        });
+
+        let mut heap = HeapSeries::new();
+        parse_reader(&eventlog[..], &mut heap).unwrap();
+        let samples = heap.finish();
+
+        let allocated: Vec<_> = samples[&CapsetId(0)].iter()
+            .map(|sample| (sample.time.as_nanos() / second, sample.allocated, sample.allocation_rate))
+            .collect();
+        assert_eq!(allocated, [
+            (1, Some(1500), None),
+            (2, Some(4500), Some(3000.0)),
+            (3, Some(5500), Some(1000.0)),
+        ]);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::parse::{Context, EventlogParser};
+use crate::types::{CapsetId, Timestamp};
+
+/// One census of the heap.
+struct Sample {
+    time: Timestamp,
+    /// Residency in bytes of each band, in the order they were written
+    bands: Vec<(String, u64)>,
+}
+
+/// Rebuilds the `.hp` heap profile of a program run with `-l` and one of the `-h` options.
+///
+/// The samples are the HEAP_PROF_SAMPLE_BEGIN ... HEAP_PROF_SAMPLE_END sections, the bands of a
+/// cost centre profile are named by their cost centre stack like the RTS does. Write the
+/// profile with [`HeapProfile::write`] after parsing.
+#[derive(Default)]
+pub struct HeapProfile {
+    /// The program and its arguments
+    job: Option<String>,
+    /// Wall clock time in seconds at the start of the eventlog
+    start: Option<u64>,
+    /// Labels of the cost centres, from HEAP_PROF_COST_CENTRE
+    cost_centres: HashMap<u32, String>,
+    samples: Vec<Sample>,
+    /// A sample has begun and has not ended
+    open: bool,
+}
+
+impl HeapProfile {
+    pub fn new() -> Self {
+        HeapProfile::default()
+    }
+
+    /// The band of a cost centre stack, eg `go/main`, leaving out MAIN at the bottom
+    /// of the stack unless it is all there is.
+    fn stack_label(&self, stack: &[u32]) -> String {
+        let labels: Vec<&str> = stack.iter()
+            .map(|ccid| self.cost_centres.get(ccid).map_or("???", String::as_str))
+            .collect();
+        let trimmed = match labels.iter().rposition(|label| *label != "MAIN") {
+            Some(last) => &labels[..=last],
+            None => &labels[..labels.len().min(1)],
+        };
+        trimmed.join("/")
+    }
+
+    fn band(&mut self, _ctx: &Context, label: String, residency: u64) {
+        if !self.open {
+            self.samples.push(Sample { time: _ctx.time, bands: Vec::new() });
+            self.open = true;
+        }
+        let sample = self.samples.last_mut().unwrap();
+        match sample.bands.iter_mut().find(|(band, _)| *band == label) {
+            Some((_, total)) => *total += residency,
+            None => sample.bands.push((label, residency)),
+        }
+    }
+
+    /// Write the profile in the `.hp` format read by `hp2ps`.
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<W> {
+        let job = self.job.as_deref().unwrap_or("");
+        let date = self.start.map_or(String::new(), date);
+        writeln!(out, "JOB \"{}\"", job.replace('"', "\\\""))?;
+        writeln!(out, "DATE \"{date}\"")?;
+        writeln!(out, "SAMPLE_UNIT \"seconds\"")?;
+        writeln!(out, "VALUE_UNIT \"bytes\"")?;
+
+        // hp2ps expects the profile to start with an empty sample
+        if self.samples.first().is_none_or(|sample| sample.time.0 != 0) {
+            writeln!(out, "BEGIN_SAMPLE 0.00")?;
+            writeln!(out, "END_SAMPLE 0.00")?;
+        }
+
+        for sample in &self.samples {
+            let time = sample.time.0 as f64 / 1e9;
+            writeln!(out, "BEGIN_SAMPLE {time:.2}")?;
+            for (band, residency) in &sample.bands {
+                writeln!(out, "{band}\t{residency}")?;
+            }
+            writeln!(out, "END_SAMPLE {time:.2}")?;
+        }
+
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+/// A wall clock time in seconds since the epoch, shown like `ctime` does in UTC, eg
+/// `Sat Nov 18 21:33:20 2023`.
+fn date(secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let days = secs / 86400;
+    let time = secs % 86400;
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{} {} {:2} {:02}:{:02}:{:02} {}",
+        WEEKDAYS[(days % 7) as usize], MONTHS[(month - 1) as usize], day,
+        time / 3600, time / 60 % 60, time % 60, year)
+}
+
+impl EventlogParser for HeapProfile {
+    fn event_program_args(&mut self, _ctx: &Context, _capset: CapsetId, _args: Vec<u8>) {
+        let args: Vec<String> = _args.split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        self.job.get_or_insert(args.join(" "));
+    }
+
+    fn event_wall_clock_time(&mut self, _ctx: &Context, _capset: CapsetId, _sec: u64, _nsec: u32) {
+        self.start.get_or_insert(_sec.saturating_sub(_ctx.time.0 / 1_000_000_000));
+    }
+
+    fn event_heap_prof_cost_centre(&mut self, _ctx: &Context, _ccid: u32, _label: Vec<u8>, _module: Vec<u8>, _srcloc: Vec<u8>, _flags: u8) {
+        let label = String::from_utf8_lossy(&_label);
+        // CAFs are named by their module, like the RTS does
+        let name = if _flags & 1 != 0 {
+            format!("{}.{label}", String::from_utf8_lossy(&_module))
+        } else {
+            label.into_owned()
+        };
+        self.cost_centres.insert(_ccid, name);
+    }
+
+    fn event_heap_prof_sample_begin(&mut self, _ctx: &Context, _era: u64) {
+        self.samples.push(Sample { time: _ctx.time, bands: Vec::new() });
+        self.open = true;
+    }
+    fn event_heap_bio_prof_sample_begin(&mut self, _ctx: &Context, _era: u64, _time: Timestamp) {
+        self.samples.push(Sample { time: _time, bands: Vec::new() });
+        self.open = true;
+    }
+    fn event_heap_prof_sample_end(&mut self, _ctx: &Context, _era: u64) {
+        self.open = false;
+    }
+
+    fn event_heap_prof_sample_cost_centre(&mut self, _ctx: &Context, _profile: u8, _residency: u64, _stack: Vec<u32>) {
+        let label = self.stack_label(&_stack);
+        self.band(_ctx, label, _residency);
+    }
+    fn event_heap_prof_sample_string(&mut self, _ctx: &Context, _profile: u8, _residency: u64, _label: Vec<u8>) {
+        let label = String::from_utf8_lossy(&_label).into_owned();
+        self.band(_ctx, label, _residency);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn hp_file() {
+        let ms = 1_000_000;
+        let eventlog = write(|w| {
+            w.event(30, Timestamp(0), &[&Payload::new().u32(0)[..], b"prog\0+RTS\0-hc\0"].concat())?;
+            w.event(43, Timestamp(0), &Payload::new().u32(0).u64(1700343200).u32(0))?;
+            w.event(161, Timestamp(0), &Payload::new().u32(1).string("MAIN").string("MAIN").string("<built-in>").u8(0))?;
+            w.event(161, Timestamp(0), &Payload::new().u32(2).string("go").string("Main").string("Main.hs:3:1").u8(0))?;
+            w.event(161, Timestamp(0), &Payload::new().u32(3).string("CAF").string("Lib").string("<entire-module>").u8(1))?;
+            w.event(162, Timestamp(500 * ms), &Payload::new().u64(1))?;
+            for (residency, stack) in [(100, &[2, 1][..]), (50, &[3, 1]), (200, &[2, 1]), (10, &[1])] {
+                let mut payload = Payload::new().u8(1).u64(residency).u8(stack.len() as u8);
+                for ccid in stack {
+                    payload = payload.u32(*ccid);
+                }
+                w.event(163, Timestamp(500 * ms), &payload)?;
+            }
+            w.event(165, Timestamp(500 * ms), &Payload::new().u64(1))?;
+            w.event(162, Timestamp(1250 * ms), &Payload::new().u64(2))?;
+            w.event(164, Timestamp(1250 * ms), &Payload::new().u8(1).u64(8).string("Int"))?;
+            w.event(165, Timestamp(1250 * ms), &Payload::new().u64(2))?;
+            Ok(())
+        });
+
+        let mut profile = HeapProfile::new();
+        parse_reader(&eventlog[..], &mut profile).unwrap();
+        let hp = String::from_utf8(profile.write(Vec::new()).unwrap()).unwrap();
+        assert_eq!(hp, "\
+JOB \"prog +RTS -hc\"
+DATE \"Sat Nov 18 21:33:20 2023\"
+SAMPLE_UNIT \"seconds\"
+VALUE_UNIT \"bytes\"
+BEGIN_SAMPLE 0.00
+END_SAMPLE 0.00
+BEGIN_SAMPLE 0.50
+go\t300
+Lib.CAF\t50
+MAIN\t10
+END_SAMPLE 0.50
+BEGIN_SAMPLE 1.25
+Int\t8
+END_SAMPLE 1.25
+");
+    }
+}
@@ -0,0 +1,31 @@
+pub mod types;
+pub mod parse;
+pub mod encode;
+pub mod filter;
+pub mod merge;
+pub mod redact;
+pub mod order;
+pub mod timeline;
+pub mod threads;
+pub mod blocked;
+pub mod gc;
+pub mod mmu;
+pub mod heap;
+pub mod hp;
+pub mod sparks;
+pub mod chrome;
+pub mod perfetto;
+pub mod prof;
+pub mod pprof;
+pub mod export;
+#[cfg(feature = "parquet")]
+pub mod columnar;
+pub mod markers;
+pub mod otlp;
+pub mod metrics;
+pub mod stats;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+mod json;
+mod protobuf;
+mod svg;
@@ -1,343 +1,1212 @@
-#![feature(const_size_of_val)]
-#![feature(buf_read_has_data_left)]
+use std::collections::HashSet;
+use std::io::Write;
+use std::process::exit;
+use std::str::FromStr;
 
-mod parse;
+use ev::blocked::blocked_time;
+use ev::chrome::ChromeTrace;
+use ev::export::{Export, Format, Record, Records, Value};
+use ev::filter::{Filter, Selection};
+use ev::gc::{summary, GcPauses};
+use ev::heap::{heap_svg, write_csv, HeapSeries};
+use ev::hp::HeapProfile;
+use ev::merge::Merge;
+use ev::metrics::{serve, Metrics};
+use ev::mmu::{default_widths, mmu_svg, Utilisation};
+use ev::order::{RawEvent, TimeOrder};
+use ev::otlp::{post, OtlpTrace};
+use ev::perfetto::PerfettoTrace;
+use ev::pprof::{write_pprof_cpu, write_pprof_heap};
+use ev::prof::{write_folded, write_speedscope, ProfSamples};
+use ev::redact::{Redact, Redaction, Rewrite};
+use ev::sparks::{totals, Sparks};
+use ev::stats::Stats;
+use ev::threads::Threads;
+use ev::timeline::Timeline;
+use ev::parse::*;
+use ev::types::*;
 
-use crate::parse::*;
+/// Exit status when something went wrong that is not one of the below.
+const EXIT_FAILURE: i32 = 1;
+/// Exit status for a bad command line.
+const EXIT_USAGE: i32 = 2;
+/// Exit status when an input can not be read or is not an eventlog.
+const EXIT_INPUT: i32 = 3;
+/// Exit status when an output can not be written.
+const EXIT_OUTPUT: i32 = 4;
 
-struct PrintEvents {
-    current_block_remaining: i64,
-    current_block_capno: Option<u16>,
+const USAGE: &str = "usage: ev COMMAND [OPTIONS] ...
+
+An INPUT of - reads the eventlog from stdin. Every command writes to stdout, or to OUTPUT with
+-o OUTPUT, an OUTPUT of - is stdout too. parquet and sqlite need -o OUTPUT.
+
+Commands:
+    show        print the events
+    header      print the event types of the header
+    stats       count the events by type
+    export      write the events as CSV or JSON Lines
+    parquet     write the events as Parquet tables
+    sqlite      load the events and derived tables into SQLite
+    filter      select events into a new eventlog
+    merge       merge the eventlogs of several processes
+    redact      rewrite the strings of an eventlog
+    timeline    what every capability was doing
+    threads     what every thread was doing
+    blocked     time threads spent blocked
+    gc          GC pauses
+    mmu         mutator utilisation
+    heap        heap size over time
+    hp          write the heap profile as a .hp file
+    sparks      spark counters
+    folded      write the cost centre profile as folded stacks
+    speedscope  write the cost centre profile for speedscope
+    pprof       write the cost centre profile for pprof
+    chrome      write a Chrome trace
+    perfetto    write a Perfetto trace
+    otlp        write the user marker spans as OpenTelemetry spans
+    metrics     serve the metrics of a running program
+
+Run a command without arguments to see its usage.
+";
+
+/// Open INPUT, a path or `-` for stdin, exiting if it can not be opened.
+fn open_input(input: &str) -> Box<dyn std::io::Read> {
+    if input == "-" {
+        return Box::new(std::io::stdin().lock());
+    }
+    match std::fs::File::open(input) {
+        Ok(file) => Box::new(file),
+        Err(err) => {
+            eprintln!("could not open {input}: {err}");
+            exit(EXIT_INPUT);
+        },
+    }
+}
+
+/// Exit after failing to read INPUT.
+fn read_failed(input: &str, err: std::io::Error) -> ! {
+    eprintln!("could not read {input}: {err}");
+    exit(EXIT_INPUT);
+}
+
+/// Parse INPUT, a path or `-` for stdin, exiting if it can not be read.
+fn parse_input<Parser: EventlogParser>(input: &str, handle: &mut Parser) {
+    if let Err(err) = parse_reader(open_input(input), handle) {
+        read_failed(input, err);
+    }
+}
+
+/// Create OUTPUT, stdout if there is none or it is `-`, exiting if it can not be created.
+fn create_output(output: Option<&str>) -> Box<dyn Write> {
+    match output {
+        None | Some("-") => Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+        Some(output) => match std::fs::File::create(output) {
+            Ok(file) => Box::new(std::io::BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("could not create {output}: {err}");
+                exit(EXIT_OUTPUT);
+            },
+        },
+    }
+}
+
+/// Exit after failing to write OUTPUT, quietly when it is a pipe that was closed, like by `head`.
+fn write_failed(output: Option<&str>, err: std::io::Error) -> ! {
+    if err.kind() != std::io::ErrorKind::BrokenPipe {
+        eprintln!("could not write {}: {err}", output.unwrap_or("-"));
+    }
+    exit(EXIT_OUTPUT);
+}
+
+/// An OUTPUT that is written under a temporary name next to it and renamed to OUTPUT once it is
+/// complete, for the commands that write while they parse, so a bad input does not leave a
+/// truncated OUTPUT behind. Stdout is written as it goes.
+struct TempOutput {
+    /// OUTPUT, `None` for stdout
+    output: Option<String>,
+    temp: Option<String>,
 }
 
-impl PrintEvents {
-    fn new() -> PrintEvents {
-        PrintEvents {
-            current_block_remaining: 0,
-            current_block_capno: None,
+impl TempOutput {
+    fn create(output: Option<&str>) -> (TempOutput, Box<dyn Write>) {
+        let Some(output) = output.filter(|output| *output != "-") else {
+            return (TempOutput { output: None, temp: None }, create_output(None));
+        };
+        let temp = format!("{output}.{}.tmp", std::process::id());
+        let out: Box<dyn Write> = match std::fs::File::create(&temp) {
+            Ok(file) => Box::new(std::io::BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("could not create {output}: {err}");
+                exit(EXIT_OUTPUT);
+            },
+        };
+        (TempOutput { output: Some(output.to_string()), temp: Some(temp) }, out)
+    }
+
+    fn remove(&self) {
+        if let Some(temp) = &self.temp {
+            let _ = std::fs::remove_file(temp);
+        }
+    }
+
+    /// Remove the temporary file and exit, see [`read_failed`].
+    fn read_failed(&self, input: &str, err: std::io::Error) -> ! {
+        self.remove();
+        read_failed(input, err);
+    }
+
+    /// Remove the temporary file and exit, see [`write_failed`].
+    fn write_failed(&self, err: std::io::Error) -> ! {
+        self.remove();
+        write_failed(self.output.as_deref(), err);
+    }
+
+    /// Rename the complete temporary file to OUTPUT.
+    fn finish(self) {
+        if let (Some(output), Some(temp)) = (&self.output, &self.temp) {
+            if let Err(err) = std::fs::rename(temp, output) {
+                self.write_failed(err);
+            }
         }
     }
+}
 
-    fn start(&self, _time: u64) {
-        print!("[{_time}]{} ", match self.current_block_capno {
-            Some(x) if self.current_block_remaining > 0 =>
-                format!("[{}]", x),
-            _ => String::new(),
-        });
+/// The value of an option, exiting if it is missing or bad.
+fn value<T: FromStr>(arg: &str, value: Option<&String>, usage: &str) -> T
+where
+    T::Err: std::fmt::Display,
+{
+    let Some(value) = value else {
+        eprintln!("missing value for {arg}\n{usage}");
+        exit(EXIT_USAGE);
+    };
+    match value.parse() {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("bad value for {arg}: {err}\n{usage}");
+            exit(EXIT_USAGE);
+        },
     }
 }
 
-impl EventlogParser for PrintEvents {
-    fn event_start(&mut self, _id: u16, _time: u64, _size: usize) {
-        self.current_block_remaining -= (2 + 8 + _size) as i64;
+/// A path argument, exiting if it is an option the command does not have.
+fn path<'a>(arg: &'a String, usage: &str) -> &'a str {
+    if arg.starts_with('-') && arg != "-" {
+        eprintln!("unknown option {arg}\n{usage}");
+        exit(EXIT_USAGE);
     }
-    fn event_unknown(&mut self, id: u16, _time: u64, bytes: Vec<u8>) {
-        self.start(_time);
-        println!("[unknown {id}] {} bytes", bytes.len());
+    arg
+}
+
+/// The arguments of the commands that take only flags: the flags they take, `--output` and the
+/// input.
+fn simple_args<'a>(args: &'a [String], flags: &[&str], usage: &str) -> (Vec<&'a str>, Option<String>, &'a str) {
+    let mut set = Vec::new();
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value::<String>(arg, args.next(), usage)),
+            flag if flags.contains(&flag) => set.push(flag),
+            _ => paths.push(path(arg, usage)),
+        }
     }
 
-    fn event_block_marker(&mut self, _time: u64, _block_size: u32, _time_end: u64, _capno: u16) {
-        self.start(_time);
+    let [input] = paths[..] else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+    (set, output, input)
+}
 
-        let _capno = if _capno == !0 {
-            None
-        } else {
-            Some(_capno)
-        };
+fn show(args: &[String]) {
+    let usage = "usage: ev show [--raw] [-o OUTPUT] INPUT
+
+Prints every event in time order, --raw prints them in the order of the file with the block
+markers.";
+    let (flags, output, input) = simple_args(args, &["--raw"], usage);
+
+    let reader = open_input(input);
+    let (temp, mut out) = TempOutput::create(output.as_deref());
+    let mut res = Ok(());
+    let mut print = |record: Record| {
+        if res.is_err() {
+            return;
+        }
+        let cap = record.ctx.capno.map(|capno| capno.0.to_string()).unwrap_or("-".to_string());
+        let mut line = format!("{} {cap} {}", record.ctx.time, record.event);
+        for (name, value) in &record.fields {
+            match value {
+                Value::Text(value) => line.push_str(&format!(" {name}={value:?}")),
+                value => line.push_str(&format!(" {name}={value}")),
+            }
+        }
+        res = writeln!(out, "{line}");
+    };
 
-        println!("block start c:{_capno:?} {_block_size} {_time_end}");
-        self.current_block_capno = _capno;
-        self.current_block_remaining = _block_size as i64;
+    let parsed = if flags.contains(&"--raw") {
+        parse_reader(reader, &mut Records::new(&mut print))
+    } else {
+        let mut records = Records::new(&mut print);
+        let mut order = TimeOrder::new(|event: RawEvent| event.decode(&mut records));
+        let parsed = parse_reader(reader, &mut order);
+        order.finish();
+        parsed
+    };
+    if let Err(err) = parsed {
+        temp.read_failed(input, err);
     }
 
-    fn event_capset_create(&mut self, _time: u64, _capset: u32, _type_: u16) {
-        self.start(_time);
-        println!("capset create {_capset} {_type_}");
+    if let Err(err) = res.and_then(|_| out.flush()) {
+        temp.write_failed(err);
     }
-    fn event_capset_delete(&mut self, _time: u64, _capset: u32) {
-        self.start(_time);
-        println!("capset delete {_capset}");
+    drop(out);
+    temp.finish();
+}
+
+fn header(args: &[String]) {
+    let usage = "usage: ev header [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let event_types = match read_header(open_input(input)) {
+        Ok(event_types) => event_types,
+        Err(err) => {
+            eprintln!("could not read {input}: {err}");
+            exit(EXIT_INPUT);
+        },
+    };
+
+    let mut out = create_output(output.as_deref());
+    let res = event_types.iter()
+        .try_for_each(|(id, et)| {
+            let size = match et.size {
+                EventSize::Variable => "variable".to_string(),
+                EventSize::Fixed(size) => size.to_string(),
+            };
+            writeln!(out, "{id} {size} {}", et.descr)
+        })
+        .and_then(|_| out.flush());
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
     }
-    fn event_capset_assign_cap(&mut self, _time: u64, _capset: u32, _capno: u16) {
-        self.start(_time);
-        println!("capset assign {_capset} c:{_capno}");
+}
+
+fn stats(args: &[String]) {
+    let usage = "usage: ev stats [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut stats = Stats::new();
+    parse_input(input, &mut stats);
+    let summary = stats.finish();
+
+    let mut out = create_output(output.as_deref());
+    let total = summary.total();
+    let mut counts: Vec<_> = summary.counts.iter().collect();
+    counts.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+
+    let res = (|| {
+        writeln!(out, "events: {} ({} bytes)", total.count, total.bytes)?;
+        if let (Some(first), Some(last)) = (summary.first, summary.last) {
+            writeln!(out, "time: {first} to {last}")?;
+        }
+        writeln!(out, "capabilities: {}", summary.caps.len())?;
+        writeln!(out)?;
+        writeln!(out, "id count bytes event")?;
+        for (id, count) in counts {
+            writeln!(out, "{id} {} {} {}", count.count, count.bytes, summary.name(*id))?;
+        }
+        out.flush()
+    })();
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
     }
-    fn event_capset_remove_cap(&mut self, _time: u64, _capset: u32, _capno: u16) {
-        self.start(_time);
-        println!("capset remove {_capset} c:{_capno}");
+}
+fn filter(args: &[String]) {
+    let usage = "usage: ev filter [--from TIME] [--to TIME] [--event ID]... [--cap CAPNO]... [--thread THREADID]... [-o OUTPUT] INPUT";
+
+    let mut selection = Selection::default();
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => {
+                selection.from = Some(value(arg, args.next(), usage));
+            },
+            "--to" => {
+                selection.to = Some(value(arg, args.next(), usage));
+            },
+            "--event" => {
+                let id = value(arg, args.next(), usage);
+                selection.events.get_or_insert_with(HashSet::new).insert(id);
+            },
+            "--cap" => {
+                let capno = value(arg, args.next(), usage);
+                selection.caps.get_or_insert_with(HashSet::new).insert(capno);
+            },
+            "--thread" => {
+                let threadid = value(arg, args.next(), usage);
+                selection.threads.get_or_insert_with(HashSet::new).insert(threadid);
+            },
+            "-o" | "--output" => output = Some(value::<String>(arg, args.next(), usage)),
+            _ => paths.push(path(arg, usage)),
+        }
     }
 
-    fn event_cap_create(&mut self, _time: u64, _capno: u16) {
-        self.start(_time);
-        println!("cap create c:{_capno}");
+    let [input] = paths[..] else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    let reader = open_input(input);
+    let (temp, out) = TempOutput::create(output.as_deref());
+
+    let mut filter = Filter::new(selection, out);
+    if let Err(err) = parse_reader(reader, &mut filter) {
+        temp.read_failed(input, err);
     }
-    fn event_cap_delete(&mut self, _time: u64, _capno: u16) {
-        self.start(_time);
-        println!("cap delete c:{_capno}");
+    if let Err(err) = filter.finish() {
+        temp.write_failed(err);
     }
-    fn event_cap_disable(&mut self, _time: u64, _capno: u16) {
-        self.start(_time);
-        println!("cap disable c:{_capno}");
-    }
-
-    fn event_task_create(&mut self, _time: u64, _taskid: u64, _capno: u16, _k_threadid: u64) {
-        self.start(_time);
-        println!("task create ta:{_taskid} c:{_capno} {_k_threadid}");
-    }
-    fn event_task_migrate(&mut self, _time: u64, _taskid: u64, _from_capno: u16, _to_capno: u16) {
-        self.start(_time);
-        println!("task migrate ta:{_taskid} c:{_from_capno} c:{_to_capno}");
-    }
-    fn event_task_delete(&mut self, _time: u64, _taskid: u64) {
-        self.start(_time);
-        println!("task delete ta:{_taskid}");
-    }
-
-    fn event_thread_create(&mut self, _time: u64, _threadid: u32) {
-        self.start(_time);
-        println!("thread create ti:{_threadid}");
-    }
-    fn event_thread_run(&mut self, _time: u64, _threadid: u32) {
-        self.start(_time);
-        println!("thread run ti:{_threadid}");
-    }
-    fn event_thread_stop(&mut self, _time: u64, _threadid: u32, _status: u16, _block_threadid: u32) {
-        self.start(_time);
-        println!("thread stop ti:{_threadid}");
-    }
-    fn event_thread_label(&mut self, _time: u64, _threadid: u32, _label: Vec<u8>) {
-        self.start(_time);
-        println!("thread label ti:{_threadid}");
-    }
-    fn event_thread_runnable(&mut self, _time: u64, _threadid: u32) {
-        self.start(_time);
-        println!("thread runnable ti:{_threadid}");
-    }
-    fn event_thread_migrate(&mut self, _time: u64, _threadid: u32, _capno: u16) {
-        self.start(_time);
-        println!("thread migrate ti:{_threadid} c:{_capno}");
-    }
-    fn event_thread_wakeup(&mut self, _time: u64, _threadid: u32, _capno: u16) {
-        self.start(_time);
-        println!("thread wakeup ti:{_threadid} c:{_capno}");
-    }
-}
-
-#[repr(u16)]
-#[derive(Debug, Clone, Copy)]
-pub enum StopStatus {
-    HeapOverflow = 1,
-    StackOverflow = 2,
-    ThreadYielding = 3,
-    ThreadBlocked = 4,
-    ThreadFinished = 5,
-    ForeignCall = 6,
-    BlockedOnMVar = 7,
-    BlockedOnBlackHole = 8,
-    BlockedOnRead = 9,
-    BlockedOnWrite = 10,
-    BlockedOnDelay = 11,
-    BlockedOnSTM = 12,
-    BlockedOnDoProc = 13,
-    UNUSED2 = 14, // ? maybe used
-    UNUSED3 = 15, // ? maybe used
-    BlockedOnMsgThrowTo = 16,
-    UNUSED4 = 17, // ? maybe used
-    UNUSED5 = 18, // ? maybe used
-    UNUSED6 = 19, // ? maybe used
-    BlockedOnMVarRead = 20,
-}
-
-impl StopStatus {
-    fn from(status: u16) -> StopStatus {
-        assert!(
-            (status > 0 && status <= 20),
-            "unknown stop status: {status}");
-        unsafe {
-            *(&status as *const u16 as *const StopStatus)
-        }
-    }
-}
-
-#[derive(Debug)]
-pub enum Ev {
-    CapCreate {
-        capno: u16,
-    },
-    CapDelete {
-        capno: u16,
-    },
-    CapDisable {
-        capno: u16,
-    },
-
-    TaskCreate {
-        taskid: u64,
-        capno: u16,
-        kernel_tid: u64,
-    },
-    TaskMigrate {
-        taskid: u64,
-        from: u16,
-        to: u16,
-    },
-    TaskDelete {
-        taskid: u64,
-    },
-
-    ThreadCreate {
-        id: u32,
-    },
-    ThreadLabel {
-        id: u32,
-        label: String,
-    },
-    ThreadRun {
-        id: u32,
-    },
-    ThreadStop {
-        id: u32,
-        status: StopStatus,
-        block_on: u32,
-    },
-    ThreadRunnable {
-        id: u32,
-    },
-    ThreadMigrate {
-        id: u32,
-        capno: u16,
-    },
-    ThreadWakeup {
-        id: u32,
-        capno: u16,
-    },
-}
-
-use std::collections::BTreeMap;
-
-struct SchedEvents {
-    ordered: BTreeMap<u64, Vec<(Option<u16>, Ev)>>,
-    current_block_remaining: i64,
-    current_block_capno: Option<u16>,
-}
-
-impl SchedEvents {
-    fn new() -> Self {
-        SchedEvents {
-            ordered: BTreeMap::new(),
-            current_block_remaining: 0,
-            current_block_capno: None,
-        }
-    }
-
-    fn add(&mut self, _time: u64, ev: Ev) {
-        let block_cap = self.block_cap();
-        self.ordered.entry(_time).or_default()
-            .push((block_cap, ev));
-    }
-
-    fn block_cap(&self) -> Option<u16> {
-        if self.current_block_remaining > 0 {
-            self.current_block_capno
-        } else {
-            None
-        }
-    }
-}
-
-impl EventlogParser for SchedEvents {
-    fn event_start(&mut self, _id: u16, _time: u64, _size: usize) {
-        self.current_block_remaining -= (2 + 8 + _size) as i64;
-    }
-
-    fn event_block_marker(&mut self, _time: u64, _block_size: u32, _time_end: u64, _capno: u16) {
-        let _capno = if _capno == !0 {
-            None
-        } else {
-            Some(_capno)
+    temp.finish();
+}
+
+fn merge(args: &[String]) {
+    let usage = "usage: ev merge [-o OUTPUT] INPUT...";
+
+    let mut output = None;
+    let mut inputs = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value::<String>(arg, args.next(), usage)),
+            _ => inputs.push(path(arg, usage)),
+        }
+    }
+
+    if inputs.is_empty() {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    }
+
+    let mut merge = Merge::new();
+    for input in inputs {
+        if let Err(err) = merge.add(open_input(input)) {
+            read_failed(input, err);
+        }
+    }
+
+    let (temp, out) = TempOutput::create(output.as_deref());
+
+    if let Err(err) = merge.write(out) {
+        temp.write_failed(err);
+    }
+    temp.finish();
+}
+
+fn redact(args: &[String]) {
+    let usage = "usage: ev redact [--hash KIND]... [--drop KIND]... [--replace KIND REGEX REPLACEMENT]... [-o OUTPUT] INPUT
+KIND is one of args, env, messages, labels, cost-centres, heap-profile or all";
+
+    let mut redaction = Redaction::default();
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(value::<String>(arg, args.next(), usage));
+                continue;
+            },
+            "--hash" | "--drop" | "--replace" => {},
+            _ => {
+                paths.push(path(arg, usage));
+                continue;
+            },
+        }
+
+        let kind = args.next().map(String::as_str);
+        let rewrite = match arg.as_str() {
+            "--hash" => Rewrite::Hash,
+            "--drop" => Rewrite::Drop,
+            _ => {
+                let (Some(regex), Some(replacement)) = (args.next(), args.next()) else {
+                    eprintln!("missing regex or replacement for {arg}\n{usage}");
+                    exit(EXIT_USAGE);
+                };
+                let regex = match regex::bytes::Regex::new(regex) {
+                    Ok(regex) => regex,
+                    Err(err) => {
+                        eprintln!("bad regex for {arg}: {err}");
+                        exit(EXIT_USAGE);
+                    },
+                };
+                Rewrite::Replace(regex, replacement.as_bytes().to_vec())
+            },
         };
 
-        self.current_block_capno = _capno;
-        self.current_block_remaining = _block_size as i64;
+        let targets = match kind {
+            Some("args") => vec![&mut redaction.program_args],
+            Some("env") => vec![&mut redaction.program_env],
+            Some("messages") => vec![&mut redaction.user_messages],
+            Some("labels") => vec![&mut redaction.thread_labels],
+            Some("cost-centres") => vec![&mut redaction.cost_centres],
+            Some("heap-profile") => vec![&mut redaction.heap_profile],
+            Some("all") => vec![
+                &mut redaction.program_args,
+                &mut redaction.program_env,
+                &mut redaction.user_messages,
+                &mut redaction.thread_labels,
+                &mut redaction.cost_centres,
+                &mut redaction.heap_profile,
+            ],
+            _ => {
+                eprintln!("bad or missing kind for {arg}\n{usage}");
+                exit(EXIT_USAGE);
+            },
+        };
+        for target in targets {
+            *target = rewrite.clone();
+        }
     }
 
-    fn event_unknown(&mut self, id: u16, _time: u64, _bytes: Vec<u8>) {
-        panic!("unknown event {id} len={}", _bytes.len());
+    let [input] = paths[..] else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    let reader = open_input(input);
+    let (temp, out) = TempOutput::create(output.as_deref());
+
+    let mut redact = Redact::new(redaction, out);
+    if let Err(err) = parse_reader(reader, &mut redact) {
+        temp.read_failed(input, err);
     }
+    if let Err(err) = redact.finish() {
+        temp.write_failed(err);
+    }
+    temp.finish();
+}
+
+fn timeline(args: &[String]) {
+    let usage = "usage: ev timeline [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut timeline = Timeline::new();
+    let mut order = TimeOrder::new(|event: RawEvent| {
+        event.decode(&mut timeline);
+    });
+    parse_input(input, &mut order);
+    order.finish();
 
-    fn event_cap_create(&mut self, _time: u64, _capno: u16) {
-        let ev = Ev::CapCreate { capno: _capno };
-        self.add(_time, ev);
+    let mut out = create_output(output.as_deref());
+    let res = (|| {
+        for (capno, intervals) in timeline.finish() {
+            for interval in intervals {
+                writeln!(out, "{capno} {} {} {}", interval.start, interval.end, interval.activity)?;
+            }
+        }
+        out.flush()
+    })();
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
     }
-    fn event_cap_delete(&mut self, _time: u64, _capno: u16) {
-        let ev = Ev::CapDelete { capno: _capno };
-        self.add(_time, ev);
+}
+
+fn threads(args: &[String]) {
+    let usage = "usage: ev threads [--thread THREADID] [--at TIME] [-o OUTPUT] INPUT";
+
+    let mut only = None;
+    let mut at = None;
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--thread" => only = Some(value::<ThreadId>(arg, args.next(), usage)),
+            "--at" => at = Some(value::<Timestamp>(arg, args.next(), usage)),
+            "-o" | "--output" => output = Some(value::<String>(arg, args.next(), usage)),
+            _ => paths.push(path(arg, usage)),
+        }
     }
-    fn event_cap_disable(&mut self, _time: u64, _capno: u16) {
-        let ev = Ev::CapDisable { capno: _capno };
-        self.add(_time, ev);
+
+    let [input] = paths[..] else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    let mut threads = Threads::new();
+    let mut order = TimeOrder::new(|event: RawEvent| {
+        event.decode(&mut threads);
+    });
+    parse_input(input, &mut order);
+    order.finish();
+
+    let mut out = create_output(output.as_deref());
+    let res = (|| {
+        for (threadid, thread) in threads.finish() {
+            if only.is_some_and(|only| only != threadid) {
+                continue;
+            }
+
+            if let Some(at) = at {
+                match thread.state_at(at) {
+                    Some(state) => writeln!(out, "{threadid} {state}")?,
+                    None => writeln!(out, "{threadid} not alive")?,
+                }
+                continue;
+            }
+
+            write!(out, "{threadid}")?;
+            if let Some(label) = &thread.label {
+                write!(out, " {label:?}")?;
+            }
+            if let Some(created) = thread.created {
+                write!(out, " created {created}")?;
+            }
+            if let Some(finished) = thread.finished {
+                write!(out, " finished {finished}")?;
+            }
+            writeln!(out)?;
+            for interval in &thread.intervals {
+                writeln!(out, "  {} {} {}", interval.start, interval.end, interval.state)?;
+            }
+        }
+        out.flush()
+    })();
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
     }
+}
 
-    fn event_task_create(&mut self, _time: u64, taskid: u64, capno: u16, kernel_tid: u64) {
-        let ev = Ev::TaskCreate { taskid, capno, kernel_tid };
-        self.add(_time, ev);
+fn blocked(args: &[String]) {
+    let usage = "usage: ev blocked [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut threads = Threads::new();
+    let mut order = TimeOrder::new(|event: RawEvent| {
+        event.decode(&mut threads);
+    });
+    parse_input(input, &mut order);
+    order.finish();
+
+    let blocked = blocked_time(&threads.finish());
+
+    let mut out = create_output(output.as_deref());
+    let res = (|| {
+        writeln!(out, "total")?;
+        for (reason, time) in &blocked.total {
+            writeln!(out, "  {reason} {time:?}")?;
+        }
+
+        for (threadid, reasons) in &blocked.threads {
+            writeln!(out, "{threadid}")?;
+            for (reason, time) in reasons {
+                writeln!(out, "  {reason} {time:?}")?;
+            }
+        }
+
+        for (blocker, blocked) in &blocked.blockers {
+            writeln!(out, "{blocker} blocks")?;
+            for (threadid, time) in blocked {
+                writeln!(out, "  {threadid} {time:?}")?;
+            }
+        }
+        out.flush()
+    })();
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
     }
-    fn event_task_migrate(&mut self, _time: u64, taskid: u64, from: u16, to: u16) {
-        let ev = Ev::TaskMigrate { taskid, from, to };
-        self.add(_time, ev);
+}
+
+fn gc(args: &[String]) {
+    let usage = "usage: ev gc [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut pauses = GcPauses::new();
+    let mut order = TimeOrder::new(|event: RawEvent| {
+        event.decode(&mut pauses);
+    });
+    parse_input(input, &mut order);
+    order.finish();
+
+    let gcs = pauses.finish();
+
+    let gens: std::collections::BTreeSet<u16> = gcs.iter()
+        .filter_map(|gc| gc.stats.map(|stats| stats.gen))
+        .collect();
+    let summaries = std::iter::once(("all".to_string(), summary(&gcs)))
+        .chain(gens.into_iter().map(|gen| {
+            (format!("gen {gen}"), summary(gcs.iter().filter(|gc| gc.stats.is_some_and(|stats| stats.gen == gen))))
+        }));
+
+    let mut out = create_output(output.as_deref());
+    let res = (|| {
+        writeln!(out, "start pause sync gen copied slop fragmentation threads balance")?;
+        for gc in &gcs {
+            let stats = gc.stats;
+            writeln!(out, "{} {:?} {} {} {} {} {} {} {}",
+                gc.start,
+                gc.pause(),
+                gc.sync().map_or("-".to_string(), |sync| format!("{sync:?}")),
+                stats.map_or("-".to_string(), |stats| stats.gen.to_string()),
+                stats.map_or("-".to_string(), |stats| stats.copied.to_string()),
+                stats.map_or("-".to_string(), |stats| stats.slop.to_string()),
+                stats.map_or("-".to_string(), |stats| stats.fragmentation.to_string()),
+                stats.map_or("-".to_string(), |stats| stats.threads.to_string()),
+                gc.balance().map_or("-".to_string(), |balance| format!("{:.1}%", balance * 100.0)),
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "gcs count total p50 p90 p99 max")?;
+        for (name, summary) in summaries {
+            if let Some(summary) = summary {
+                writeln!(out, "{name} {} {:?} {:?} {:?} {:?} {:?}",
+                    summary.count, summary.total, summary.p50, summary.p90, summary.p99, summary.max)?;
+            }
+        }
+        out.flush()
+    })();
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
     }
-    fn event_task_delete(&mut self, _time: u64, taskid: u64) {
-        let ev = Ev::TaskDelete { taskid };
-        self.add(_time, ev);
+}
+
+fn mmu(args: &[String]) {
+    let usage = "usage: ev mmu [--width TIME]... [--windows TIME] [--svg SVG] [-o OUTPUT] INPUT";
+
+    let duration = |time: Timestamp| std::time::Duration::from_nanos(time.as_nanos());
+
+    let mut widths = Vec::new();
+    let mut windows = None;
+    let mut svg = None;
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" => widths.push(duration(value(arg, args.next(), usage))),
+            "--windows" => windows = Some(duration(value(arg, args.next(), usage))),
+            "--svg" => svg = Some(value::<String>(arg, args.next(), usage)),
+            "-o" | "--output" => output = Some(value::<String>(arg, args.next(), usage)),
+            _ => paths.push(path(arg, usage)),
+        }
     }
 
-    fn event_thread_create(&mut self, _time: u64, id: u32) {
-        let ev = Ev::ThreadCreate { id };
-        self.add(_time, ev);
+    let [input] = paths[..] else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    if widths.is_empty() {
+        widths = default_widths();
     }
-    fn event_thread_run(&mut self, _time: u64, id: u32) {
-        let ev = Ev::ThreadRun { id };
-        self.add(_time, ev);
+
+    let mut timeline = Timeline::new();
+    let mut order = TimeOrder::new(|event: RawEvent| {
+        event.decode(&mut timeline);
+    });
+    parse_input(input, &mut order);
+    order.finish();
+
+    let utilisation = Utilisation::new(&timeline.finish());
+
+    let mut out = create_output(output.as_deref());
+
+    if let Some(width) = windows {
+        let res = (|| {
+            writeln!(out, "start utilisation")?;
+            for (start, utilisation) in utilisation.windows(width, width / 10) {
+                writeln!(out, "{start} {utilisation:.4}")?;
+            }
+            out.flush()
+        })();
+        if let Err(err) = res {
+            write_failed(output.as_deref(), err);
+        }
+        return;
     }
-    fn event_thread_stop(&mut self, _time: u64, id: u32, status: u16, block_on: u32) {
-        let ev = Ev::ThreadStop { id, status: StopStatus::from(status), block_on };
-        self.add(_time, ev);
+
+    let curve = utilisation.mmu_curve(&widths);
+    let res = (|| {
+        writeln!(out, "width mmu gc_overhead")?;
+        for (width, mmu) in &curve {
+            writeln!(out, "{width:?} {mmu:.4} {:.4}", 1.0 - mmu)?;
+        }
+        out.flush()
+    })();
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
     }
-    fn event_thread_label(&mut self, _time: u64, id: u32, _label: Vec<u8>) {
-        let ev = Ev::ThreadLabel { id, label: String::from_utf8(_label).unwrap() };
-        self.add(_time, ev);
+
+    if let Some(svg) = svg {
+        if let Err(err) = std::fs::write(&svg, mmu_svg(&curve)) {
+            write_failed(Some(&svg), err);
+        }
     }
-    fn event_thread_runnable(&mut self, _time: u64, id: u32) {
-        let ev = Ev::ThreadRunnable { id };
-        self.add(_time, ev);
+}
+
+fn heap(args: &[String]) {
+    let usage = "usage: ev heap [--svg SVG] [-o OUTPUT] INPUT";
+
+    let mut svg = None;
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--svg" => svg = Some(value::<String>(arg, args.next(), usage)),
+            "-o" | "--output" => output = Some(value::<String>(arg, args.next(), usage)),
+            _ => paths.push(path(arg, usage)),
+        }
     }
-    fn event_thread_migrate(&mut self, _time: u64, id: u32, capno: u16) {
-        let ev = Ev::ThreadMigrate { id, capno };
-        self.add(_time, ev);
+
+    let [input] = paths[..] else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    let mut heap = HeapSeries::new();
+    let mut order = TimeOrder::new(|event: RawEvent| {
+        event.decode(&mut heap);
+    });
+    parse_input(input, &mut order);
+    order.finish();
+
+    let samples = heap.finish();
+
+    if let Err(err) = write_csv(&samples, create_output(output.as_deref())) {
+        write_failed(output.as_deref(), err);
     }
-    fn event_thread_wakeup(&mut self, _time: u64, id: u32, capno: u16) {
-        let ev = Ev::ThreadWakeup { id, capno };
-        self.add(_time, ev);
+
+    if let Some(svg) = svg {
+        if let Err(err) = std::fs::write(&svg, heap_svg(&samples)) {
+            write_failed(Some(&svg), err);
+        }
     }
 }
 
+fn hp(args: &[String]) {
+    let usage = "usage: ev hp [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
 
+    let mut profile = HeapProfile::new();
+    parse_input(input, &mut profile);
 
+    if let Err(err) = profile.write(create_output(output.as_deref())) {
+        write_failed(output.as_deref(), err);
+    }
+}
 
-fn main() {
-    // let mut printer = PrintEvents::new();
-    // parse("./ghc-9.4.4.eventlog", &mut printer);
-    let mut printer = SchedEvents::new();
-    // parse("./ghc-9.4.4.eventlog", &mut printer);
-    parse("./main.eventlog", &mut printer);
-    for (time, events) in printer.ordered {
-        for (mcap, event) in events {
-            print!("[{time}]");
-            match mcap {
-                Some(x) => { print!("[{}]", x) },
-                _ => {},
+fn sparks(args: &[String]) {
+    let usage = "usage: ev sparks [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut sparks = Sparks::new();
+    parse_input(input, &mut sparks);
+    let caps = sparks.finish();
+
+    let mut out = create_output(output.as_deref());
+    let res = (|| {
+        writeln!(out, "time cap created dud overflowed converted gcd fizzled remaining")?;
+        for (capno, sparks) in &caps {
+            for (time, delta) in sparks.deltas() {
+                writeln!(out, "{time} {capno} {} {} {} {} {} {} {}",
+                    delta.created, delta.dud, delta.overflowed, delta.converted, delta.gcd, delta.fizzled, delta.remaining)?;
             }
-            println!(" {event:?}");
         }
+
+        writeln!(out)?;
+        writeln!(out, "cap total created dud overflowed converted gcd fizzled remaining")?;
+        let total = totals(&caps);
+        let rows = caps.iter()
+            .map(|(capno, sparks)| (capno.to_string(), sparks.totals()))
+            .chain(std::iter::once(("all".to_string(), total)));
+        for (name, counters) in rows {
+            writeln!(out, "{name} {} {} {} {} {} {} {} {}",
+                counters.total(), counters.created, counters.dud, counters.overflowed, counters.converted, counters.gcd, counters.fizzled, counters.remaining)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{total}")?;
+        out.flush()
+    })();
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
+    }
+}
+
+fn chrome(args: &[String]) {
+    let usage = "usage: ev chrome [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut trace = ChromeTrace::new();
+    let mut order = TimeOrder::new(|event: RawEvent| trace.add(&event));
+    parse_input(input, &mut order);
+    order.finish();
+
+    if let Err(err) = trace.write(create_output(output.as_deref())) {
+        write_failed(output.as_deref(), err);
+    }
+}
+
+fn perfetto(args: &[String]) {
+    let usage = "usage: ev perfetto [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let reader = open_input(input);
+    let (temp, out) = TempOutput::create(output.as_deref());
+
+    let mut trace = PerfettoTrace::new(out);
+    let mut order = TimeOrder::new(|event: RawEvent| {
+        event.decode(&mut trace);
+    });
+    if let Err(err) = parse_reader(reader, &mut order) {
+        temp.read_failed(input, err);
+    }
+    order.finish();
+
+    if let Err(err) = trace.finish() {
+        temp.write_failed(err);
+    }
+    temp.finish();
+}
+
+fn folded(args: &[String]) {
+    let usage = "usage: ev folded [--heap] [-o OUTPUT] INPUT";
+    let (flags, output, input) = simple_args(args, &["--heap"], usage);
+    let heap = flags.contains(&"--heap");
+
+    let mut samples = ProfSamples::new();
+    parse_input(input, &mut samples);
+    let profiles = samples.finish();
+
+    let stacks: Vec<(&[u32], u64)> = if heap {
+        match profiles.peak_census() {
+            Some(census) => census.samples.iter()
+                .map(|(stack, residency)| (stack.as_slice(), *residency))
+                .collect(),
+            None => {
+                eprintln!("{input} has no cost centre heap profile, run the program with +RTS -hc -l");
+                exit(EXIT_FAILURE);
+            },
+        }
+    } else {
+        if profiles.cpu.is_empty() {
+            eprintln!("{input} has no time profile samples, run the program with +RTS -p -l");
+            exit(EXIT_FAILURE);
+        }
+        profiles.cpu_stacks()
+    };
+
+    if let Err(err) = write_folded(&profiles, stacks, create_output(output.as_deref())) {
+        write_failed(output.as_deref(), err);
+    }
+}
+
+fn speedscope(args: &[String]) {
+    let usage = "usage: ev speedscope [-o OUTPUT] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut samples = ProfSamples::new();
+    parse_input(input, &mut samples);
+    let profiles = samples.finish();
+
+    if let Err(err) = write_speedscope(&profiles, input, create_output(output.as_deref())) {
+        write_failed(output.as_deref(), err);
+    }
+}
+
+fn pprof(args: &[String]) {
+    let usage = "usage: ev pprof [--heap] [-o OUTPUT] INPUT";
+    let (flags, output, input) = simple_args(args, &["--heap"], usage);
+
+    let mut samples = ProfSamples::new();
+    parse_input(input, &mut samples);
+    let profiles = samples.finish();
+
+    let out = create_output(output.as_deref());
+
+    let res = if flags.contains(&"--heap") {
+        write_pprof_heap(&profiles, out)
+    } else {
+        write_pprof_cpu(&profiles, out)
+    };
+    if let Err(err) = res {
+        write_failed(output.as_deref(), err);
+    }
+}
+
+fn export(args: &[String]) {
+    let usage = "usage: ev export [--format csv|jsonl] [-o OUTPUT] INPUT";
+
+    let mut format = Format::Csv;
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = value(arg, args.next(), usage),
+            "-o" | "--output" => output = Some(value::<String>(arg, args.next(), usage)),
+            _ => paths.push(path(arg, usage)),
+        }
+    }
+
+    let [input] = paths[..] else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    let reader = open_input(input);
+    let (temp, out) = TempOutput::create(output.as_deref());
+
+    let mut export = Export::new(out, format);
+    let mut records = Records::new(|record: Record| export.add(&record));
+    if let Err(err) = parse_reader(reader, &mut records) {
+        temp.read_failed(input, err);
+    }
+
+    if let Err(err) = export.finish() {
+        temp.write_failed(err);
+    }
+    temp.finish();
+}
+
+#[cfg(feature = "parquet")]
+fn parquet(args: &[String]) {
+    let usage = "usage: ev parquet -o DIR INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+    let Some(output) = output.as_deref().filter(|output| *output != "-") else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    if let Err(err) = std::fs::create_dir_all(output) {
+        eprintln!("could not create {output}: {err}");
+        exit(EXIT_OUTPUT);
+    }
+    let mut export = match ev::columnar::ParquetExport::create(output) {
+        Ok(export) => export,
+        Err(err) => {
+            eprintln!("could not create {output}: {err}");
+            exit(EXIT_OUTPUT);
+        },
+    };
+
+    let mut records = Records::new(|record: Record| export.add(&record));
+    parse_input(input, &mut records);
+
+    if let Err(err) = export.finish() {
+        write_failed(Some(output), err);
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet(_args: &[String]) {
+    eprintln!("ev was built without Parquet support, build it with --features parquet");
+    exit(EXIT_USAGE);
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite(args: &[String]) {
+    let usage = "usage: ev sqlite -o OUTPUT INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+    let Some(output) = output.as_deref().filter(|output| *output != "-") else {
+        eprintln!("{usage}");
+        exit(EXIT_USAGE);
+    };
+
+    // start from an empty database, like the other outputs are truncated
+    if let Err(err) = std::fs::remove_file(output) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("could not remove {output}: {err}");
+            exit(EXIT_OUTPUT);
+        }
+    }
+    let mut db = match ev::sqlite::SqliteExport::create(output) {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("could not create {output}: {err}");
+            exit(EXIT_OUTPUT);
+        },
+    };
+
+    let mut order = TimeOrder::new(|event: RawEvent| db.add(&event));
+    parse_input(input, &mut order);
+    order.finish();
+
+    if let Err(err) = db.finish() {
+        eprintln!("could not write {output}: {err}");
+        exit(EXIT_OUTPUT);
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite(_args: &[String]) {
+    eprintln!("ev was built without SQLite support, build it with --features sqlite");
+    exit(EXIT_USAGE);
+}
+
+fn otlp(args: &[String]) {
+    let usage = "usage: ev otlp [-o OUTPUT|http://HOST:PORT[/PATH]] INPUT";
+    let (_, output, input) = simple_args(args, &[], usage);
+
+    let mut trace = OtlpTrace::new();
+    let mut order = TimeOrder::new(|event: RawEvent| trace.add(&event));
+    parse_input(input, &mut order);
+    order.finish();
+
+    if let Some(url) = output.as_deref().filter(|output| output.starts_with("http://")) {
+        let body = match trace.write(Vec::new()) {
+            Ok(body) => body,
+            Err(err) => {
+                eprintln!("could not write the trace: {err}");
+                exit(EXIT_FAILURE);
+            },
+        };
+        if let Err(err) = post(url, &body) {
+            eprintln!("could not send to {url}: {err}");
+            exit(EXIT_FAILURE);
+        }
+        return;
+    }
+
+    if let Err(err) = trace.write(create_output(output.as_deref())) {
+        write_failed(output.as_deref(), err);
+    }
+}
+
+fn metrics(args: &[String]) {
+    let usage = "usage: ev metrics [--listen ADDR] (--follow INPUT | --socket PATH | INPUT)";
+
+    let mut listen = "127.0.0.1:9464".to_string();
+    let mut follow = None;
+    let mut socket = None;
+    let mut input = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => {
+                listen = value(arg, args.next(), usage);
+            },
+            "--follow" => {
+                follow = Some(value(arg, args.next(), usage));
+            },
+            "--socket" => {
+                socket = Some(value(arg, args.next(), usage));
+            },
+            _ => {
+                let arg = path(arg, usage);
+                if input.is_some() {
+                    eprintln!("more than one input\n{usage}");
+                    exit(EXIT_USAGE);
+                }
+                input = Some(arg.to_string());
+            },
+        }
+    }
+
+    let listener = match std::net::TcpListener::bind(&listen) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("could not listen on {listen}: {err}");
+            exit(EXIT_FAILURE);
+        },
+    };
+
+    let mut metrics = Metrics::new();
+    let values = metrics.values();
+    let server = std::thread::spawn(move || serve(listener, values));
+    eprintln!("serving metrics on http://{listen}/metrics");
+
+    let res = match (&follow, &socket, &input) {
+        (Some(path), None, None) => match ev::parse::Follow::open(path) {
+            Ok(reader) => parse_reader(reader, &mut metrics),
+            Err(err) => {
+                eprintln!("could not open {path}: {err}");
+                exit(EXIT_INPUT);
+            },
+        },
+        #[cfg(unix)]
+        (None, Some(path), None) => match std::os::unix::net::UnixStream::connect(path) {
+            Ok(reader) => parse_reader(reader, &mut metrics),
+            Err(err) => {
+                eprintln!("could not connect to {path}: {err}");
+                exit(EXIT_INPUT);
+            },
+        },
+        (None, None, Some(path)) => parse_reader(open_input(path), &mut metrics),
+        _ => {
+            eprintln!("{usage}");
+            exit(EXIT_USAGE);
+        },
+    };
+    if let Err(err) = res {
+        let input = follow.or(socket).or(input).unwrap_or_default();
+        eprintln!("could not read {input}: {err}");
+        exit(EXIT_INPUT);
+    }
+    eprintln!("the eventlog has ended, still serving its last metrics");
+
+    if let Ok(Err(err)) = server.join() {
+        eprintln!("could not serve metrics: {err}");
+        exit(EXIT_FAILURE);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(command) = args.get(1) else {
+        eprint!("{USAGE}");
+        exit(EXIT_USAGE);
+    };
+    let args = &args[2..];
+
+    match command.as_str() {
+        "show" => show(args),
+        "header" => header(args),
+        "stats" => stats(args),
+        "export" => export(args),
+        "parquet" => parquet(args),
+        "sqlite" => sqlite(args),
+        "filter" => filter(args),
+        "merge" => merge(args),
+        "redact" => redact(args),
+        "timeline" => timeline(args),
+        "threads" => threads(args),
+        "blocked" => blocked(args),
+        "gc" => gc(args),
+        "mmu" => mmu(args),
+        "heap" => heap(args),
+        "hp" => hp(args),
+        "sparks" => sparks(args),
+        "folded" => folded(args),
+        "speedscope" => speedscope(args),
+        "pprof" => pprof(args),
+        "chrome" => chrome(args),
+        "perfetto" => perfetto(args),
+        "otlp" => otlp(args),
+        "metrics" => metrics(args),
+        "help" | "-h" | "--help" => {
+            if let Err(err) = std::io::stdout().write_all(USAGE.as_bytes()) {
+                write_failed(None, err);
+            }
+        },
+        _ => {
+            eprintln!("unknown command {command}");
+            eprint!("{USAGE}");
+            exit(EXIT_USAGE);
+        },
     }
 }
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::parse::{Context, EventlogParser};
+use crate::types::{CapNo, Timestamp};
+
+/// Prefix of the user marker that begins a span.
+pub const BEGIN: &str = "begin:";
+/// Prefix of the user marker that ends a span.
+pub const END: &str = "end:";
+
+/// A span of the program between a `begin:NAME` and an `end:NAME` user marker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub name: String,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// The capability that began the span
+    pub capno: Option<CapNo>,
+}
+
+/// Pairs the user markers `begin:NAME` and `end:NAME` into [`Span`]s.
+///
+/// An end marker ends the latest span of the same name that has not ended, so spans of the same
+/// name can nest. End markers without a begin marker and begin markers that never end are
+/// ignored.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]), the markers of a span can be
+/// written by different capabilities.
+///
+/// Call [`MarkerSpans::finish`] after the last event to get the spans.
+#[derive(Default)]
+pub struct MarkerSpans {
+    spans: Vec<Span>,
+    /// Spans that have begun and not ended, with their start and capability
+    open: HashMap<String, Vec<(Timestamp, Option<CapNo>)>>,
+}
+
+impl MarkerSpans {
+    pub fn new() -> Self {
+        MarkerSpans::default()
+    }
+
+    /// Return the spans in the order they ended.
+    pub fn finish(self) -> Vec<Span> {
+        self.spans
+    }
+}
+
+impl EventlogParser for MarkerSpans {
+    fn event_user_marker(&mut self, _ctx: &Context, _marker: Vec<u8>) {
+        let marker = String::from_utf8_lossy(&_marker);
+        if let Some(name) = marker.strip_prefix(BEGIN) {
+            self.open.entry(name.to_string()).or_default().push((_ctx.time, _ctx.capno));
+        } else if let Some(name) = marker.strip_prefix(END) {
+            let Some((start, capno)) = self.open.get_mut(name).and_then(Vec::pop) else {
+                return;
+            };
+            self.spans.push(Span {
+                name: name.to_string(),
+                start,
+                end: _ctx.time,
+                capno,
+            });
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::encode::EventlogWriter;
+use crate::parse::{parse_reader, Context, EventType, EventlogParser, BLOCK_MARKER};
+use crate::types::{CapNo, CapsetId, Timestamp};
+
+/// Event types whose payload starts with a capset.
+const CAPSET_EVENTS: [u16; 17] = [
+    25, // CAPSET_CREATE
+    26, // CAPSET_DELETE
+    27, // CAPSET_ASSIGN_CAP
+    28, // CAPSET_REMOVE_CAP
+    29, // RTS_IDENTIFIER
+    30, // PROGRAM_ARGS
+    31, // PROGRAM_ENV
+    32, // OSPROCESS_PID
+    33, // OSPROCESS_PPID
+    43, // WALL_CLOCK_TIME
+    49, // HEAP_ALLOCATED
+    50, // HEAP_SIZE
+    51, // HEAP_LIVE
+    52, // HEAP_INFO_GHC
+    53, // GC_STATS_GHC
+    90, // MEM_RETURN
+    91, // BLOCKS_SIZE
+];
+
+/// Capability numbers in payloads, as the event type, the offset and the size of the number.
+const CAP_FIELDS: [(u16, usize, usize); 13] = [
+    (4, 4, 2),    // MIGRATE_THREAD
+    (8, 4, 2),    // THREAD_WAKEUP
+    (27, 4, 2),   // CAPSET_ASSIGN_CAP
+    (28, 4, 2),   // CAPSET_REMOVE_CAP
+    (38, 0, 2),   // SPARK_STEAL
+    (45, 0, 2),   // CAP_CREATE
+    (46, 0, 2),   // CAP_DELETE
+    (47, 0, 2),   // CAP_DISABLE
+    (48, 0, 2),   // CAP_ENABLE
+    (55, 8, 2),   // TASK_CREATE
+    (56, 8, 2),   // TASK_MIGRATE
+    (56, 10, 2),  // TASK_MIGRATE
+    (167, 0, 4),  // PROF_SAMPLE_COST_CENTRE
+];
+
+/// Thread ids in payloads, as the event type and the offset of the id.
+const THREAD_FIELDS: [(u16, usize); 9] = [
+    (0, 0),  // CREATE_THREAD
+    (1, 0),  // RUN_THREAD
+    (2, 0),  // STOP_THREAD
+    (2, 6),  // STOP_THREAD, the blocking thread
+    (3, 0),  // THREAD_RUNNABLE
+    (4, 0),  // MIGRATE_THREAD
+    (8, 0),  // THREAD_WAKEUP
+    (15, 0), // CREATE_SPARK_THREAD
+    (44, 0), // THREAD_LABEL
+];
+
+/// Read the big endian number of `size` bytes at `offset`, if the payload has it.
+fn field(payload: &[u8], offset: usize, size: usize) -> Option<u32> {
+    let bytes = payload.get(offset..offset + size)?;
+    Some(bytes.iter().fold(0, |n, b| n << 8 | *b as u32))
+}
+
+fn set_field(payload: &mut [u8], offset: usize, size: usize, n: u32) {
+    payload[offset..offset + size].copy_from_slice(&n.to_be_bytes()[4 - size..]);
+}
+
+/// An event from one of the merged eventlogs.
+#[derive(Debug, Clone)]
+pub struct MergedEvent {
+    /// Index of the eventlog the event came from, in the order they were added
+    pub source: usize,
+    /// Process id of the process that wrote the eventlog, from OSPROCESS_PID
+    pub pid: Option<u32>,
+    /// Time of the event on the merged timeline
+    pub time: Timestamp,
+    /// Capability of the block the event is in, renumbered
+    pub capno: Option<CapNo>,
+    pub id: u16,
+    /// Payload of the event, with capsets, capabilities and thread ids renumbered
+    pub payload: Vec<u8>,
+}
+
+struct Log {
+    event_types: Vec<(u16, EventType)>,
+    events: Vec<(Timestamp, Option<CapNo>, u16, Vec<u8>)>,
+    /// Wall clock time in nanoseconds at time 0 of the eventlog
+    origin: Option<u64>,
+    pid: Option<u32>,
+}
+
+/// Collects the events of one eventlog.
+struct Collect {
+    log: Log,
+}
+
+impl EventlogParser for Collect {
+    fn header(&mut self, event_types: &[(u16, EventType)]) {
+        self.log.event_types = event_types.to_vec();
+    }
+
+    fn event_end(&mut self, ctx: &Context, payload: &[u8]) {
+        if ctx.id != BLOCK_MARKER {
+            self.log.events.push((ctx.time, ctx.capno, ctx.id, payload.to_vec()));
+        }
+    }
+
+    fn event_wall_clock_time(&mut self, _ctx: &Context, _capset: CapsetId, _sec: u64, _nsec: u32) {
+        // a time that does not fit is corrupt, the eventlog is then left unaligned
+        let Some(wall) = _sec.checked_mul(1_000_000_000).and_then(|wall| wall.checked_add(_nsec as u64)) else {
+            return;
+        };
+        self.log.origin.get_or_insert(wall.saturating_sub(_ctx.time.as_nanos()));
+    }
+
+    fn event_osprocess_pid(&mut self, _ctx: &Context, _capset: CapsetId, _pid: u32) {
+        self.log.pid.get_or_insert(_pid);
+    }
+}
+
+/// Merges eventlogs from multiple processes into one timeline.
+///
+/// The eventlogs are aligned using their WALL_CLOCK_TIME events, eventlogs without one are left
+/// as they are. Capsets, capabilities and thread ids are renumbered so that every eventlog gets
+/// its own: the capabilities and threads of an eventlog come after those of the eventlogs added
+/// before it, in the same order.
+#[derive(Default)]
+pub struct Merge {
+    logs: Vec<Log>,
+    next_capset: u32,
+    next_capno: u32,
+    next_threadid: u32,
+}
+
+impl Merge {
+    pub fn new() -> Self {
+        Merge::default()
+    }
+
+    /// Parse an eventlog and add it to the merge.
+    pub fn add<R: Read>(&mut self, input: R) -> io::Result<()> {
+        let mut collect = Collect {
+            log: Log {
+                event_types: Vec::new(),
+                events: Vec::new(),
+                origin: None,
+                pid: None,
+            },
+        };
+        parse_reader(input, &mut collect)?;
+
+        let mut log = collect.log;
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("too many {what} to merge"));
+
+        // the capabilities and threads of this eventlog start after the ones of the previous
+        let mut capnos = 0;
+        let mut threadids = 0;
+        for (_, capno, id, payload) in &log.events {
+            if let Some(capno) = capno {
+                capnos = capnos.max(capno.0 as u32 + 1);
+            }
+            for (_, offset, size) in CAP_FIELDS.iter().filter(|(event, _, _)| event == id) {
+                if let Some(capno) = field(payload, *offset, *size) {
+                    capnos = capnos.max(capno + 1);
+                }
+            }
+            for (_, offset) in THREAD_FIELDS.iter().filter(|(event, _)| event == id) {
+                if let Some(threadid) = field(payload, *offset, 4) {
+                    threadids = threadids.max(threadid);
+                }
+            }
+        }
+        let cap_base = self.next_capno;
+        let thread_base = self.next_threadid;
+        self.next_capno = cap_base.checked_add(capnos)
+            .filter(|next| *next <= u16::MAX as u32)
+            .ok_or_else(|| invalid("capabilities"))?;
+        self.next_threadid = thread_base.checked_add(threadids)
+            .ok_or_else(|| invalid("threads"))?;
+
+        let mut capsets = HashMap::new();
+        for (_, capno, id, payload) in log.events.iter_mut() {
+            if let Some(capno) = capno {
+                capno.0 += cap_base as u16;
+            }
+            for (_, offset, size) in CAP_FIELDS.iter().filter(|(event, _, _)| event == id) {
+                if let Some(capno) = field(payload, *offset, *size) {
+                    set_field(payload, *offset, *size, capno + cap_base);
+                }
+            }
+            for (_, offset) in THREAD_FIELDS.iter().filter(|(event, _)| event == id) {
+                // 0 is no thread
+                if let Some(threadid @ 1..) = field(payload, *offset, 4) {
+                    set_field(payload, *offset, 4, threadid + thread_base);
+                }
+            }
+
+            if !CAPSET_EVENTS.contains(id) || payload.len() < 4 {
+                continue;
+            }
+            let capset = u32::from_be_bytes(payload[0..4].try_into().unwrap());
+            let capset = *capsets.entry(capset).or_insert_with(|| {
+                self.next_capset += 1;
+                self.next_capset - 1
+            });
+            payload[0..4].copy_from_slice(&capset.to_be_bytes());
+        }
+
+        self.logs.push(log);
+        Ok(())
+    }
+
+    /// How much to move the events of each eventlog forward in time.
+    fn shifts(&self) -> Vec<Duration> {
+        let base = self.logs.iter()
+            .filter_map(|log| log.origin)
+            .min()
+            .unwrap_or(0);
+        self.logs.iter()
+            .map(|log| Duration::from_nanos(log.origin.map_or(0, |origin| origin - base)))
+            .collect()
+    }
+
+    /// The events of all eventlogs, ordered by time on the merged timeline.
+    pub fn events(&self) -> Vec<MergedEvent> {
+        let shifts = self.shifts();
+        let mut events: Vec<MergedEvent> = self.logs.iter().enumerate()
+            .flat_map(|(source, log)| {
+                let shift = shifts[source];
+                log.events.iter().map(move |(time, capno, id, payload)| MergedEvent {
+                    source,
+                    pid: log.pid,
+                    time: *time + shift,
+                    capno: *capno,
+                    id: *id,
+                    payload: payload.clone(),
+                })
+            })
+            .collect();
+        events.sort_by_key(|ev| ev.time);
+        events
+    }
+
+    /// Write the merged eventlog.
+    ///
+    /// The header has the event types of all the eventlogs, it is an error if two eventlogs have
+    /// different sizes for the same event type.
+    pub fn write<W: Write>(&self, out: W) -> io::Result<W> {
+        let mut event_types: Vec<(u16, EventType)> = Vec::new();
+        for log in &self.logs {
+            for (id, et) in &log.event_types {
+                match event_types.iter().find(|(other, _)| other == id) {
+                    None => event_types.push((*id, et.clone())),
+                    Some((_, other)) if other.size == et.size => {},
+                    Some(_) => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                            format!("eventlogs disagree on the size of event type {id}")));
+                    },
+                }
+            }
+        }
+
+        let mut writer = EventlogWriter::new(out);
+        writer.header(&event_types)?;
+
+        let mut current_block = None;
+        for ev in self.events() {
+            let block = ev.capno.map(|capno| (ev.source, capno));
+            if block != current_block {
+                match ev.capno {
+                    Some(capno) => writer.begin_block(ev.time, ev.time, Some(capno))?,
+                    None => writer.end_block()?,
+                }
+                current_block = block;
+            }
+            writer.event(ev.id, ev.time, &ev.payload)?;
+        }
+
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::write;
+
+    fn wall_clock_time(sec: u64, nsec: u32) -> Vec<u8> {
+        let mut payload = vec![0, 0, 0, 0];
+        payload.extend(sec.to_be_bytes());
+        payload.extend(nsec.to_be_bytes());
+        payload
+    }
+
+    /// An eventlog with one capset, capabilities 0 and 1 and thread 1 created on capability 0.
+    fn eventlog(wall_clock: &[u8]) -> Vec<u8> {
+        write(|w| {
+            w.event(25, Timestamp(0), &[0, 0, 0, 0, 0, 2])?;
+            w.event(43, Timestamp(0), wall_clock)?;
+            w.event(45, Timestamp(0), &[0, 0])?;
+            w.event(45, Timestamp(0), &[0, 1])?;
+            w.begin_block(Timestamp(10), Timestamp(10), Some(CapNo(0)))?;
+            w.event(0, Timestamp(10), &[0, 0, 0, 1])?;
+            Ok(())
+        })
+    }
+
+    /// Source, time, capability, id and payload of an event.
+    type Row = (usize, Timestamp, Option<CapNo>, u16, Vec<u8>);
+
+    fn events(merge: &Merge) -> Vec<Row> {
+        merge.events().into_iter()
+            .map(|ev| (ev.source, ev.time, ev.capno, ev.id, ev.payload))
+            .collect()
+    }
+
+    #[test]
+    fn aligns_and_renumbers() {
+        let mut merge = Merge::new();
+        merge.add(&eventlog(&wall_clock_time(100, 0))[..]).unwrap();
+        merge.add(&eventlog(&wall_clock_time(100, 500))[..]).unwrap();
+
+        assert_eq!(events(&merge), [
+            (0, Timestamp(0), None, 25, vec![0, 0, 0, 0, 0, 2]),
+            (0, Timestamp(0), None, 43, wall_clock_time(100, 0)),
+            (0, Timestamp(0), None, 45, vec![0, 0]),
+            (0, Timestamp(0), None, 45, vec![0, 1]),
+            (0, Timestamp(10), Some(CapNo(0)), 0, vec![0, 0, 0, 1]),
+            (1, Timestamp(500), None, 25, vec![0, 0, 0, 1, 0, 2]),
+            (1, Timestamp(500), None, 43, {
+                let mut payload = wall_clock_time(100, 500);
+                payload[3] = 1;
+                payload
+            }),
+            (1, Timestamp(500), None, 45, vec![0, 2]),
+            (1, Timestamp(500), None, 45, vec![0, 3]),
+            (1, Timestamp(510), Some(CapNo(2)), 0, vec![0, 0, 0, 2]),
+        ]);
+    }
+
+    #[test]
+    fn ignores_overflowing_wall_clock() {
+        let mut merge = Merge::new();
+        merge.add(&eventlog(&wall_clock_time(100, 0))[..]).unwrap();
+        merge.add(&eventlog(&wall_clock_time(u64::MAX, 0))[..]).unwrap();
+
+        let times: Vec<_> = events(&merge).into_iter().map(|(source, time, ..)| (source, time)).collect();
+        assert!(times.contains(&(1, Timestamp(10))));
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+
+use crate::parse::{Context, EventlogParser, SparkCounters, ThreadStopStatus};
+use crate::types::{CapNo, CapsetId, ThreadId, Timestamp};
+
+/// Upper bounds of the buckets of the GC pause histogram, in seconds.
+const PAUSE_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Histogram of the GC pauses of one generation.
+#[derive(Debug, Clone, Default)]
+pub struct PauseHistogram {
+    /// Pauses in each of [`PAUSE_BUCKETS`], not cumulative
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    /// Seconds
+    pub sum: f64,
+}
+
+impl PauseHistogram {
+    fn observe(&mut self, seconds: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; PAUSE_BUCKETS.len()];
+        }
+        if let Some(i) = PAUSE_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// The current values of the metrics, see [`Metrics`].
+#[derive(Debug, Clone, Default)]
+pub struct MetricValues {
+    pub events: u64,
+    pub heap_size: BTreeMap<CapsetId, u64>,
+    pub heap_live: BTreeMap<CapsetId, u64>,
+    /// Bytes allocated by all the capabilities of each capset
+    pub allocated: BTreeMap<CapsetId, u64>,
+    /// Bytes per second between the last two times the capabilities of each capset reported
+    /// HEAP_ALLOCATED
+    pub allocation_rate: BTreeMap<CapsetId, f64>,
+    pub gc_pauses: BTreeMap<u16, PauseHistogram>,
+    pub gc_copied: BTreeMap<u16, u64>,
+    pub threads: u64,
+    pub runnable: u64,
+    pub sparks: BTreeMap<CapNo, SparkCounters>,
+}
+
+/// One of the counters of [`SparkCounters`].
+type SparkCounter = fn(&SparkCounters) -> u64;
+
+fn family(out: &mut String, name: &str, type_: &str, unit: Option<&str>, help: &str) {
+    let _ = writeln!(out, "# TYPE {name} {type_}");
+    if let Some(unit) = unit {
+        let _ = writeln!(out, "# UNIT {name} {unit}");
+    }
+    let _ = writeln!(out, "# HELP {name} {help}");
+}
+
+impl MetricValues {
+    /// The metrics in the OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        family(&mut out, "ghc_eventlog_events", "counter", None, "Events parsed from the eventlog.");
+        let _ = writeln!(out, "ghc_eventlog_events_total {}", self.events);
+
+        family(&mut out, "ghc_heap_size_bytes", "gauge", Some("bytes"), "Size of the heap.");
+        for (capset, size) in &self.heap_size {
+            let _ = writeln!(out, "ghc_heap_size_bytes{{capset=\"{}\"}} {size}", capset.0);
+        }
+        family(&mut out, "ghc_heap_live_bytes", "gauge", Some("bytes"), "Live data in the heap after the last major GC.");
+        for (capset, live) in &self.heap_live {
+            let _ = writeln!(out, "ghc_heap_live_bytes{{capset=\"{}\"}} {live}", capset.0);
+        }
+        family(&mut out, "ghc_allocated_bytes", "counter", Some("bytes"), "Bytes allocated since the program started.");
+        for (capset, allocated) in &self.allocated {
+            let _ = writeln!(out, "ghc_allocated_bytes_total{{capset=\"{}\"}} {allocated}", capset.0);
+        }
+        family(&mut out, "ghc_allocation_rate_bytes_per_second", "gauge", None, "Allocation rate between the last two heap samples.");
+        for (capset, rate) in &self.allocation_rate {
+            let _ = writeln!(out, "ghc_allocation_rate_bytes_per_second{{capset=\"{}\"}} {rate}", capset.0);
+        }
+
+        family(&mut out, "ghc_gc_pause_seconds", "histogram", Some("seconds"), "GC pauses by generation.");
+        for (gen, histogram) in &self.gc_pauses {
+            let mut cumulative = 0;
+            for (bound, count) in PAUSE_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "ghc_gc_pause_seconds_bucket{{gen=\"{gen}\",le=\"{bound:?}\"}} {cumulative}");
+            }
+            let _ = writeln!(out, "ghc_gc_pause_seconds_bucket{{gen=\"{gen}\",le=\"+Inf\"}} {}", histogram.count);
+            let _ = writeln!(out, "ghc_gc_pause_seconds_count{{gen=\"{gen}\"}} {}", histogram.count);
+            let _ = writeln!(out, "ghc_gc_pause_seconds_sum{{gen=\"{gen}\"}} {}", histogram.sum);
+        }
+        family(&mut out, "ghc_gc_copied_bytes", "counter", Some("bytes"), "Bytes copied by GC, by generation.");
+        for (gen, copied) in &self.gc_copied {
+            let _ = writeln!(out, "ghc_gc_copied_bytes_total{{gen=\"{gen}\"}} {copied}");
+        }
+
+        family(&mut out, "ghc_threads", "gauge", None, "Haskell threads that have not finished.");
+        let _ = writeln!(out, "ghc_threads {}", self.threads);
+        family(&mut out, "ghc_threads_runnable", "gauge", None, "Haskell threads waiting to be run.");
+        let _ = writeln!(out, "ghc_threads_runnable {}", self.runnable);
+
+        let sparks: [(&str, SparkCounter); 6] = [
+            ("created", |c| c.created),
+            ("dud", |c| c.dud),
+            ("overflowed", |c| c.overflowed),
+            ("converted", |c| c.converted),
+            ("gcd", |c| c.gcd),
+            ("fizzled", |c| c.fizzled),
+        ];
+        for (name, value) in sparks {
+            family(&mut out, &format!("ghc_sparks_{name}"), "counter", None, &format!("Sparks {name}, by capability."));
+            for (capno, counters) in &self.sparks {
+                let _ = writeln!(out, "ghc_sparks_{name}_total{{cap=\"{}\"}} {}", capno.0, value(counters));
+            }
+        }
+        family(&mut out, "ghc_sparks_remaining", "gauge", None, "Sparks in the spark pool, by capability.");
+        for (capno, counters) in &self.sparks {
+            let _ = writeln!(out, "ghc_sparks_remaining{{cap=\"{}\"}} {}", capno.0, counters.remaining);
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+/// The allocated bytes of a capset, GHC writes a HEAP_ALLOCATED for every capability with the
+/// bytes allocated by that capability.
+#[derive(Default)]
+struct Allocated {
+    /// The last HEAP_ALLOCATED of every capability
+    caps: BTreeMap<Option<CapNo>, u64>,
+    /// The latest time of a HEAP_ALLOCATED and the total at that time
+    last: Option<(Timestamp, u64)>,
+    /// The latest time and total before that
+    before: Option<(Timestamp, u64)>,
+}
+
+/// Keeps the metrics of a running program up to date from its eventlog: the heap size, live
+/// bytes and allocation, the GC pauses and copied bytes by generation, the number of threads and
+/// runnable threads, and the spark counters.
+///
+/// The values are shared with [`serve`], get them with [`Metrics::values`] before parsing. The
+/// events are used in the order they are parsed, so the values follow the program as its
+/// eventlog blocks are written.
+#[derive(Default)]
+pub struct Metrics {
+    values: Arc<Mutex<MetricValues>>,
+    /// Threads that have not finished
+    threads: BTreeSet<ThreadId>,
+    runnable: BTreeSet<ThreadId>,
+    allocated: BTreeMap<CapsetId, Allocated>,
+    /// Start of the GC in progress, and the latest GC_END
+    gc_start: Option<Timestamp>,
+    gc_end: Timestamp,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn values(&self) -> Arc<Mutex<MetricValues>> {
+        self.values.clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut MetricValues)) {
+        f(&mut self.values.lock().unwrap());
+    }
+
+    fn update_threads(&self) {
+        let threads = self.threads.len() as u64;
+        let runnable = self.runnable.len() as u64;
+        self.update(|values| {
+            values.threads = threads;
+            values.runnable = runnable;
+        });
+    }
+}
+
+impl EventlogParser for Metrics {
+    fn event_end(&mut self, _ctx: &Context, _payload: &[u8]) {
+        self.update(|values| values.events += 1);
+    }
+
+    fn event_heap_size(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        self.update(|values| {
+            values.heap_size.insert(_capset, _size);
+        });
+    }
+    fn event_heap_live(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        self.update(|values| {
+            values.heap_live.insert(_capset, _size);
+        });
+    }
+    fn event_heap_allocated(&mut self, _ctx: &Context, _capset: CapsetId, _allocated_bytes: u64) {
+        let allocated = self.allocated.entry(_capset).or_default();
+        allocated.caps.insert(_ctx.capno, _allocated_bytes);
+        let total = allocated.caps.values().sum();
+        let time = match allocated.last {
+            Some((time, _)) if time >= _ctx.time => time,
+            last => {
+                allocated.before = last;
+                _ctx.time
+            },
+        };
+        allocated.last = Some((time, total));
+        let rate = allocated.before
+            .map(|(before_time, before)| total.saturating_sub(before) as f64 / (time - before_time).as_secs_f64());
+        self.update(|values| {
+            values.allocated.insert(_capset, total);
+            if let Some(rate) = rate {
+                values.allocation_rate.insert(_capset, rate);
+            }
+        });
+    }
+
+    fn event_gc_start(&mut self, _ctx: &Context) {
+        self.gc_start.get_or_insert(_ctx.time);
+    }
+    fn event_gc_end(&mut self, _ctx: &Context) {
+        self.gc_end = self.gc_end.max(_ctx.time);
+    }
+    fn event_gc_stats_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _copied: u64, _slop: u64, _fragmentation: u64, _threads: u32, _max_copied: u64, _total_copied: u64, _balanced_copied: u64) {
+        let pause = self.gc_start.take().map(|start| {
+            // the stats can come before the last GC_END of a parallel GC
+            let end = if self.gc_end > start { self.gc_end } else { _ctx.time };
+            (end - start).as_secs_f64()
+        });
+        self.update(|values| {
+            if let Some(pause) = pause {
+                values.gc_pauses.entry(_gen).or_default().observe(pause);
+            }
+            *values.gc_copied.entry(_gen).or_default() += _copied;
+        });
+    }
+
+    fn event_thread_create(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.threads.insert(_threadid);
+        self.runnable.insert(_threadid);
+        self.update_threads();
+    }
+    fn event_thread_run(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.threads.insert(_threadid);
+        self.runnable.remove(&_threadid);
+        self.update_threads();
+    }
+    fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, _status: ThreadStopStatus) {
+        if _status == ThreadStopStatus::ThreadFinished {
+            self.threads.remove(&_threadid);
+            self.runnable.remove(&_threadid);
+        } else if _status.is_blocked() || _status == ThreadStopStatus::ForeignCall {
+            self.runnable.remove(&_threadid);
+        } else {
+            self.runnable.insert(_threadid);
+        }
+        self.update_threads();
+    }
+    fn event_thread_runnable(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.runnable.insert(_threadid);
+        self.update_threads();
+    }
+    fn event_thread_wakeup(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.runnable.insert(_threadid);
+        self.update_threads();
+    }
+    fn event_thread_migrate(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.runnable.insert(_threadid);
+        self.update_threads();
+    }
+
+    fn event_spark_counters(&mut self, _ctx: &Context, _counters: SparkCounters) {
+        let Some(capno) = _ctx.capno else {
+            return;
+        };
+        self.update(|values| {
+            values.sparks.insert(capno, _counters);
+        });
+    }
+}
+
+/// How long a client gets to send its request and read the metrics.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve the metrics over HTTP, every request gets them in the OpenMetrics text format. Every
+/// connection is handled on a thread of its own, so a slow client does not hold up the others.
+/// Runs until accepting a connection fails.
+pub fn serve(listener: TcpListener, values: Arc<Mutex<MetricValues>>) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let values = values.clone();
+        std::thread::spawn(move || {
+            // a client that went away or timed out does not stop the server
+            let _ = respond(stream, &values);
+        });
+    }
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, values: &Mutex<MetricValues>) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    // read the request up to the empty line, the path does not matter
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line != "\r\n" {
+        line.clear();
+    }
+
+    let body = values.lock().unwrap().render();
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len())?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn allocated_by_all_capabilities() {
+        let second = 1_000_000_000;
+        let eventlog = write(|w| {
+            for (time, capno, allocated) in [(second, 0, 100), (second, 1, 200), (2 * second, 0, 300), (2 * second, 1, 400)] {
+                w.begin_block(Timestamp(time), Timestamp(time), Some(CapNo(capno)))?;
+                w.event(49, Timestamp(time), &Payload::new().u32(0).u64(allocated))?;
+            }
+            Ok(())
+        });
+
+        let mut metrics = Metrics::new();
+        let values = metrics.values();
+        parse_reader(&eventlog[..], &mut metrics).unwrap();
+
+        let values = values.lock().unwrap();
+        assert_eq!(values.allocated[&CapsetId(0)], 700);
+        assert_eq!(values.allocation_rate[&CapsetId(0)], 400.0);
+        let metrics = values.render();
+        assert!(metrics.contains("ghc_allocated_bytes_total{capset=\"0\"} 700\n"), "{metrics}");
+        assert!(metrics.contains("ghc_allocation_rate_bytes_per_second{capset=\"0\"} 400\n"), "{metrics}");
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::svg::{Chart, Series};
+use crate::timeline::{Activity, Interval};
+use crate::types::{CapNo, Timestamp};
+
+/// Mutator utilisation over time, from the GC intervals of the capabilities.
+///
+/// The utilisation of a window of time is the share of the time the capabilities were not in
+/// GC, over all capabilities together: 0.75 means that a quarter of the capability time in the
+/// window went to GC. A capability that was idle counts as available to the mutator.
+pub struct Utilisation {
+    /// Times at which the number of capabilities in GC changes
+    times: Vec<u64>,
+    /// Number of capabilities in GC from each time up to the next
+    gc: Vec<u64>,
+    /// GC time of all capabilities together up to each time
+    gc_before: Vec<u128>,
+    caps: u64,
+    start: Timestamp,
+    end: Timestamp,
+}
+
+impl Utilisation {
+    /// The span of the timelines is from the start of the first interval to the end of the last.
+    pub fn new(timelines: &BTreeMap<CapNo, Vec<Interval>>) -> Self {
+        let mut changes: BTreeMap<u64, i64> = BTreeMap::new();
+        for interval in timelines.values().flatten() {
+            if interval.activity == Activity::Gc {
+                *changes.entry(interval.start.0).or_default() += 1;
+                *changes.entry(interval.end.0).or_default() -= 1;
+            }
+        }
+
+        let mut times = Vec::new();
+        let mut gc = Vec::new();
+        let mut gc_before = Vec::new();
+        let mut count = 0i64;
+        let mut total = 0u128;
+        for (time, change) in changes {
+            if let (Some(prev), Some(prev_count)) = (times.last(), gc.last()) {
+                total += (time - prev) as u128 * *prev_count as u128;
+            }
+            count += change;
+            times.push(time);
+            gc.push(count as u64);
+            gc_before.push(total);
+        }
+
+        let intervals = || timelines.values().flatten();
+        Utilisation {
+            times,
+            gc,
+            gc_before,
+            caps: timelines.values().filter(|intervals| !intervals.is_empty()).count() as u64,
+            start: intervals().map(|interval| interval.start).min().unwrap_or_default(),
+            end: intervals().map(|interval| interval.end).max().unwrap_or_default(),
+        }
+    }
+
+    /// GC time of all capabilities together up to `time`, in nanoseconds.
+    fn gc_until(&self, time: u64) -> u128 {
+        let i = self.times.partition_point(|t| *t <= time);
+        if i == 0 {
+            return 0;
+        }
+        self.gc_before[i - 1] + (time - self.times[i - 1]) as u128 * self.gc[i - 1] as u128
+    }
+
+    /// Utilisation of the window from `start` lasting `width`.
+    pub fn window(&self, start: Timestamp, width: Duration) -> f64 {
+        let width = width.as_nanos() as u64;
+        if width == 0 || self.caps == 0 {
+            return 1.0;
+        }
+        let gc = self.gc_until(start.0.saturating_add(width)) - self.gc_until(start.0);
+        1.0 - gc as f64 / (width as u128 * self.caps as u128) as f64
+    }
+
+    /// Utilisation of the windows of `width` starting every `step`, from the start of the span
+    /// until the windows reach its end.
+    pub fn windows(&self, width: Duration, step: Duration) -> Vec<(Timestamp, f64)> {
+        let mut windows = Vec::new();
+        if step.is_zero() {
+            return windows;
+        }
+        let mut start = self.start;
+        while start + width <= self.end {
+            windows.push((start, self.window(start, width)));
+            start = start + step;
+        }
+        windows
+    }
+
+    /// The lowest utilisation of any window of `width`, `None` if the span is shorter than
+    /// `width`.
+    pub fn mmu(&self, width: Duration) -> Option<f64> {
+        if self.start + width > self.end {
+            return None;
+        }
+        let last = (self.end - width).0;
+        let width_ns = width.as_nanos() as u64;
+
+        // the utilisation only has its minimum where a window starts or ends at a change
+        self.times.iter()
+            .flat_map(|time| [*time, time.saturating_sub(width_ns)])
+            .chain([self.start.0, last])
+            .map(|time| time.clamp(self.start.0, last))
+            .map(|time| self.window(Timestamp(time), width))
+            .min_by(f64::total_cmp)
+    }
+
+    /// The MMU for each of `widths`, leaving out the widths longer than the span.
+    pub fn mmu_curve(&self, widths: &[Duration]) -> Vec<(Duration, f64)> {
+        widths.iter()
+            .filter_map(|width| self.mmu(*width).map(|mmu| (*width, mmu)))
+            .collect()
+    }
+}
+
+/// Window widths from 1ms to 1s, in steps of 1, 2 and 5.
+pub fn default_widths() -> Vec<Duration> {
+    [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000]
+        .into_iter()
+        .map(Duration::from_millis)
+        .collect()
+}
+
+/// Plot an MMU curve, with the GC overhead (one minus the MMU) alongside.
+pub fn mmu_svg(curve: &[(Duration, f64)]) -> String {
+    Chart {
+        title: "Minimum mutator utilisation",
+        x_label: "window",
+        y_label: "utilisation",
+        log_x: true,
+        y_range: Some((0.0, 1.0)),
+        x_format: |ms| format!("{ms}ms"),
+        y_format: |v| format!("{:.0}%", v * 100.0),
+        series: vec![
+            Series {
+                name: "MMU",
+                points: curve.iter().map(|(width, mmu)| (width.as_secs_f64() * 1000.0, *mmu)).collect(),
+            },
+            Series {
+                name: "max GC overhead",
+                points: curve.iter().map(|(width, mmu)| (width.as_secs_f64() * 1000.0, 1.0 - mmu)).collect(),
+            },
+        ],
+    }.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ThreadId;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    /// Two capabilities over 100ns, both in GC from 20 to 30, capability 1 also from 60 to 80.
+    fn utilisation() -> Utilisation {
+        let timeline = |intervals: &[(u64, u64, Activity)]| -> Vec<Interval> {
+            intervals.iter()
+                .map(|(start, end, activity)| Interval { start: Timestamp(*start), end: Timestamp(*end), activity: *activity })
+                .collect()
+        };
+        let running = Activity::Running(ThreadId(1));
+        Utilisation::new(&BTreeMap::from([
+            (CapNo(0), timeline(&[(0, 20, Activity::Idle), (20, 30, Activity::Gc), (30, 100, running)])),
+            (CapNo(1), timeline(&[
+                (0, 20, running),
+                (20, 30, Activity::Gc),
+                (30, 60, Activity::Idle),
+                (60, 80, Activity::Gc),
+                (80, 100, running),
+            ])),
+        ]))
+    }
+
+    #[test]
+    fn window() {
+        let utilisation = utilisation();
+        let nanos = Duration::from_nanos;
+        assert!(close(utilisation.window(Timestamp(0), nanos(100)), 0.8));
+        assert!(close(utilisation.window(Timestamp(20), nanos(10)), 0.0));
+        assert!(close(utilisation.window(Timestamp(30), nanos(30)), 1.0));
+
+        let windows = utilisation.windows(nanos(50), nanos(25));
+        assert_eq!(windows.iter().map(|(start, _)| start.0).collect::<Vec<_>>(), [0, 25, 50]);
+        for ((_, got), expected) in windows.iter().zip([0.8, 0.75, 0.8]) {
+            assert!(close(*got, expected), "{got} != {expected}");
+        }
+    }
+
+    #[test]
+    fn mmu() {
+        let utilisation = utilisation();
+        let cases = [(10, 0.0), (20, 0.5), (60, 1.0 - 40.0 / 120.0), (100, 0.8)];
+        for (width, expected) in cases {
+            let mmu = utilisation.mmu(Duration::from_nanos(width)).unwrap();
+            assert!(close(mmu, expected), "width {width}: {mmu} != {expected}");
+        }
+        assert_eq!(utilisation.mmu(Duration::from_nanos(101)), None);
+    }
+}
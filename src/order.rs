@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::parse::{decode, Context, EventlogParser, BLOCK_MARKER};
+use crate::types::{CapNo, Timestamp};
+
+/// An undecoded event, pass it to [`crate::parse::decode`] to decode it.
+#[derive(Debug, Clone)]
+pub struct RawEvent {
+    pub ctx: Context,
+    /// The event without its header
+    pub payload: Vec<u8>,
+}
+
+impl RawEvent {
+    /// Decode the event with `handle`, see [`decode`]. The event was decoded when it was parsed,
+    /// so this does not fail.
+    pub fn decode<Parser: EventlogParser>(&self, handle: &mut Parser) {
+        let _ = decode(handle, &self.ctx, &self.payload);
+    }
+}
+
+/// The events of one capability, or of no capability.
+struct Queue {
+    events: VecDeque<RawEvent>,
+    /// No later event on this queue will have an earlier time than this
+    bound: Timestamp,
+    /// The capability has been deleted, no more events will be added
+    done: bool,
+}
+
+impl Queue {
+    fn new(bound: Timestamp) -> Queue {
+        Queue {
+            events: VecDeque::new(),
+            bound,
+            done: false,
+        }
+    }
+}
+
+/// Puts the events of an eventlog in time order.
+///
+/// The events in an eventlog are written in blocks, one block per capability, and are only
+/// ordered within a block. This merges the blocks and passes the events to `sink` ordered by
+/// time.
+///
+/// An event is passed on once every capability has moved past its time: a capability has moved
+/// past the end time of its last complete block, and past the time of its last event in the
+/// current block. Only the events that can not be passed on yet are kept in memory. GHC writes a
+/// block for every capability, empty or not, each time it flushes the eventlog, so memory use
+/// depends on how often it flushes (see `--eventlog-flush-interval`) rather than on the size of
+/// the eventlog. A capability that writes no blocks holds back the events until the end.
+///
+/// Capabilities are known from CAP_CREATE and from their blocks. Events that are not tied to a
+/// capability are rare and do not hold back the others while none are waiting. An event that turns
+/// up earlier than an already passed on event is passed on straight away.
+///
+/// Call [`TimeOrder::finish`] after parsing to pass on the remaining events.
+pub struct TimeOrder<F: FnMut(RawEvent)> {
+    sink: F,
+    queues: BTreeMap<Option<CapNo>, Queue>,
+    /// Time of the last event passed on
+    released: Timestamp,
+    current_block_capno: Option<CapNo>,
+    /// End time of the current block
+    current_block_end: Option<Timestamp>,
+}
+
+impl<F: FnMut(RawEvent)> TimeOrder<F> {
+    pub fn new(sink: F) -> Self {
+        let mut queues = BTreeMap::new();
+        queues.insert(None, Queue::new(Timestamp(0)));
+        TimeOrder {
+            sink,
+            queues,
+            released: Timestamp(0),
+            current_block_capno: None,
+            current_block_end: None,
+        }
+    }
+
+    /// Pass on all remaining events.
+    pub fn finish(mut self) {
+        self.end_block();
+        self.release(Timestamp(u64::MAX));
+    }
+
+    fn queue(&mut self, capno: Option<CapNo>, time: Timestamp) -> &mut Queue {
+        self.queues.entry(capno).or_insert_with(|| Queue::new(time))
+    }
+
+    /// Move the queue of the current block past the end of the block.
+    fn end_block(&mut self) {
+        if let Some(end) = self.current_block_end.take() {
+            let queue = self.queue(self.current_block_capno, end);
+            queue.bound = queue.bound.max(end);
+        }
+    }
+
+    /// Pass on the events up to `time`, in time order.
+    fn release(&mut self, time: Timestamp) {
+        loop {
+            let next = self.queues.iter()
+                .filter_map(|(capno, queue)| queue.events.front().map(|ev| (ev.ctx.time, *capno)))
+                .min();
+            let Some((next_time, capno)) = next else {
+                break;
+            };
+            if next_time > time {
+                break;
+            }
+
+            let queue = self.queues.get_mut(&capno).unwrap();
+            let ev = queue.events.pop_front().unwrap();
+            self.released = ev.ctx.time;
+            (self.sink)(ev);
+        }
+
+        self.queues.retain(|capno, queue| capno.is_none() || !queue.done || !queue.events.is_empty());
+    }
+
+    /// The time up to which every queue has moved, the queue of events not tied to a capability
+    /// only counts while it has events.
+    fn watermark(&self) -> Timestamp {
+        self.queues.iter()
+            .filter(|(capno, queue)| !queue.done && (capno.is_some() || !queue.events.is_empty()))
+            .map(|(_, queue)| queue.bound)
+            .min()
+            .unwrap_or(Timestamp(u64::MAX))
+    }
+}
+
+impl<F: FnMut(RawEvent)> EventlogParser for TimeOrder<F> {
+    fn event_end(&mut self, ctx: &Context, payload: &[u8]) {
+        if ctx.id == BLOCK_MARKER {
+            return;
+        }
+
+        let ev = RawEvent {
+            ctx: *ctx,
+            payload: payload.to_vec(),
+        };
+
+        if ctx.time < self.released {
+            (self.sink)(ev);
+            return;
+        }
+
+        let queue = self.queue(ctx.capno, ctx.time);
+        queue.bound = queue.bound.max(ctx.time);
+        queue.events.push_back(ev);
+
+        let watermark = self.watermark();
+        self.release(watermark);
+    }
+
+    fn event_block_marker(&mut self, _ctx: &Context, _block_size: u32, _time_end: Timestamp, _capno: Option<CapNo>) {
+        self.end_block();
+
+        self.current_block_capno = _ctx.capno;
+        self.current_block_end = Some(_time_end);
+
+        let queue = self.queue(_ctx.capno, _ctx.time);
+        queue.bound = queue.bound.max(_ctx.time);
+    }
+
+    fn event_cap_create(&mut self, _ctx: &Context, _capno: CapNo) {
+        let queue = self.queue(Some(_capno), _ctx.time);
+        queue.done = false;
+    }
+
+    fn event_cap_delete(&mut self, _ctx: &Context, _capno: CapNo) {
+        if let Some(queue) = self.queues.get_mut(&Some(_capno)) {
+            queue.done = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::encode::tests::write;
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn orders_across_caps() {
+        let eventlog = write(|w| {
+            w.event(45, Timestamp(0), &[0, 0])?;
+            w.event(45, Timestamp(0), &[0, 1])?;
+            w.begin_block(Timestamp(10), Timestamp(30), Some(CapNo(0)))?;
+            w.event(0, Timestamp(10), &[0, 0, 0, 1])?;
+            w.event(0, Timestamp(30), &[0, 0, 0, 1])?;
+            w.end_block()?;
+            w.event(19, Timestamp(15), b"global")?;
+            w.begin_block(Timestamp(20), Timestamp(40), Some(CapNo(1)))?;
+            w.event(0, Timestamp(20), &[0, 0, 0, 2])?;
+            w.event(0, Timestamp(40), &[0, 0, 0, 2])?;
+            w.end_block()?;
+            w.event(19, Timestamp(35), b"global")?;
+            w.begin_block(Timestamp(50), Timestamp(50), Some(CapNo(0)))?;
+            w.event(0, Timestamp(50), &[0, 0, 0, 1])?;
+            w.begin_block(Timestamp(45), Timestamp(45), Some(CapNo(1)))?;
+            w.event(0, Timestamp(45), &[0, 0, 0, 2])?;
+            Ok(())
+        });
+
+        let mut events = Vec::new();
+        let mut order = TimeOrder::new(|ev: RawEvent| events.push((ev.ctx.time, ev.ctx.capno)));
+        parse_reader(&eventlog[..], &mut order).unwrap();
+        order.finish();
+
+        assert_eq!(events, [
+            (Timestamp(0), None),
+            (Timestamp(0), None),
+            (Timestamp(10), Some(CapNo(0))),
+            (Timestamp(15), None),
+            (Timestamp(20), Some(CapNo(1))),
+            (Timestamp(30), Some(CapNo(0))),
+            (Timestamp(35), None),
+            (Timestamp(40), Some(CapNo(1))),
+            (Timestamp(45), Some(CapNo(1))),
+            (Timestamp(50), Some(CapNo(0))),
+        ]);
+    }
+
+    #[test]
+    fn releases_before_finish() {
+        let eventlog = write(|w| {
+            w.event(45, Timestamp(0), &[0, 0])?;
+            w.event(45, Timestamp(0), &[0, 1])?;
+            w.begin_block(Timestamp(10), Timestamp(30), Some(CapNo(0)))?;
+            w.event(0, Timestamp(10), &[0, 0, 0, 1])?;
+            w.event(0, Timestamp(30), &[0, 0, 0, 1])?;
+            w.begin_block(Timestamp(20), Timestamp(40), Some(CapNo(1)))?;
+            w.event(0, Timestamp(20), &[0, 0, 0, 2])?;
+            w.event(0, Timestamp(40), &[0, 0, 0, 2])?;
+            w.begin_block(Timestamp(50), Timestamp(50), Some(CapNo(0)))?;
+            w.event(0, Timestamp(50), &[0, 0, 0, 1])?;
+            w.begin_block(Timestamp(60), Timestamp(60), Some(CapNo(1)))?;
+            w.event(0, Timestamp(60), &[0, 0, 0, 2])?;
+            Ok(())
+        });
+
+        let times = RefCell::new(Vec::new());
+        let mut order = TimeOrder::new(|ev: RawEvent| times.borrow_mut().push(ev.ctx.time.0));
+        parse_reader(&eventlog[..], &mut order).unwrap();
+        // capability 0 may still have events before 60
+        assert_eq!(*times.borrow(), [0, 0, 10, 20, 30, 40, 50]);
+        order.finish();
+        assert_eq!(*times.borrow(), [0, 0, 10, 20, 30, 40, 50, 60]);
+    }
+}
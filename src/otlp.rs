@@ -0,0 +1,249 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use crate::blocked::BlockReason;
+use crate::gc::GcPauses;
+use crate::json;
+use crate::markers::{MarkerSpans, Span};
+use crate::order::RawEvent;
+use crate::parse::{Context, EventlogParser};
+use crate::redact::fnv1a;
+use crate::threads::{ThreadState, Threads};
+use crate::types::{CapsetId, Timestamp};
+
+const SPAN_KIND_INTERNAL: u32 = 1;
+
+/// An OTLP attribute.
+fn attribute(key: &str, value: &str) -> String {
+    format!("{{\"key\":{},\"value\":{value}}}", json::string(key))
+}
+
+fn string_value(value: &str) -> String {
+    format!("{{\"stringValue\":{}}}", json::string(value))
+}
+
+/// OTLP JSON wants 64 bit integers as strings.
+fn int_value(value: u64) -> String {
+    format!("{{\"intValue\":\"{value}\"}}")
+}
+
+fn bool_value(value: bool) -> String {
+    format!("{{\"boolValue\":{value}}}")
+}
+
+/// An id of `n` bytes as hex, hashed from the fields of `key`. Every field is hashed after its
+/// length, so the id is the same for the same fields in every build of `ev`, and exporting an
+/// eventlog again gives the same ids.
+fn id(key: &[&[u8]], n: usize) -> String {
+    let mut id = String::new();
+    for i in 0..n.div_ceil(8) {
+        let mut bytes = vec![i as u8];
+        for field in key {
+            bytes.extend((field.len() as u64).to_be_bytes());
+            bytes.extend(*field);
+        }
+        id.push_str(&format!("{:016x}", fnv1a(&bytes)));
+    }
+    id.truncate(n * 2);
+    id
+}
+
+/// Builds OpenTelemetry spans, as OTLP JSON, from the user marker spans of an eventlog (see
+/// [`MarkerSpans`]).
+///
+/// Every marker span is the root span of a trace of its own. The GCs during the span are child
+/// spans, and so are the times the thread that began the span was blocked during it. The times
+/// are moved to the wall clock with WALL_CLOCK_TIME, if the eventlog has it.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]) with [`OtlpTrace::add`],
+/// then write the trace with [`OtlpTrace::write`].
+#[derive(Default)]
+pub struct OtlpTrace {
+    threads: Threads,
+    gc: GcPauses,
+    markers: MarkerSpans,
+    /// Nanoseconds since the Unix epoch at the start of the eventlog
+    epoch: Option<u64>,
+    program: Option<String>,
+    pid: Option<u32>,
+}
+
+impl OtlpTrace {
+    pub fn new() -> Self {
+        OtlpTrace::default()
+    }
+
+    pub fn add(&mut self, event: &RawEvent) {
+        event.decode(&mut self.threads);
+        event.decode(&mut self.gc);
+        event.decode(&mut self.markers);
+        event.decode(self);
+    }
+
+    /// Write the spans as an OTLP `ExportTraceServiceRequest` in JSON.
+    pub fn write<W: Write>(self, mut out: W) -> io::Result<W> {
+        let epoch = self.epoch.unwrap_or(0);
+        let nanos = |time: Timestamp| format!("\"{}\"", epoch.saturating_add(time.0));
+        let threads = self.threads.finish();
+        let gcs = self.gc.finish();
+        let spans = self.markers.finish();
+        let service = self.program.as_deref()
+            .map(|program| program.rsplit('/').next().unwrap_or(program))
+            .unwrap_or("ghc");
+
+        let mut out_spans = Vec::new();
+        let mut span_json = |trace_id: &str, span_id: &str, parent: Option<&str>, name: &str, start: Timestamp, end: Timestamp, attributes: Vec<String>| {
+            out_spans.push(format!(
+                "{{\"traceId\":\"{trace_id}\",\"spanId\":\"{span_id}\",\"parentSpanId\":\"{}\",\"name\":{},\"kind\":{SPAN_KIND_INTERNAL},\"startTimeUnixNano\":{},\"endTimeUnixNano\":{},\"attributes\":[{}]}}",
+                parent.unwrap_or(""), json::string(name), nanos(start), nanos(end), attributes.join(",")));
+        };
+
+        for (i, span) in spans.iter().enumerate() {
+            let Span { name, start, end, capno } = span;
+            let pid = self.pid.map_or(Vec::new(), |pid| pid.to_be_bytes().to_vec());
+            let trace_id = id(&[&pid, name.as_bytes(), &start.0.to_be_bytes(), &(i as u64).to_be_bytes()], 16);
+            let span_id = id(&[trace_id.as_bytes(), b"span"], 8);
+
+            // the thread that began the span
+            let thread = capno.and_then(|capno| {
+                threads.iter().find(|(_, thread)| thread.state_at(*start) == Some(ThreadState::Running(capno)))
+            });
+
+            let mut attributes = Vec::new();
+            if let Some(capno) = capno {
+                attributes.push(attribute("ghc.cap", &int_value(capno.0 as u64)));
+            }
+            if let Some((threadid, thread)) = thread {
+                attributes.push(attribute("ghc.thread", &int_value(threadid.0 as u64)));
+                if let Some(label) = &thread.label {
+                    attributes.push(attribute("ghc.thread.label", &string_value(label)));
+                }
+            }
+            span_json(&trace_id, &span_id, None, name, *start, *end, attributes);
+
+            for (j, gc) in gcs.iter().filter(|gc| gc.start < *end && gc.end > *start).enumerate() {
+                let mut attributes = Vec::new();
+                if let Some(parallel) = gc.parallel {
+                    attributes.push(attribute("ghc.gc.parallel", &bool_value(parallel)));
+                }
+                if let Some(stats) = &gc.stats {
+                    attributes.push(attribute("ghc.gc.generation", &int_value(stats.gen as u64)));
+                    attributes.push(attribute("ghc.gc.copied_bytes", &int_value(stats.copied)));
+                }
+                span_json(&trace_id, &id(&[trace_id.as_bytes(), b"gc", &(j as u64).to_be_bytes()], 8), Some(&span_id), "GC", gc.start, gc.end, attributes);
+            }
+
+            let Some((threadid, thread)) = thread else {
+                continue;
+            };
+            let blocked = thread.intervals.iter()
+                .filter(|interval| interval.start < *end && interval.end > *start);
+            for (j, interval) in blocked.enumerate() {
+                let ThreadState::Blocked(status) = interval.state else {
+                    continue;
+                };
+                let reason = BlockReason::of(status);
+                let mut attributes = vec![
+                    attribute("ghc.thread", &int_value(threadid.0 as u64)),
+                    attribute("ghc.block.status", &string_value(&status.to_string())),
+                ];
+                if let Some(blocker) = status.blocked_on() {
+                    attributes.push(attribute("ghc.block.blocked_on", &int_value(blocker.0 as u64)));
+                }
+                span_json(&trace_id, &id(&[trace_id.as_bytes(), b"blocked", &(j as u64).to_be_bytes()], 8), Some(&span_id), &format!("blocked on {reason}"),
+                    interval.start.max(*start), interval.end.min(*end), attributes);
+            }
+        }
+
+        let mut resource = vec![attribute("service.name", &string_value(service))];
+        if let Some(pid) = self.pid {
+            resource.push(attribute("process.pid", &int_value(pid as u64)));
+        }
+
+        writeln!(out, "{{\"resourceSpans\":[{{\"resource\":{{\"attributes\":[{}]}},\"scopeSpans\":[{{\"scope\":{{\"name\":\"ev\"}},\"spans\":[{}]}}]}}]}}",
+            resource.join(","), out_spans.join(","))?;
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+impl EventlogParser for OtlpTrace {
+    fn event_wall_clock_time(&mut self, _ctx: &Context, _capset: CapsetId, _sec: u64, _nsec: u32) {
+        // a time that does not fit is corrupt, the spans then keep the eventlog times
+        let Some(now) = _sec.checked_mul(1_000_000_000).and_then(|now| now.checked_add(_nsec as u64)) else {
+            return;
+        };
+        self.epoch.get_or_insert(now.saturating_sub(_ctx.time.0));
+    }
+
+    fn event_program_args(&mut self, _ctx: &Context, _capset: CapsetId, _args: Vec<u8>) {
+        let program = _args.split(|b| *b == 0).next().unwrap_or_default();
+        self.program.get_or_insert_with(|| String::from_utf8_lossy(program).into_owned());
+    }
+
+    fn event_osprocess_pid(&mut self, _ctx: &Context, _capset: CapsetId, _pid: u32) {
+        self.pid.get_or_insert(_pid);
+    }
+}
+
+/// Send OTLP JSON to a collector with OTLP/HTTP, `url` is like `http://localhost:4318`, the
+/// path defaults to `/v1/traces`. Only plain HTTP is supported.
+pub fn post(url: &str, body: &[u8]) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+    let rest = url.strip_prefix("http://")
+        .ok_or_else(|| invalid(format!("{url} is not an http:// url")))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/v1/traces"),
+    };
+    let addr = if host.contains(':') { host.to_string() } else { format!("{host}:80") };
+
+    let mut stream = TcpStream::connect(addr)?;
+    write!(stream, "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split(' ').nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("{url} answered {status}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn ids() {
+        let trace_id = id(&[b"ab", b"c"], 16);
+        assert_eq!(trace_id.len(), 32);
+        assert_eq!(trace_id, "2eb9c9a233b5485a60793f161c442f67");
+        assert_eq!(id(&[b"ab", b"c"], 8), trace_id[..16]);
+        // the fields are kept apart
+        assert_ne!(id(&[b"a", b"bc"], 16), trace_id);
+        assert_ne!(id(&[b"abc"], 16), trace_id);
+    }
+
+    #[test]
+    fn wall_clock() {
+        let epoch = |sec| {
+            let eventlog = write(|w| {
+                w.begin_block(Timestamp(5), Timestamp(5), None)?;
+                w.event(43, Timestamp(5), &Payload::new().u32(0).u64(sec).u32(7))
+            });
+            let mut trace = OtlpTrace::new();
+            parse_reader(&eventlog[..], &mut trace).unwrap();
+            trace.epoch
+        };
+        assert_eq!(epoch(2), Some(2_000_000_002));
+        assert_eq!(epoch(u64::MAX / 1_000_000_000 + 1), None);
+    }
+}
@@ -3,15 +3,235 @@ use std::io::Read;
 use std::path::Path;
 use std::collections::HashMap;
 
-#[derive(Debug, Clone)]
+use crate::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EventSize {
     Variable,
     Fixed(u16),
 }
 
+#[derive(Debug, Clone)]
 pub struct EventType {
     pub size: EventSize,
     pub descr: String,
+    /// Extra info from the header, kept around so the header can be written back unchanged.
+    pub extra: Vec<u8>,
+}
+
+/// Event type id of BLOCK_MARKER.
+pub const BLOCK_MARKER: u16 = 18;
+
+/// Event type id of USER_MARKER.
+pub const USER_MARKER: u16 = 58;
+
+/// Where and when an event happened, passed to every method of [`EventlogParser`].
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    /// The event type id
+    pub id: u16,
+    /// Time of the event
+    pub time: Timestamp,
+    /// The capability of the block the event is in, `None` for events that are not tied to a
+    /// capability
+    pub capno: Option<CapNo>,
+    /// Offset of the event from the start of the eventlog, in bytes
+    pub offset: u64,
+    /// Size of the payload of the event, without the event header
+    pub size: usize,
+    /// Whether the header has USER_MARKER, which tells the numberings of [`ThreadStopStatus`]
+    /// apart
+    pub user_marker: bool,
+}
+
+/// Why a thread stopped running, from STOP_THREAD.
+///
+/// The blocked statuses are the reason the thread blocked, GHC numbers them as its `why_blocked`
+/// plus 6. The `why_blocked` values above BlockedOnDoProc were renumbered, so statuses 14 to 19
+/// mean different things depending on the version of GHC. Like ghc-events, the numbering is told
+/// apart by whether the header has USER_MARKER, which was added in GHC 7.8, as the blocking
+/// thread of STOP_THREAD is older than the renumbering:
+///
+/// | status | without USER_MARKER         | with USER_MARKER            |
+/// |--------|-----------------------------|-----------------------------|
+/// | 14     | BlockedOnCCall              | -                           |
+/// | 15     | BlockedOnCCallNoUnblockExc  | -                           |
+/// | 16     | BlockedOnMsgThrowTo         | BlockedOnCCall              |
+/// | 17     | ThreadMigrating             | BlockedOnCCallInterruptible |
+/// | 18     | BlockedOnMsgGlobalise       | BlockedOnMsgThrowTo         |
+/// | 19     | -                           | ThreadMigrating             |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThreadStopStatus {
+    HeapOverflow,
+    StackOverflow,
+    ThreadYielding,
+    ThreadBlocked,
+    ThreadFinished,
+    ForeignCall,
+    BlockedOnMVar,
+    /// `owner` is the thread evaluating the black hole, if it is known
+    BlockedOnBlackHole { owner: Option<ThreadId> },
+    BlockedOnRead,
+    BlockedOnWrite,
+    BlockedOnDelay,
+    BlockedOnSTM,
+    BlockedOnDoProc,
+    BlockedOnCCall,
+    BlockedOnCCallInterruptible,
+    /// Only written by GHC before 7.8
+    BlockedOnCCallNoUnblockExc,
+    /// `target` is the thread the exception is thrown to, if it is known
+    BlockedOnMsgThrowTo { target: Option<ThreadId> },
+    ThreadMigrating,
+    /// Only written by GHC before 7.8
+    BlockedOnMsgGlobalise,
+    BlockedOnMVarRead,
+    /// A status this parser does not know about, or 0
+    Unknown(u16),
+}
+
+impl ThreadStopStatus {
+    /// Decode the status of a STOP_THREAD event. `block_threadid` is `None` for eventlogs that
+    /// do not have it, and 0 when there is no blocking thread. `user_marker` is whether the
+    /// header has USER_MARKER, see the table above.
+    pub fn decode(status: u16, block_threadid: Option<ThreadId>, user_marker: bool) -> ThreadStopStatus {
+        use ThreadStopStatus::*;
+
+        let blocker = block_threadid.filter(|threadid| threadid.0 != 0);
+
+        match (status, user_marker) {
+            (1, _) => HeapOverflow,
+            (2, _) => StackOverflow,
+            (3, _) => ThreadYielding,
+            (4, _) => ThreadBlocked,
+            (5, _) => ThreadFinished,
+            (6, _) => ForeignCall,
+            (7, _) => BlockedOnMVar,
+            (8, _) => BlockedOnBlackHole { owner: blocker },
+            (9, _) => BlockedOnRead,
+            (10, _) => BlockedOnWrite,
+            (11, _) => BlockedOnDelay,
+            (12, _) => BlockedOnSTM,
+            (13, _) => BlockedOnDoProc,
+            (14, false) => BlockedOnCCall,
+            (15, false) => BlockedOnCCallNoUnblockExc,
+            (16, false) => BlockedOnMsgThrowTo { target: blocker },
+            (17, false) => ThreadMigrating,
+            (18, false) => BlockedOnMsgGlobalise,
+            (16, true) => BlockedOnCCall,
+            (17, true) => BlockedOnCCallInterruptible,
+            (18, true) => BlockedOnMsgThrowTo { target: blocker },
+            (19, true) => ThreadMigrating,
+            (20, _) => BlockedOnMVarRead,
+            (status, _) => Unknown(status),
+        }
+    }
+
+    /// The thread blocking the stopped thread, if it is known.
+    pub fn blocked_on(&self) -> Option<ThreadId> {
+        match *self {
+            ThreadStopStatus::BlockedOnBlackHole { owner } => owner,
+            ThreadStopStatus::BlockedOnMsgThrowTo { target } => target,
+            _ => None,
+        }
+    }
+
+    /// Whether the thread stopped because it blocked.
+    pub fn is_blocked(&self) -> bool {
+        use ThreadStopStatus::*;
+
+        matches!(self,
+            ThreadBlocked
+            | BlockedOnMVar
+            | BlockedOnBlackHole { .. }
+            | BlockedOnRead
+            | BlockedOnWrite
+            | BlockedOnDelay
+            | BlockedOnSTM
+            | BlockedOnDoProc
+            | BlockedOnCCall
+            | BlockedOnCCallInterruptible
+            | BlockedOnCCallNoUnblockExc
+            | BlockedOnMsgThrowTo { .. }
+            | BlockedOnMsgGlobalise
+            | BlockedOnMVarRead)
+    }
+}
+
+/// Shown as the name of the status, without the blocking thread.
+impl std::fmt::Display for ThreadStopStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ThreadStopStatus::BlockedOnBlackHole { .. } => write!(f, "BlockedOnBlackHole"),
+            ThreadStopStatus::BlockedOnMsgThrowTo { .. } => write!(f, "BlockedOnMsgThrowTo"),
+            ThreadStopStatus::Unknown(status) => write!(f, "Unknown({status})"),
+            status => write!(f, "{status:?}"),
+        }
+    }
+}
+
+/// The spark counters of a capability since the program started, from SPARK_COUNTERS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SparkCounters {
+    /// Sparks added to the spark pool
+    pub created: u64,
+    /// Sparks not added because they were already evaluated
+    pub dud: u64,
+    /// Sparks not added because the spark pool was full
+    pub overflowed: u64,
+    /// Sparks that were run
+    pub converted: u64,
+    /// Sparks that were garbage collected before they were run
+    pub gcd: u64,
+    /// Sparks that were evaluated by something else before they were run
+    pub fizzled: u64,
+    /// Sparks still in the spark pool
+    pub remaining: u64,
+}
+
+impl SparkCounters {
+    /// Every spark that was sparked, whether it was added to the pool or not.
+    pub fn total(&self) -> u64 {
+        self.created + self.dud + self.overflowed
+    }
+
+    /// The change from `earlier` to these counters. The remaining sparks are the ones
+    /// remaining now, they are not a count since the start.
+    pub fn since(&self, earlier: &SparkCounters) -> SparkCounters {
+        SparkCounters {
+            created: self.created.saturating_sub(earlier.created),
+            dud: self.dud.saturating_sub(earlier.dud),
+            overflowed: self.overflowed.saturating_sub(earlier.overflowed),
+            converted: self.converted.saturating_sub(earlier.converted),
+            gcd: self.gcd.saturating_sub(earlier.gcd),
+            fizzled: self.fizzled.saturating_sub(earlier.fizzled),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl std::ops::Add for SparkCounters {
+    type Output = SparkCounters;
+
+    fn add(self, rhs: SparkCounters) -> SparkCounters {
+        SparkCounters {
+            created: self.created + rhs.created,
+            dud: self.dud + rhs.dud,
+            overflowed: self.overflowed + rhs.overflowed,
+            converted: self.converted + rhs.converted,
+            gcd: self.gcd + rhs.gcd,
+            fizzled: self.fizzled + rhs.fizzled,
+            remaining: self.remaining + rhs.remaining,
+        }
+    }
+}
+
+/// Shown like the SPARKS line of `+RTS -s`.
+impl std::fmt::Display for SparkCounters {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SPARKS: {} ({} converted, {} overflowed, {} dud, {} GC'd, {} fizzled)",
+            self.total(), self.converted, self.overflowed, self.dud, self.gcd, self.fizzled)
+    }
 }
 
 /// Implement the methods of this trait for the events you require.
@@ -20,78 +240,163 @@ pub struct EventType {
 /// <https://ghc.gitlab.haskell.org/ghc/doc/users_guide/eventlog-formats.html>
 ///
 pub trait EventlogParser {
+    /// Called once with the event types from the header, in the order they appear in the file
+    fn header(&mut self, _event_types: &[(u16, EventType)]) {}
+
     /// Called at before the start of every event
-    ///
-    /// The `size` argument is the size of the payload, the whole event is `size + 2 + 8` bytes
-    /// long (ie with the event header).
-    fn event_start(&mut self, _id: u16, _time: u64, _size: usize) {}
+    fn event_start(&mut self, _ctx: &Context) {}
+
+    /// Called after every event, with the raw payload of the event
+    fn event_end(&mut self, _ctx: &Context, _payload: &[u8]) {}
 
     /// Called for unknown events
-    fn event_unknown(&mut self, _tag: u16, _time: u64, _bytes: Vec<u8>) {}
+    fn event_unknown(&mut self, _ctx: &Context, _bytes: Vec<u8>) {}
 
-    /// This event marks the start of a block of events. This is used to set note the capaiblity
-    /// the following events are tied to.
+    /// This event marks the start of a block of events. The parser uses this to find the
+    /// capability of the following events, see [`Context::capno`].
     ///
     /// `block_size` is the size in bytes of the related events (including the event headers).
-    fn event_block_marker(&mut self, _time: u64, _block_size: u32, _time_end: u64, _capno: u16) {}
+    fn event_block_marker(&mut self, _ctx: &Context, _block_size: u32, _time_end: Timestamp, _capno: Option<CapNo>) {}
+
+    fn event_rts_identifier(&mut self, _ctx: &Context, _capset: CapsetId, _name: Vec<u8>) {}
 
-    fn event_rts_identifier(&mut self, _time: u64, _capset: u32, _name: Vec<u8>) {}
+    fn event_wall_clock_time(&mut self, _ctx: &Context, _capset: CapsetId, _sec: u64, _nsec: u32) {}
+    fn event_osprocess_pid(&mut self, _ctx: &Context, _capset: CapsetId, _pid: u32) {}
+    fn event_osprocess_ppid(&mut self, _ctx: &Context, _capset: CapsetId, _ppid: u32) {}
+    /// `args` are the arguments separated by NUL bytes
+    fn event_program_args(&mut self, _ctx: &Context, _capset: CapsetId, _args: Vec<u8>) {}
+    /// `env` are the environment variables separated by NUL bytes
+    fn event_program_env(&mut self, _ctx: &Context, _capset: CapsetId, _env: Vec<u8>) {}
 
-    fn event_wall_clock_time(&mut self, _time: u64, _capset: u32, _sec: u64, _nsec: u32) {}
-    fn event_osprocess_pid(&mut self, _time: u64, _capset: u32, _pid: u32) {}
-    fn event_osprocess_ppid(&mut self, _time: u64, _capset: u32, _ppid: u32) {}
-    fn event_program_args(&mut self, _time: u64, _args: Vec<u8>) {}
-    fn event_program_env(&mut self, _time: u64, _env: Vec<u8>) {}
+    fn event_spark_counters(&mut self, _ctx: &Context, _counters: SparkCounters) {}
 
-    fn event_spark_counters(&mut self, _time: u64, _counters: [u64; 7]) {}
+    fn event_thread_create(&mut self, _ctx: &Context, _threadid: ThreadId) {}
+    fn event_thread_run(&mut self, _ctx: &Context, _threadid: ThreadId) {}
+    fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, _status: ThreadStopStatus) {}
+    fn event_thread_label(&mut self, _ctx: &Context, _threadid: ThreadId, _label: Vec<u8>) {}
+    fn event_thread_runnable(&mut self, _ctx: &Context, _threadid: ThreadId) {}
+    fn event_thread_migrate(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {}
+    fn event_thread_wakeup(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {}
 
-    fn event_thread_create(&mut self, _time: u64, _threadid: u32) {}
-    fn event_thread_run(&mut self, _time: u64, _threadid: u32) {}
-    fn event_thread_stop(&mut self, _time: u64, _threadid: u32, _status: u16, _block_threadid: u32) {}
-    fn event_thread_label(&mut self, _time: u64, _threadid: u32, _label: Vec<u8>) {}
-    fn event_thread_runnable(&mut self, _time: u64, _threadid: u32) {}
-    fn event_thread_migrate(&mut self, _time: u64, _threadid: u32, _capno: u16) {}
-    fn event_thread_wakeup(&mut self, _time: u64, _threadid: u32, _capno: u16) {}
+    fn event_task_create(&mut self, _ctx: &Context, _taskid: TaskId, _capno: CapNo, _k_threadid: u64) {}
+    fn event_task_migrate(&mut self, _ctx: &Context, _taskid: TaskId, _from_capno: CapNo, _to_capno: CapNo) {}
+    fn event_task_delete(&mut self, _ctx: &Context, _taskid: TaskId) {}
 
-    fn event_task_create(&mut self, _time: u64, _taskid: u64, _capno: u16, _k_threadid: u64) {}
-    fn event_task_migrate(&mut self, _time: u64, _taskid: u64, _from_capno: u16, _to_capno: u16) {}
-    fn event_task_delete(&mut self, _time: u64, _taskid: u64) {}
+    fn event_request_seq_gc(&mut self, _ctx: &Context) {}
+    fn event_request_par_gc(&mut self, _ctx: &Context) {}
 
-    fn event_request_seq_gc(&mut self, _time: u64) {}
-    fn event_request_par_gc(&mut self, _time: u64) {}
+    fn event_gc_start(&mut self, _ctx: &Context) {}
+    fn event_gc_end(&mut self, _ctx: &Context) {}
+    fn event_gc_work(&mut self, _ctx: &Context) {}
+    fn event_gc_idle(&mut self, _ctx: &Context) {}
+    fn event_gc_done(&mut self, _ctx: &Context) {}
+    fn event_gc_global_sync(&mut self, _ctx: &Context) {}
 
-    fn event_gc_start(&mut self, _time: u64) {}
-    fn event_gc_end(&mut self, _time: u64) {}
-    fn event_gc_work(&mut self, _time: u64) {}
-    fn event_gc_idle(&mut self, _time: u64) {}
-    fn event_gc_done(&mut self, _time: u64) {}
-    fn event_gc_global_sync(&mut self, _time: u64) {}
+    #[allow(clippy::too_many_arguments)]
+    fn event_gc_stats_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _copied: u64, _slop: u64, _fragmentation: u64, _threads: u32, _max_copied: u64, _total_copied: u64, _balanced_copied: u64) {}
 
-    fn event_gc_stats_ghc(&mut self, _time: u64, _capset: u32, _gen: u16, _copied: u64, _slop: u64, _fragmentation: u64, _threads: u32, _max_copied: u64, _total_copied: u64, _balanced_copied: u64) {}
+    #[allow(clippy::too_many_arguments)]
+    fn event_heap_info_ghc(&mut self, _ctx: &Context, _capset: CapsetId, _gen: u16, _max_heap: u64, _alloc_size: u64, _mblock_size: u64, _block_size: u64) {}
 
-    fn event_heap_info_ghc(&mut self, _time: u64, _capset: u32, _gen: u16, _max_heap: u64, _alloc_size: u64, _mblock_size: u64, _block_size: u64) {}
+    fn event_heap_allocated(&mut self, _ctx: &Context, _capset: CapsetId, _allocated_bytes: u64) {}
+    fn event_heap_size(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {}
+    fn event_heap_live(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {}
+    fn event_blocks_size(&mut self, _ctx: &Context, _capset: CapsetId, _blocks: u64) {}
+    fn event_mem_return(&mut self, _ctx: &Context, _capset: CapsetId, _mblocks: u32, _retain: u32, _return_: u32) {}
 
-    fn event_heap_allocated(&mut self, _time: u64, _capset: u32, _allocated_bytes: u64) {}
-    fn event_heap_size(&mut self, _time: u64, _capset: u32, _size: u64) {}
-    fn event_heap_live(&mut self, _time: u64, _capset: u32, _size: u64) {}
-    fn event_blocks_size(&mut self, _time: u64, _capset: u32, _blocks: u64) {}
-    fn event_mem_return(&mut self, _time: u64, _capset: u32, _mblocks: u32, _retain: u32, _return_: u32) {}
+    fn event_user_msg(&mut self, _ctx: &Context, _bytes: Vec<u8>) {}
+    fn event_user_marker(&mut self, _ctx: &Context, _marker: Vec<u8>) {}
 
-    fn event_user_msg(&mut self, _time: u64, _bytes: Vec<u8>) {}
+    fn event_cap_create(&mut self, _ctx: &Context, _capno: CapNo) {}
+    fn event_cap_delete(&mut self, _ctx: &Context, _capno: CapNo) {}
+    fn event_cap_disable(&mut self, _ctx: &Context, _capno: CapNo) {}
+    fn event_cap_enable(&mut self, _ctx: &Context, _capno: CapNo) {}
 
-    fn event_cap_create(&mut self, _time: u64, _capno: u16) {}
-    fn event_cap_delete(&mut self, _time: u64, _capno: u16) {}
-    fn event_cap_disable(&mut self, _time: u64, _capno: u16) {}
+    fn event_capset_create(&mut self, _ctx: &Context, _capset: CapsetId, _type_: u16) {}
+    fn event_capset_delete(&mut self, _ctx: &Context, _capset: CapsetId) {}
+    fn event_capset_assign_cap(&mut self, _ctx: &Context, _capset: CapsetId, _capno: CapNo) {}
+    fn event_capset_remove_cap(&mut self, _ctx: &Context, _capset: CapsetId, _capno: CapNo) {}
 
-    fn event_capset_create(&mut self, _time: u64, _capset: u32, _type_: u16) {}
-    fn event_capset_delete(&mut self, _time: u64, _capset: u32) {}
-    fn event_capset_assign_cap(&mut self, _time: u64, _capset: u32, _capno: u16) {}
-    fn event_capset_remove_cap(&mut self, _time: u64, _capset: u32, _capno: u16) {}
+    /// The strings are the filters given with `-hm`, `-hd`, `-hy`, `-hc`, `-hC`, `-hr` and
+    /// `-hb`, empty when not given. `_breakdown` is the kind of heap profile, 1 for `-hc`, 2
+    /// `-hm`, 3 `-hd`, 4 `-hy`, 5 `-hr`, 6 `-hb`, 7 `-hT`, 8 `-hi` and 9 `-he`.
+    #[allow(clippy::too_many_arguments)]
+    fn event_heap_prof_begin(&mut self, _ctx: &Context, _profile: u8, _sampling_period: u64, _breakdown: u32, _module: Vec<u8>, _closure_descr: Vec<u8>, _type_descr: Vec<u8>, _cost_centre: Vec<u8>, _cost_centre_stack: Vec<u8>, _retainer: Vec<u8>, _biography: Vec<u8>) {}
+    fn event_heap_prof_cost_centre(&mut self, _ctx: &Context, _ccid: u32, _label: Vec<u8>, _module: Vec<u8>, _srcloc: Vec<u8>, _flags: u8) {}
+    fn event_heap_prof_sample_begin(&mut self, _ctx: &Context, _era: u64) {}
+    /// `_stack` is the cost centre stack, innermost cost centre first.
+    fn event_heap_prof_sample_cost_centre(&mut self, _ctx: &Context, _profile: u8, _residency: u64, _stack: Vec<u32>) {}
+    fn event_heap_prof_sample_string(&mut self, _ctx: &Context, _profile: u8, _residency: u64, _label: Vec<u8>) {}
+    fn event_heap_prof_sample_end(&mut self, _ctx: &Context, _era: u64) {}
+    /// `_time` is the time of the sample, the biographical profile is written after the census.
+    fn event_heap_bio_prof_sample_begin(&mut self, _ctx: &Context, _era: u64, _time: Timestamp) {}
+
+    /// `_tick_interval` is the time between samples, in nanoseconds.
+    fn event_prof_begin(&mut self, _ctx: &Context, _tick_interval: u64) {}
+    /// `_ticks` is the number of ticks so far, `_stack` is the cost centre stack that was
+    /// running, innermost cost centre first.
+    fn event_prof_sample_cost_centre(&mut self, _ctx: &Context, _capno: CapNo, _ticks: u64, _stack: Vec<u32>) {}
+}
+
+
+/// Keeps count of the bytes read.
+struct Counting<R> {
+    inner: R,
+    count: u64,
 }
 
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
 
 /// Parse an eventlog with the provided parser.
-pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, handle: &mut Parser) {
+///
+/// Fails when the file can not be read or is not an eventlog. An eventlog that ends without
+/// the end of its data, like the eventlog of a program that was killed, ends at its last
+/// complete event.
+pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, handle: &mut Parser) -> std::io::Result<()> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)?;
+    parse_reader(file, handle)
+}
+
+/// Reads a file that is still being written, like `tail -f`: at the end of the file it waits
+/// for more to be written instead of ending.
+///
+/// Parsing a followed eventlog ends at the end of its data, once the program has exited.
+pub struct Follow {
+    file: std::fs::File,
+}
+
+impl Follow {
+    pub fn open<FilePath: AsRef<Path>>(path: FilePath) -> std::io::Result<Follow> {
+        Ok(Follow { file: std::fs::File::open(path)? })
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let len = self.file.read(buf)?;
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// Read the event types of the header of an eventlog, up to the start of the events.
+pub fn read_header<R: Read>(mut reader: R) -> std::io::Result<Vec<(u16, EventType)>> {
     // ```
     // EventLog :
     //       EVENT_HEADER_BEGIN 4xWord8 -- 'hdre'
@@ -121,26 +426,14 @@ pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, hand
     //       ... event specific info ...
     // ```
 
-    let file = OpenOptions::new()
-        .read(true)
-        .open(path).unwrap();
-
-    let mut reader = std::io::BufReader::new(file);
-
-    let mut event_types = HashMap::new();
+    let mut header = Vec::new();
 
     macro_rules! check_constant {
         ($comp:expr, $err:expr) => {{
             let mut buf = [0u8; std::mem::size_of_val($comp)];
-            reader.read_exact(&mut buf[..]).unwrap();
-            assert!(&buf == $comp, $err);
-        }}
-    }
-
-    macro_rules! skip {
-        ($len:expr) => {{
-            if $len != 0 {
-                reader.seek_relative($len).unwrap();
+            reader.read_exact(&mut buf[..])?;
+            if &buf != $comp {
+                return Err(invalid(format!("not an eventlog, bad {}", $err)));
             }
         }}
     }
@@ -148,12 +441,12 @@ pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, hand
     macro_rules! bytes {
         ($len:literal) => {{
             let mut buf = [0u8; $len];
-            reader.read_exact(&mut buf[..]).unwrap();
+            reader.read_exact(&mut buf[..])?;
             buf
         }};
         ($len:expr) => {{
             let mut buf = vec![0u8; $len];
-            reader.read_exact(&mut buf[..]).unwrap();
+            reader.read_exact(&mut buf[..])?;
             buf
         }};
     }
@@ -161,7 +454,7 @@ pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, hand
     macro_rules! num {
         ($ty:ty) => {{
             let mut buf = [0u8; std::mem::size_of::<$ty>()];
-            reader.read_exact(&mut buf[..]).unwrap();
+            reader.read_exact(&mut buf[..])?;
             <$ty>::from_be_bytes(buf)
         }}
     }
@@ -178,8 +471,9 @@ pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, hand
             break;
         }
 
-        assert!(&etb == b"etb\0",
-            "event type begin");
+        if &etb != b"etb\0" {
+            return Err(invalid("not an eventlog, bad event type begin".to_string()));
+        }
 
         let id = num!(u16);
 
@@ -189,34 +483,67 @@ pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, hand
         } else if size >= 0 {
             EventSize::Fixed(size as u16)
         } else {
-            panic!("event size bad {}", size)
+            return Err(invalid(format!("bad size {size} of event type {id}")));
         };
 
         let descr_size = num!(u32);
         let descr = bytes!(descr_size as usize);
-        let descr = String::from_utf8(descr).unwrap();
+        let descr = String::from_utf8_lossy(&descr).into_owned();
 
-        // println!("{id} {size:?} {descr}");
+        let extra_size = num!(u32);
+        let extra = bytes!(extra_size as usize);
 
-        event_types.insert(id, EventType {
+        header.push((id, EventType {
             size,
             descr,
-        });
-
-        let extra_size = num!(u32);
-        skip!(extra_size as i64);
+            extra,
+        }));
 
         check_constant!(b"ete\0", "event type end");
     }
 
     check_constant!(b"hdre", "header end");
-
     check_constant!(b"datb", "data begin");
 
+    Ok(header)
+}
+
+/// Parse an eventlog from a reader, like a socket or stdin, with the provided parser, see
+/// [`parse`].
+pub fn parse_reader<R: Read, Parser: EventlogParser>(input: R, handle: &mut Parser) -> std::io::Result<()> {
+    let mut reader = Counting {
+        inner: std::io::BufReader::new(input),
+        count: 0,
+    };
+
+    let header = read_header(&mut reader)?;
+    handle.header(&header);
+    let event_types: HashMap<u16, EventType> = header.into_iter().collect();
+    let user_marker = event_types.contains_key(&USER_MARKER);
+
+    macro_rules! num {
+        ($ty:ty) => {{
+            let mut buf = [0u8; std::mem::size_of::<$ty>()];
+            reader.read_exact(&mut buf[..])?;
+            <$ty>::from_be_bytes(buf)
+        }}
+    }
+
+    // capability of the current block, and where it ends
+    let mut block_capno = None;
+    let mut block_end = 0;
 
     // parse all the events
     loop {
-        let id = num!(u16);
+        let offset = reader.count;
+        let mut id = [0u8; 2];
+        match reader.read_exact(&mut id) {
+            Ok(()) => {},
+            // the program did not get to end the eventlog
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof && reader.count == offset => break,
+            Err(err) => return Err(err),
+        }
+        let id = u16::from_be_bytes(id);
         if id == 0xffff {
             // we've reached the end of the event log data
             break;
@@ -224,7 +551,9 @@ pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, hand
 
         let time = num!(u64);
 
-        let et = event_types.get(&id).unwrap();
+        let Some(et) = event_types.get(&id) else {
+            return Err(invalid(format!("event type {id} at offset {offset} is not in the header")));
+        };
         let size = match et.size {
             EventSize::Variable => {
                 let size = num!(u16);
@@ -235,249 +564,503 @@ pub fn parse<FilePath: AsRef<Path>, Parser: EventlogParser>(path: FilePath, hand
             },
         };
 
-        handle.event_start(id, time, size);
+        let mut payload = vec![0u8; size];
+        reader.read_exact(&mut payload)?;
 
-        match id {
-            // CREATE_THREAD
-            0 => {
-                let threadid = num!(u32);
-                handle.event_thread_create(time, threadid);
-            },
-            // RUN_THREAD
-            1 => {
-                let threadid = num!(u32);
-                handle.event_thread_run(time, threadid);
-            },
-            // STOP_THREAD
-            2 => {
-                let threadid = num!(u32);
-                let status = num!(u16);
-                let block_threadid = num!(u32);
-                handle.event_thread_stop(time, threadid, status, block_threadid);
-            },
-            // THREAD_RUNNABLE
-            3 => {
-                let threadid = num!(u32);
-                handle.event_thread_runnable(time, threadid);
-            },
-            // MIGRATE_THREAD
-            4 => {
-                let threadid = num!(u32);
-                let capno = num!(u16);
-                handle.event_thread_migrate(time, threadid, capno);
-            },
-            // THREAD_WAKEUP
-            8 => {
-                let threadid = num!(u32);
-                let capno = num!(u16);
-                handle.event_thread_wakeup(time, threadid, capno);
-            },
-            // GC_START
-            9 => {
-                handle.event_gc_start(time);
-            }
-            // GC_END
-            10 => {
-                handle.event_gc_end(time);
-            }
-            // REQUEST_PAR_GC
-            11 => {
-                handle.event_request_seq_gc(time);
-            }
-            // REQUEST_PAR_GC
-            12 => {
-                handle.event_request_par_gc(time);
-            }
-            // BLOCK_MARKER
-            18 => {
-                let block_size = num!(u32);
-                let time_end = num!(u64);
-                let cap_no = num!(u16);
-                handle.event_block_marker(time, block_size, time_end, cap_no);
-            },
-            // USER_MSG
-            19 => {
-                let message = bytes!(size);
-                handle.event_user_msg(time, message);
-            },
-            // GC_IDLE
-            20 => {
-                handle.event_gc_idle(time);
-            },
-            // GC_WORK
-            21 => {
-                handle.event_gc_work(time);
-            },
-            // GC_DONE
-            22 => {
-                handle.event_gc_done(time);
-            },
-            // CAPSET_CREATE
-            25 => {
-                let capset = num!(u32);
-                let type_ = num!(u16);
-                handle.event_capset_create(time, capset, type_);
-            },
-            // CAPSET_DELETE
-            26 => {
-                let capset = num!(u32);
-                handle.event_capset_delete(time, capset);
-            },
-            // CAPSET_ASSIGN_CAP
-            27 => {
-                let capset = num!(u32);
-                let capno = num!(u16);
-                handle.event_capset_assign_cap(time, capset, capno);
-            },
-            // CAPSET_REMOVE_CAP
-            28 => {
-                let capset = num!(u32);
-                let capno = num!(u16);
-                handle.event_capset_remove_cap(time, capset, capno);
-            },
-            // RTS_IDENTIFIER
-            29 => {
-                let capset = num!(u32);
-                let bytes = bytes!(size - 4);
-                handle.event_rts_identifier(time, capset, bytes);
-            },
-            // PROGRAM_ARGS
-            30 => {
-                let args = bytes!(size);
-                handle.event_program_args(time, args);
-            },
-            // OSPROCESS_PID
-            32 => {
-                let capset = num!(u32);
-                let pid = num!(u32);
-                handle.event_osprocess_pid(time, capset, pid);
-            }
-            // OSPROCESS_PPID
-            33 => {
-                let capset = num!(u32);
-                let ppid = num!(u32);
-                handle.event_osprocess_ppid(time, capset, ppid);
+        if id == BLOCK_MARKER && size >= 14 {
+            let block_size = u32::from_be_bytes(payload[0..4].try_into().unwrap());
+            let capno = u16::from_be_bytes(payload[12..14].try_into().unwrap());
+            block_capno = if capno == !0 {
+                None
+            } else {
+                Some(CapNo(capno))
+            };
+            block_end = offset + block_size as u64;
+        }
+
+        let ctx = Context {
+            id,
+            time: Timestamp(time),
+            capno: if offset < block_end { block_capno } else { None },
+            offset,
+            size,
+            user_marker,
+        };
+
+        handle.event_start(&ctx);
+        decode(handle, &ctx, &payload)?;
+        handle.event_end(&ctx, &payload);
+    }
+
+    Ok(())
+}
+
+/// Decode the payload of a single event and call the matching method of the parser.
+///
+/// `payload` is the event without its header, ie without the event type id, timestamp and
+/// (for variable sized events) the size. Fails, without calling the parser, when the payload is
+/// too short for the event or a string in it is not terminated.
+pub fn decode<Parser: EventlogParser>(handle: &mut Parser, ctx: &Context, payload: &[u8]) -> std::io::Result<()> {
+    let mut reader = payload;
+    let malformed = |what: &str| invalid(format!("event {} at offset {} {what}", ctx.id, ctx.offset));
+
+    // the rest of the payload, always the last field of an event
+    macro_rules! rest {
+        () => {
+            reader.to_vec()
+        };
+    }
+
+    macro_rules! num {
+        ($ty:ty) => {{
+            let mut buf = [0u8; std::mem::size_of::<$ty>()];
+            reader.read_exact(&mut buf[..]).map_err(|_| malformed("is too short"))?;
+            <$ty>::from_be_bytes(buf)
+        }}
+    }
+
+    // a NUL terminated string
+    macro_rules! string {
+        () => {{
+            let Some(len) = reader.iter().position(|b| *b == 0) else {
+                return Err(malformed("has a string without its NUL"));
+            };
+            let string = reader[..len].to_vec();
+            // the last string of an event is not followed by anything
+            #[allow(unused_assignments)]
+            {
+                reader = &reader[len + 1..];
             }
-            // SPARK_COUNTERS
-            34 => {
-                let mut counters = [0; 7];
-                for i in 0..7 {
-                    counters[i] = num!(u64);
-                }
-                handle.event_spark_counters(time, counters);
+            string
+        }}
+    }
+
+    // a cost centre stack, its depth and then the cost centres
+    macro_rules! stack {
+        () => {{
+            let depth = num!(u8);
+            let mut stack = Vec::with_capacity(depth as usize);
+            for _ in 0..depth {
+                stack.push(num!(u32));
             }
-            // WALL_CLOCK_TIME
-            43 => {
-                let capset = num!(u32);
-                let sec = num!(u64);
-                let nsec = num!(u32);
-                handle.event_wall_clock_time(time, capset, sec, nsec);
-            },
-            // THREAD_LABEL
-            44 => {
-                let threadid = num!(u32);
-                let label = bytes!(size - 4);
-                handle.event_thread_label(time, threadid, label);
-            },
-            // CAP_CREATE
-            45 => {
-                let capno = num!(u16);
-                handle.event_cap_create(time, capno);
-            },
-            // CAP_DELETE
-            46 => {
-                let capno = num!(u16);
-                handle.event_cap_delete(time, capno);
-            },
-            // CAP_DELETE
-            47 => {
-                let capno = num!(u16);
-                handle.event_cap_disable(time, capno);
-            },
-            // HEAP_ALLOCATED
-            49 => {
-                let capset = num!(u32);
-                let allocated_bytes = num!(u64);
-                handle.event_heap_allocated(time, capset, allocated_bytes);
-            },
-            // HEAP_SIZE
-            50 => {
-                let capset = num!(u32);
-                let size = num!(u64);
-                handle.event_heap_size(time, capset, size);
-            },
-            // HEAP_LIVE
-            51 => {
-                let capset = num!(u32);
-                let size = num!(u64);
-                handle.event_heap_live(time, capset, size);
-            },
-            // HEAP_INFO_GHC
-            52 => {
-                let capset = num!(u32);
-                let gen = num!(u16);
-                let max_heap = num!(u64);
-                let alloc_size = num!(u64);
-                let mblock_size = num!(u64);
-                let block_size = num!(u64);
-                handle.event_heap_info_ghc(time, capset, gen, max_heap, alloc_size, mblock_size, block_size);
-            },
-            // GC_STATS_GHC
-            53 => {
-                let capset = num!(u32);
-                let gen = num!(u16);
-                let copied = num!(u64);
-                let slop = num!(u64);
-                let fragmentation = num!(u64);
-                let threads = num!(u32);
-                let max_copied = num!(u64);
-                let total_copied = num!(u64);
-                let balanced_copied = num!(u64);
-
-                handle.event_gc_stats_ghc(time, capset, gen, copied, slop, fragmentation, threads, max_copied, total_copied, balanced_copied);
-            },
-            // GC_GLOBAL_SYNC
-            54 => {
-                handle.event_gc_global_sync(time);
-            },
-            // TASK_CREATE
-            55 => {
-                let taskid = num!(u64);
-                let capno = num!(u16);
-                let k_threadid = num!(u64);
-                handle.event_task_create(time, taskid, capno, k_threadid);
-            },
-            // TASK_MIGRATE
-            56 => {
-                let taskid = num!(u64);
-                let from_capno = num!(u16);
-                let to_capno = num!(u16);
-                handle.event_task_migrate(time, taskid, from_capno, to_capno);
-            },
-            // TASK_DELETE
-            57 => {
-                let taskid = num!(u64);
-                handle.event_task_delete(time, taskid);
-            },
-            // MEM_RETURN
-            90 => {
-                let capset = num!(u32);
-                let mblocks = num!(u32);
-                let retain = num!(u32);
-                let return_ = num!(u32);
-                handle.event_mem_return(time, capset, mblocks, retain, return_);
-            },
-            // BLOCKS_SIZE
-            91 => {
-                let capset = num!(u32);
-                let blocks = num!(u64);
-                handle.event_blocks_size(time, capset, blocks);
-            },
-            _ => {
-                let bytes = bytes!(size);
-                handle.event_unknown(id, time, bytes);
-            },
+            stack
+        }}
+    }
+
+    match ctx.id {
+        // CREATE_THREAD
+        0 => {
+            let threadid = ThreadId(num!(u32));
+            handle.event_thread_create(ctx, threadid);
+        },
+        // RUN_THREAD
+        1 => {
+            let threadid = ThreadId(num!(u32));
+            handle.event_thread_run(ctx, threadid);
+        },
+        // STOP_THREAD
+        2 => {
+            let threadid = ThreadId(num!(u32));
+            let status = num!(u16);
+            // older eventlogs do not have the blocking thread
+            let block_threadid = if reader.len() >= 4 {
+                Some(ThreadId(num!(u32)))
+            } else {
+                None
+            };
+            let status = ThreadStopStatus::decode(status, block_threadid, ctx.user_marker);
+            handle.event_thread_stop(ctx, threadid, status);
+        },
+        // THREAD_RUNNABLE
+        3 => {
+            let threadid = ThreadId(num!(u32));
+            handle.event_thread_runnable(ctx, threadid);
+        },
+        // MIGRATE_THREAD
+        4 => {
+            let threadid = ThreadId(num!(u32));
+            let capno = CapNo(num!(u16));
+            handle.event_thread_migrate(ctx, threadid, capno);
+        },
+        // THREAD_WAKEUP
+        8 => {
+            let threadid = ThreadId(num!(u32));
+            let capno = CapNo(num!(u16));
+            handle.event_thread_wakeup(ctx, threadid, capno);
+        },
+        // GC_START
+        9 => {
+            handle.event_gc_start(ctx);
+        }
+        // GC_END
+        10 => {
+            handle.event_gc_end(ctx);
         }
+        // REQUEST_SEQ_GC
+        11 => {
+            handle.event_request_seq_gc(ctx);
+        }
+        // REQUEST_PAR_GC
+        12 => {
+            handle.event_request_par_gc(ctx);
+        }
+        // BLOCK_MARKER
+        18 => {
+            let block_size = num!(u32);
+            let time_end = Timestamp(num!(u64));
+            let cap_no = match num!(u16) {
+                0xffff => None,
+                capno => Some(CapNo(capno)),
+            };
+            handle.event_block_marker(ctx, block_size, time_end, cap_no);
+        },
+        // USER_MSG
+        19 => {
+            let message = rest!();
+            handle.event_user_msg(ctx, message);
+        },
+        // GC_IDLE
+        20 => {
+            handle.event_gc_idle(ctx);
+        },
+        // GC_WORK
+        21 => {
+            handle.event_gc_work(ctx);
+        },
+        // GC_DONE
+        22 => {
+            handle.event_gc_done(ctx);
+        },
+        // CAPSET_CREATE
+        25 => {
+            let capset = CapsetId(num!(u32));
+            let type_ = num!(u16);
+            handle.event_capset_create(ctx, capset, type_);
+        },
+        // CAPSET_DELETE
+        26 => {
+            let capset = CapsetId(num!(u32));
+            handle.event_capset_delete(ctx, capset);
+        },
+        // CAPSET_ASSIGN_CAP
+        27 => {
+            let capset = CapsetId(num!(u32));
+            let capno = CapNo(num!(u16));
+            handle.event_capset_assign_cap(ctx, capset, capno);
+        },
+        // CAPSET_REMOVE_CAP
+        28 => {
+            let capset = CapsetId(num!(u32));
+            let capno = CapNo(num!(u16));
+            handle.event_capset_remove_cap(ctx, capset, capno);
+        },
+        // RTS_IDENTIFIER
+        29 => {
+            let capset = CapsetId(num!(u32));
+            let bytes = rest!();
+            handle.event_rts_identifier(ctx, capset, bytes);
+        },
+        // PROGRAM_ARGS
+        30 => {
+            let capset = CapsetId(num!(u32));
+            let args = rest!();
+            handle.event_program_args(ctx, capset, args);
+        },
+        // PROGRAM_ENV
+        31 => {
+            let capset = CapsetId(num!(u32));
+            let env = rest!();
+            handle.event_program_env(ctx, capset, env);
+        },
+        // OSPROCESS_PID
+        32 => {
+            let capset = CapsetId(num!(u32));
+            let pid = num!(u32);
+            handle.event_osprocess_pid(ctx, capset, pid);
+        }
+        // OSPROCESS_PPID
+        33 => {
+            let capset = CapsetId(num!(u32));
+            let ppid = num!(u32);
+            handle.event_osprocess_ppid(ctx, capset, ppid);
+        }
+        // SPARK_COUNTERS
+        34 => {
+            let counters = SparkCounters {
+                created: num!(u64),
+                dud: num!(u64),
+                overflowed: num!(u64),
+                converted: num!(u64),
+                gcd: num!(u64),
+                fizzled: num!(u64),
+                remaining: num!(u64),
+            };
+            handle.event_spark_counters(ctx, counters);
+        }
+        // WALL_CLOCK_TIME
+        43 => {
+            let capset = CapsetId(num!(u32));
+            let sec = num!(u64);
+            let nsec = num!(u32);
+            handle.event_wall_clock_time(ctx, capset, sec, nsec);
+        },
+        // THREAD_LABEL
+        44 => {
+            let threadid = ThreadId(num!(u32));
+            let label = rest!();
+            handle.event_thread_label(ctx, threadid, label);
+        },
+        // CAP_CREATE
+        45 => {
+            let capno = CapNo(num!(u16));
+            handle.event_cap_create(ctx, capno);
+        },
+        // CAP_DELETE
+        46 => {
+            let capno = CapNo(num!(u16));
+            handle.event_cap_delete(ctx, capno);
+        },
+        // CAP_DISABLE
+        47 => {
+            let capno = CapNo(num!(u16));
+            handle.event_cap_disable(ctx, capno);
+        },
+        // CAP_ENABLE
+        48 => {
+            let capno = CapNo(num!(u16));
+            handle.event_cap_enable(ctx, capno);
+        },
+        // HEAP_ALLOCATED
+        49 => {
+            let capset = CapsetId(num!(u32));
+            let allocated_bytes = num!(u64);
+            handle.event_heap_allocated(ctx, capset, allocated_bytes);
+        },
+        // HEAP_SIZE
+        50 => {
+            let capset = CapsetId(num!(u32));
+            let size = num!(u64);
+            handle.event_heap_size(ctx, capset, size);
+        },
+        // HEAP_LIVE
+        51 => {
+            let capset = CapsetId(num!(u32));
+            let size = num!(u64);
+            handle.event_heap_live(ctx, capset, size);
+        },
+        // HEAP_INFO_GHC
+        52 => {
+            let capset = CapsetId(num!(u32));
+            let gen = num!(u16);
+            let max_heap = num!(u64);
+            let alloc_size = num!(u64);
+            let mblock_size = num!(u64);
+            let block_size = num!(u64);
+            handle.event_heap_info_ghc(ctx, capset, gen, max_heap, alloc_size, mblock_size, block_size);
+        },
+        // GC_STATS_GHC
+        53 => {
+            let capset = CapsetId(num!(u32));
+            let gen = num!(u16);
+            let copied = num!(u64);
+            let slop = num!(u64);
+            let fragmentation = num!(u64);
+            let threads = num!(u32);
+            let max_copied = num!(u64);
+            let total_copied = num!(u64);
+            let balanced_copied = num!(u64);
+
+            handle.event_gc_stats_ghc(ctx, capset, gen, copied, slop, fragmentation, threads, max_copied, total_copied, balanced_copied);
+        },
+        // GC_GLOBAL_SYNC
+        54 => {
+            handle.event_gc_global_sync(ctx);
+        },
+        // TASK_CREATE
+        55 => {
+            let taskid = TaskId(num!(u64));
+            let capno = CapNo(num!(u16));
+            let k_threadid = num!(u64);
+            handle.event_task_create(ctx, taskid, capno, k_threadid);
+        },
+        // TASK_MIGRATE
+        56 => {
+            let taskid = TaskId(num!(u64));
+            let from_capno = CapNo(num!(u16));
+            let to_capno = CapNo(num!(u16));
+            handle.event_task_migrate(ctx, taskid, from_capno, to_capno);
+        },
+        // TASK_DELETE
+        57 => {
+            let taskid = TaskId(num!(u64));
+            handle.event_task_delete(ctx, taskid);
+        },
+        // USER_MARKER
+        58 => {
+            let marker = rest!();
+            handle.event_user_marker(ctx, marker);
+        },
+        // MEM_RETURN
+        90 => {
+            let capset = CapsetId(num!(u32));
+            let mblocks = num!(u32);
+            let retain = num!(u32);
+            let return_ = num!(u32);
+            handle.event_mem_return(ctx, capset, mblocks, retain, return_);
+        },
+        // BLOCKS_SIZE
+        91 => {
+            let capset = CapsetId(num!(u32));
+            let blocks = num!(u64);
+            handle.event_blocks_size(ctx, capset, blocks);
+        },
+        // HEAP_PROF_BEGIN
+        160 => {
+            let profile = num!(u8);
+            let sampling_period = num!(u64);
+            let breakdown = num!(u32);
+            let module = string!();
+            let closure_descr = string!();
+            let type_descr = string!();
+            let cost_centre = string!();
+            let cost_centre_stack = string!();
+            let retainer = string!();
+            let biography = string!();
+            handle.event_heap_prof_begin(ctx, profile, sampling_period, breakdown, module, closure_descr, type_descr, cost_centre, cost_centre_stack, retainer, biography);
+        },
+        // HEAP_PROF_COST_CENTRE
+        161 => {
+            let ccid = num!(u32);
+            let label = string!();
+            let module = string!();
+            let srcloc = string!();
+            let flags = num!(u8);
+            handle.event_heap_prof_cost_centre(ctx, ccid, label, module, srcloc, flags);
+        },
+        // HEAP_PROF_SAMPLE_BEGIN
+        162 => {
+            let era = num!(u64);
+            handle.event_heap_prof_sample_begin(ctx, era);
+        },
+        // HEAP_PROF_SAMPLE_COST_CENTRE
+        163 => {
+            let profile = num!(u8);
+            let residency = num!(u64);
+            let stack = stack!();
+            handle.event_heap_prof_sample_cost_centre(ctx, profile, residency, stack);
+        },
+        // HEAP_PROF_SAMPLE_STRING
+        164 => {
+            let profile = num!(u8);
+            let residency = num!(u64);
+            let label = string!();
+            handle.event_heap_prof_sample_string(ctx, profile, residency, label);
+        },
+        // HEAP_PROF_SAMPLE_END
+        165 => {
+            let era = num!(u64);
+            handle.event_heap_prof_sample_end(ctx, era);
+        },
+        // HEAP_BIO_PROF_SAMPLE_BEGIN
+        166 => {
+            let era = num!(u64);
+            let time = num!(u64);
+            handle.event_heap_bio_prof_sample_begin(ctx, era, Timestamp(time));
+        },
+        // PROF_SAMPLE_COST_CENTRE
+        167 => {
+            let capno = CapNo(num!(u32) as u16);
+            let ticks = num!(u64);
+            let stack = stack!();
+            handle.event_prof_sample_cost_centre(ctx, capno, ticks, stack);
+        },
+        // PROF_BEGIN
+        168 => {
+            let tick_interval = num!(u64);
+            handle.event_prof_begin(ctx, tick_interval);
+        },
+        _ => {
+            let bytes = rest!();
+            handle.event_unknown(ctx, bytes);
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{event_types, write};
+    use crate::encode::EventlogWriter;
+
+    #[test]
+    fn stop_status_numberings() {
+        use ThreadStopStatus::*;
+
+        let blocker = Some(ThreadId(3));
+        let cases = [
+            (8, false, BlockedOnBlackHole { owner: blocker }),
+            (8, true, BlockedOnBlackHole { owner: blocker }),
+            (14, false, BlockedOnCCall),
+            (14, true, Unknown(14)),
+            (15, false, BlockedOnCCallNoUnblockExc),
+            (15, true, Unknown(15)),
+            (16, false, BlockedOnMsgThrowTo { target: blocker }),
+            (16, true, BlockedOnCCall),
+            (17, false, ThreadMigrating),
+            (17, true, BlockedOnCCallInterruptible),
+            (18, false, BlockedOnMsgGlobalise),
+            (18, true, BlockedOnMsgThrowTo { target: blocker }),
+            (19, false, Unknown(19)),
+            (19, true, ThreadMigrating),
+            (20, false, BlockedOnMVarRead),
+            (20, true, BlockedOnMVarRead),
+        ];
+        for (status, user_marker, expected) in cases {
+            assert_eq!(ThreadStopStatus::decode(status, blocker, user_marker), expected,
+                "status {status} with user_marker {user_marker}");
+        }
+
+        // 0 is no blocking thread
+        assert_eq!(ThreadStopStatus::decode(8, Some(ThreadId(0)), true), BlockedOnBlackHole { owner: None });
+        assert_eq!(ThreadStopStatus::decode(18, None, true), BlockedOnMsgThrowTo { target: None });
+    }
+
+    #[derive(Default)]
+    struct Stops(Vec<ThreadStopStatus>);
+
+    impl EventlogParser for Stops {
+        fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, status: ThreadStopStatus) {
+            self.0.push(status);
+        }
+    }
+
+    #[test]
+    fn numbering_from_header() {
+        let stop = [0, 0, 0, 1, 0, 18, 0, 0, 0, 2];
+
+        let without = write(|w| w.event(2, Timestamp(0), &stop));
+        let mut stops = Stops::default();
+        parse_reader(&without[..], &mut stops).unwrap();
+        assert_eq!(stops.0, [ThreadStopStatus::BlockedOnMsgGlobalise]);
+
+        let mut event_types = event_types();
+        event_types.push((USER_MARKER, EventType { size: EventSize::Variable, descr: "user marker".into(), extra: Vec::new() }));
+        let mut writer = EventlogWriter::new(Vec::new());
+        writer.header(&event_types).unwrap();
+        writer.event(2, Timestamp(0), &stop).unwrap();
+        let with = writer.finish().unwrap();
+        let mut stops = Stops::default();
+        parse_reader(&with[..], &mut stops).unwrap();
+        assert_eq!(stops.0, [ThreadStopStatus::BlockedOnMsgThrowTo { target: Some(ThreadId(2)) }]);
+    }
 
+    #[test]
+    fn malformed_payload() {
+        // HEAP_PROF_COST_CENTRE without its strings
+        let eventlog = write(|w| w.event(161, Timestamp(0), &[0, 0, 0, 1]));
+        let err = parse_reader(&eventlog[..], &mut Stops::default()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
     }
 }
@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::parse::{Context, EventlogParser, ThreadStopStatus};
+use crate::protobuf::Message;
+use crate::types::{CapNo, CapsetId, TaskId, ThreadId, Timestamp};
+
+// field numbers from perfetto/trace/trace.proto and the messages it includes
+const TRACE_PACKET: u32 = 1;
+
+const PACKET_TIMESTAMP: u32 = 8;
+const PACKET_SEQUENCE_ID: u32 = 10;
+const PACKET_TRACK_EVENT: u32 = 11;
+const PACKET_INTERNED_DATA: u32 = 12;
+const PACKET_SEQUENCE_FLAGS: u32 = 13;
+const PACKET_TRACK_DESCRIPTOR: u32 = 60;
+
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+const SEQ_NEEDS_INCREMENTAL_STATE: u64 = 2;
+
+const TRACK_UUID: u32 = 1;
+const TRACK_NAME: u32 = 2;
+const TRACK_PROCESS: u32 = 3;
+const TRACK_THREAD: u32 = 4;
+const TRACK_PARENT_UUID: u32 = 5;
+const TRACK_COUNTER: u32 = 8;
+
+const PROCESS_PID: u32 = 1;
+const PROCESS_NAME: u32 = 6;
+const THREAD_PID: u32 = 1;
+const THREAD_TID: u32 = 2;
+const THREAD_NAME: u32 = 5;
+const COUNTER_UNIT: u32 = 3;
+const UNIT_SIZE_BYTES: u64 = 3;
+
+const EVENT_TYPE: u32 = 9;
+const EVENT_NAME_IID: u32 = 10;
+const EVENT_TRACK_UUID: u32 = 11;
+const EVENT_NAME: u32 = 23;
+const EVENT_COUNTER_VALUE: u32 = 30;
+
+const TYPE_SLICE_BEGIN: u64 = 1;
+const TYPE_SLICE_END: u64 = 2;
+const TYPE_INSTANT: u64 = 3;
+const TYPE_COUNTER: u64 = 4;
+
+const INTERNED_EVENT_NAMES: u32 = 2;
+const INTERNED_IID: u32 = 1;
+const INTERNED_NAME: u32 = 2;
+
+const SEQUENCE_ID: u64 = 1;
+const PROCESS_UUID: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Track {
+    Cap(CapNo),
+    Task(TaskId),
+    Counter(CapsetId, &'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slice {
+    Thread,
+    Gc,
+}
+
+/// Writes a trace in Perfetto's protobuf format, for <https://ui.perfetto.dev>.
+///
+/// The trace is written while parsing, only the names and tracks seen so far are kept in
+/// memory. Every capability is a track with slices for the threads it runs and for GC, every OS
+/// task of the RTS is a thread track, user markers are instant events and the heap numbers are
+/// counter tracks. Thread labels and the other event names are interned, so each is written
+/// once.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]), then call
+/// [`PerfettoTrace::finish`].
+pub struct PerfettoTrace<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+    started: bool,
+    tracks: HashMap<Track, u64>,
+    next_uuid: u64,
+    names: HashMap<String, u64>,
+    /// Open slices of each capability, innermost last
+    slices: HashMap<CapNo, Vec<Slice>>,
+    labels: HashMap<ThreadId, String>,
+    program: Option<String>,
+    pid: Option<u32>,
+    /// OS thread ids of the tasks
+    task_tids: HashMap<TaskId, u64>,
+}
+
+impl<W: Write> PerfettoTrace<W> {
+    pub fn new(out: W) -> Self {
+        PerfettoTrace {
+            out,
+            error: None,
+            started: false,
+            tracks: HashMap::new(),
+            next_uuid: PROCESS_UUID + 1,
+            names: HashMap::new(),
+            slices: HashMap::new(),
+            labels: HashMap::new(),
+            program: None,
+            pid: None,
+            task_tids: HashMap::new(),
+        }
+    }
+
+    /// Finish writing the trace, returning the first error encountered.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn check(&mut self, res: io::Result<()>) {
+        if let Err(err) = res {
+            self.error.get_or_insert(err);
+        }
+    }
+
+    fn packet(&mut self, packet: &Message) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut trace = Message::new();
+        trace.message(TRACE_PACKET, packet);
+        let res = self.out.write_all(trace.as_bytes());
+        self.check(res);
+    }
+
+    /// Write the descriptor of the process track, again when more is known about the process.
+    fn process(&mut self) {
+        let mut process = Message::new();
+        process.uint(PROCESS_PID, self.pid.unwrap_or(0) as u64);
+        if let Some(program) = &self.program {
+            process.string(PROCESS_NAME, program);
+        }
+        let mut descriptor = Message::new();
+        descriptor.uint(TRACK_UUID, PROCESS_UUID).message(TRACK_PROCESS, &process);
+        let mut packet = Message::new();
+        packet.message(PACKET_TRACK_DESCRIPTOR, &descriptor);
+        self.packet(&packet);
+    }
+
+    fn update_process(&mut self) {
+        if self.started {
+            self.process();
+        } else {
+            self.start();
+        }
+    }
+
+    fn start(&mut self) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+
+        let mut packet = Message::new();
+        packet.uint(PACKET_SEQUENCE_ID, SEQUENCE_ID)
+            .uint(PACKET_SEQUENCE_FLAGS, SEQ_INCREMENTAL_STATE_CLEARED);
+        self.packet(&packet);
+        self.process();
+    }
+
+    /// The uuid of a track, writing its descriptor the first time.
+    fn track(&mut self, track: Track) -> u64 {
+        if let Some(uuid) = self.tracks.get(&track) {
+            return *uuid;
+        }
+        self.start();
+
+        let uuid = self.next_uuid;
+        self.next_uuid += 1;
+        self.tracks.insert(track, uuid);
+
+        let mut descriptor = Message::new();
+        descriptor.uint(TRACK_UUID, uuid).uint(TRACK_PARENT_UUID, PROCESS_UUID);
+        match track {
+            Track::Cap(capno) => {
+                descriptor.string(TRACK_NAME, &format!("cap {}", capno.0));
+            },
+            Track::Task(taskid) => {
+                let mut thread = Message::new();
+                thread.uint(THREAD_PID, self.pid.unwrap_or(0) as u64)
+                    .uint(THREAD_TID, self.task_tids.get(&taskid).copied().unwrap_or(taskid.0))
+                    .string(THREAD_NAME, &format!("task {:#x}", taskid.0));
+                descriptor.message(TRACK_THREAD, &thread);
+            },
+            Track::Counter(capset, name) => {
+                let mut counter = Message::new();
+                counter.uint(COUNTER_UNIT, UNIT_SIZE_BYTES);
+                descriptor.string(TRACK_NAME, &format!("{name} {capset}"))
+                    .message(TRACK_COUNTER, &counter);
+            },
+        }
+
+        let mut packet = Message::new();
+        packet.message(PACKET_TRACK_DESCRIPTOR, &descriptor);
+        self.packet(&packet);
+        uuid
+    }
+
+    /// Write a track event, interning its name.
+    fn event(&mut self, time: Timestamp, track: Track, type_: u64, name: Option<&str>, value: Option<i64>) {
+        let uuid = self.track(track);
+
+        let mut event = Message::new();
+        event.uint(EVENT_TYPE, type_).uint(EVENT_TRACK_UUID, uuid);
+
+        let mut packet = Message::new();
+        packet.uint(PACKET_TIMESTAMP, time.0)
+            .uint(PACKET_SEQUENCE_ID, SEQUENCE_ID)
+            .uint(PACKET_SEQUENCE_FLAGS, SEQ_NEEDS_INCREMENTAL_STATE);
+
+        if let Some(name) = name {
+            let iid = match self.names.get(name) {
+                Some(iid) => *iid,
+                None => {
+                    let iid = self.names.len() as u64 + 1;
+                    self.names.insert(name.to_string(), iid);
+
+                    let mut entry = Message::new();
+                    entry.uint(INTERNED_IID, iid).string(INTERNED_NAME, name);
+                    let mut interned = Message::new();
+                    interned.message(INTERNED_EVENT_NAMES, &entry);
+                    packet.message(PACKET_INTERNED_DATA, &interned);
+                    iid
+                },
+            };
+            event.uint(EVENT_NAME_IID, iid);
+        }
+        if let Some(value) = value {
+            event.int(EVENT_COUNTER_VALUE, value);
+        }
+
+        packet.message(PACKET_TRACK_EVENT, &event);
+        self.packet(&packet);
+    }
+
+    fn begin(&mut self, _ctx: &Context, slice: Slice, name: &str) {
+        let Some(capno) = _ctx.capno else {
+            return;
+        };
+        self.event(_ctx.time, Track::Cap(capno), TYPE_SLICE_BEGIN, Some(name), None);
+        self.slices.entry(capno).or_default().push(slice);
+    }
+
+    /// End `slice`, and the slices inside it that were not ended.
+    fn end(&mut self, _ctx: &Context, slice: Slice) {
+        let Some(capno) = _ctx.capno else {
+            return;
+        };
+        let open = self.slices.entry(capno).or_default();
+        let Some(depth) = open.iter().rposition(|open| *open == slice) else {
+            return;
+        };
+        let count = open.len() - depth;
+        open.truncate(depth);
+        for _ in 0..count {
+            self.event(_ctx.time, Track::Cap(capno), TYPE_SLICE_END, None, None);
+        }
+    }
+
+    fn counter(&mut self, _ctx: &Context, capset: CapsetId, name: &'static str, value: u64) {
+        self.event(_ctx.time, Track::Counter(capset, name), TYPE_COUNTER, None, Some(value as i64));
+    }
+}
+
+impl<W: Write> EventlogParser for PerfettoTrace<W> {
+    fn event_program_args(&mut self, _ctx: &Context, _capset: CapsetId, _args: Vec<u8>) {
+        let program = _args.split(|b| *b == 0).next().unwrap_or_default();
+        self.program.get_or_insert_with(|| String::from_utf8_lossy(program).into_owned());
+        self.update_process();
+    }
+
+    fn event_osprocess_pid(&mut self, _ctx: &Context, _capset: CapsetId, _pid: u32) {
+        self.pid.get_or_insert(_pid);
+        self.update_process();
+    }
+
+    fn event_thread_label(&mut self, _ctx: &Context, _threadid: ThreadId, _label: Vec<u8>) {
+        self.labels.insert(_threadid, String::from_utf8_lossy(&_label).into_owned());
+    }
+
+    fn event_thread_run(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        let name = match self.labels.get(&_threadid) {
+            Some(label) => label.clone(),
+            None => format!("thread {}", _threadid.0),
+        };
+        self.begin(_ctx, Slice::Thread, &name);
+    }
+    fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, _status: ThreadStopStatus) {
+        self.end(_ctx, Slice::Thread);
+    }
+
+    fn event_gc_start(&mut self, _ctx: &Context) {
+        self.begin(_ctx, Slice::Gc, "GC");
+    }
+    fn event_gc_end(&mut self, _ctx: &Context) {
+        self.end(_ctx, Slice::Gc);
+    }
+
+    fn event_user_marker(&mut self, _ctx: &Context, _marker: Vec<u8>) {
+        let Some(capno) = _ctx.capno else {
+            return;
+        };
+        let uuid = self.track(Track::Cap(capno));
+
+        // markers are often unique, they are not interned to keep memory use flat
+        let mut event = Message::new();
+        event.uint(EVENT_TYPE, TYPE_INSTANT)
+            .uint(EVENT_TRACK_UUID, uuid)
+            .string(EVENT_NAME, &String::from_utf8_lossy(&_marker));
+        let mut packet = Message::new();
+        packet.uint(PACKET_TIMESTAMP, _ctx.time.0)
+            .uint(PACKET_SEQUENCE_ID, SEQUENCE_ID)
+            .message(PACKET_TRACK_EVENT, &event);
+        self.packet(&packet);
+    }
+
+    fn event_task_create(&mut self, _ctx: &Context, _taskid: TaskId, _capno: CapNo, _k_threadid: u64) {
+        self.task_tids.insert(_taskid, _k_threadid);
+        self.event(_ctx.time, Track::Task(_taskid), TYPE_INSTANT, Some(&format!("create on cap {}", _capno.0)), None);
+    }
+    fn event_task_migrate(&mut self, _ctx: &Context, _taskid: TaskId, _from_capno: CapNo, _to_capno: CapNo) {
+        self.event(_ctx.time, Track::Task(_taskid), TYPE_INSTANT, Some(&format!("migrate to cap {}", _to_capno.0)), None);
+    }
+    fn event_task_delete(&mut self, _ctx: &Context, _taskid: TaskId) {
+        self.event(_ctx.time, Track::Task(_taskid), TYPE_INSTANT, Some("delete"), None);
+    }
+
+    fn event_heap_size(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        self.counter(_ctx, _capset, "heap size", _size);
+    }
+    fn event_heap_live(&mut self, _ctx: &Context, _capset: CapsetId, _size: u64) {
+        self.counter(_ctx, _capset, "heap live", _size);
+    }
+    fn event_blocks_size(&mut self, _ctx: &Context, _capset: CapsetId, _blocks: u64) {
+        self.counter(_ctx, _capset, "blocks size", _blocks);
+    }
+    fn event_heap_allocated(&mut self, _ctx: &Context, _capset: CapsetId, _allocated_bytes: u64) {
+        self.counter(_ctx, _capset, "allocated", _allocated_bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+    use crate::protobuf::tests::{describe, fields};
+
+    #[test]
+    fn trace() {
+        let eventlog = write(|w| {
+            w.event(30, Timestamp(0), &[&Payload::new().u32(0)[..], b"prog\0-N2\0"].concat())?;
+            w.event(32, Timestamp(0), &Payload::new().u32(0).u32(42))?;
+            w.begin_block(Timestamp(100), Timestamp(200), Some(CapNo(0)))?;
+            w.event(1, Timestamp(100), &Payload::new().u32(1))?;
+            w.event(2, Timestamp(120), &Payload::new().u32(1).u16(3).u32(0))?;
+            w.event(9, Timestamp(150), &[])?;
+            w.event(10, Timestamp(180), &[])?;
+            w.end_block()?;
+            w.event(50, Timestamp(180), &Payload::new().u32(0).u64(1 << 20))?;
+            w.begin_block(Timestamp(200), Timestamp(200), Some(CapNo(0)))?;
+            w.event(1, Timestamp(200), &Payload::new().u32(1))?;
+            Ok(())
+        });
+
+        let mut trace = PerfettoTrace::new(Vec::new());
+        parse_reader(&eventlog[..], &mut trace).unwrap();
+        let trace = trace.finish().unwrap();
+
+        // track descriptors, their process, thread and counter, track events and interned data
+        let messages = ["60", "60.3", "60.4", "60.8", "11", "12", "12.2"];
+        let packets: Vec<String> = fields(&trace).into_iter()
+            .map(|(field, packet)| {
+                assert_eq!(field, TRACE_PACKET);
+                describe(packet.bytes(), &messages)
+            })
+            .collect();
+        assert_eq!(packets, [
+            // the sequence starts, then the process is described and described again with its pid
+            "{10:1 13:1}",
+            r#"{60:{1:1 3:{1:0 6:"prog"}}}"#,
+            r#"{60:{1:1 3:{1:42 6:"prog"}}}"#,
+            // the track of capability 0 and the first thread slice, with its name interned
+            r#"{60:{1:2 5:1 2:"cap 0"}}"#,
+            r#"{8:100 10:1 13:2 12:{2:{1:1 2:"thread 1"}} 11:{9:1 11:2 10:1}}"#,
+            "{8:120 10:1 13:2 11:{9:2 11:2}}",
+            r#"{8:150 10:1 13:2 12:{2:{1:2 2:"GC"}} 11:{9:1 11:2 10:2}}"#,
+            "{8:180 10:1 13:2 11:{9:2 11:2}}",
+            // the heap size counter track
+            r#"{60:{1:3 5:1 2:"heap size cs:0" 8:{3:3}}}"#,
+            "{8:180 10:1 13:2 11:{9:4 11:3 30:1048576}}",
+            // the name of the thread is interned once
+            "{8:200 10:1 13:2 11:{9:1 11:2 10:1}}",
+        ]);
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::prof::Profiles;
+use crate::protobuf::Message;
+
+// field numbers from pprof's profile.proto
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const PROFILE_PERIOD_TYPE: u32 = 11;
+const PROFILE_PERIOD: u32 = 12;
+
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+
+const LOCATION_ID: u32 = 1;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const LINE_LINE: u32 = 2;
+
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+const FUNCTION_FILENAME: u32 = 4;
+const FUNCTION_START_LINE: u32 = 5;
+
+/// Builds a pprof `Profile`, every cost centre is a `Function` with a `Location` of the same id.
+struct Builder<'a> {
+    profiles: &'a Profiles,
+    profile: Message,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u64>,
+    locations: HashMap<u32, u64>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(profiles: &'a Profiles) -> Self {
+        let mut builder = Builder {
+            profiles,
+            profile: Message::new(),
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            locations: HashMap::new(),
+        };
+        // the string table starts with the empty string
+        builder.string("");
+        builder
+    }
+
+    fn string(&mut self, s: &str) -> u64 {
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as u64;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+
+    fn value_type(&mut self, type_: &str, unit: &str) -> Message {
+        let mut value_type = Message::new();
+        value_type
+            .uint(VALUE_TYPE_TYPE, self.string(type_))
+            .uint(VALUE_TYPE_UNIT, self.string(unit));
+        value_type
+    }
+
+    fn sample_type(&mut self, type_: &str, unit: &str) {
+        let value_type = self.value_type(type_, unit);
+        self.profile.message(PROFILE_SAMPLE_TYPE, &value_type);
+    }
+
+    fn location(&mut self, ccid: u32) -> u64 {
+        if let Some(id) = self.locations.get(&ccid) {
+            return *id;
+        }
+        let id = self.locations.len() as u64 + 1;
+        self.locations.insert(ccid, id);
+
+        let name = self.profiles.frame_name(ccid);
+        let cc = self.profiles.cost_centres.get(&ccid);
+        let (file, line) = match cc.and_then(|cc| cc.file_line()) {
+            Some((file, line)) => (file.to_string(), line),
+            None => (cc.map(|cc| cc.srcloc.clone()).unwrap_or_default(), 0),
+        };
+
+        let mut function = Message::new();
+        function
+            .uint(FUNCTION_ID, id)
+            .uint(FUNCTION_NAME, self.string(&name))
+            .uint(FUNCTION_SYSTEM_NAME, self.string(&name))
+            .uint(FUNCTION_FILENAME, self.string(&file))
+            .uint(FUNCTION_START_LINE, line);
+        self.profile.message(PROFILE_FUNCTION, &function);
+
+        let mut source_line = Message::new();
+        source_line.uint(LINE_FUNCTION_ID, id).uint(LINE_LINE, line);
+        let mut location = Message::new();
+        location.uint(LOCATION_ID, id).message(LOCATION_LINE, &source_line);
+        self.profile.message(PROFILE_LOCATION, &location);
+
+        id
+    }
+
+    /// A sample of a cost centre stack, innermost cost centre first like pprof wants.
+    fn sample(&mut self, stack: &[u32], values: &[u64]) {
+        let locations: Vec<u64> = stack.iter().map(|ccid| self.location(*ccid)).collect();
+        let mut sample = Message::new();
+        sample
+            .packed(SAMPLE_LOCATION_ID, &locations)
+            .packed(SAMPLE_VALUE, values);
+        self.profile.message(PROFILE_SAMPLE, &sample);
+    }
+
+    fn finish(mut self) -> Message {
+        for s in &self.strings {
+            self.profile.string(PROFILE_STRING_TABLE, s);
+        }
+        self.profile
+    }
+}
+
+/// Write the time profile as an uncompressed pprof CPU profile, with the samples and, when the
+/// tick interval is known, the CPU time of every cost centre stack.
+pub fn write_pprof_cpu<W: Write>(profiles: &Profiles, mut out: W) -> io::Result<W> {
+    let mut builder = Builder::new(profiles);
+    builder.sample_type("samples", "count");
+    if let Some(interval) = profiles.tick_interval {
+        builder.sample_type("cpu", "nanoseconds");
+        let period_type = builder.value_type("cpu", "nanoseconds");
+        builder.profile
+            .message(PROFILE_PERIOD_TYPE, &period_type)
+            .uint(PROFILE_PERIOD, interval);
+    }
+
+    let mut stacks: Vec<(&Vec<u32>, &u64)> = profiles.cpu.iter().collect();
+    stacks.sort();
+    for (stack, count) in stacks {
+        match profiles.tick_interval {
+            Some(interval) => builder.sample(stack, &[*count, count * interval]),
+            None => builder.sample(stack, &[*count]),
+        }
+    }
+
+    out.write_all(builder.finish().as_bytes())?;
+    out.flush()?;
+    Ok(out)
+}
+
+/// Write the peak census of the cost centre heap profile as an uncompressed pprof heap profile
+/// of `inuse_space`.
+pub fn write_pprof_heap<W: Write>(profiles: &Profiles, mut out: W) -> io::Result<W> {
+    let mut builder = Builder::new(profiles);
+    builder.sample_type("inuse_space", "bytes");
+    let period_type = builder.value_type("space", "bytes");
+    builder.profile.message(PROFILE_PERIOD_TYPE, &period_type);
+
+    if let Some(census) = profiles.peak_census() {
+        for (stack, residency) in &census.samples {
+            builder.sample(stack, &[*residency]);
+        }
+    }
+
+    out.write_all(builder.finish().as_bytes())?;
+    out.flush()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prof::tests::profiles;
+    use crate::protobuf::tests::{fields, varints, Value};
+
+    /// The parts of a decoded `Profile` the tests look at, with the strings and locations
+    /// resolved.
+    #[derive(Debug, Default, PartialEq)]
+    struct Decoded {
+        sample_types: Vec<(String, String)>,
+        period_type: Option<(String, String)>,
+        period: u64,
+        /// The name, file and start line of every function
+        functions: Vec<(String, String, u64)>,
+        /// The function names of every sample, innermost first, and its values
+        samples: Vec<(Vec<String>, Vec<u64>)>,
+    }
+
+    fn uint(fields: &[(u32, Value)], field: u32) -> u64 {
+        fields.iter()
+            .find_map(|(f, value)| match value {
+                Value::Varint(n) if *f == field => Some(*n),
+                _ => None,
+            })
+            .unwrap_or(0)
+    }
+
+    fn decode(profile: &[u8]) -> Decoded {
+        let profile = fields(profile);
+        let strings: Vec<String> = profile.iter()
+            .filter(|(field, _)| *field == PROFILE_STRING_TABLE)
+            .map(|(_, value)| String::from_utf8(value.bytes().to_vec()).unwrap())
+            .collect();
+        assert_eq!(strings[0], "");
+        let string = |id: u64| strings[id as usize].clone();
+        let value_type = |value: &Value| {
+            let value_type = fields(value.bytes());
+            (string(uint(&value_type, VALUE_TYPE_TYPE)), string(uint(&value_type, VALUE_TYPE_UNIT)))
+        };
+
+        let mut decoded = Decoded::default();
+        let mut function_names = HashMap::new();
+        let mut location_functions = HashMap::new();
+        for (field, value) in &profile {
+            match *field {
+                PROFILE_SAMPLE_TYPE => decoded.sample_types.push(value_type(value)),
+                PROFILE_PERIOD_TYPE => decoded.period_type = Some(value_type(value)),
+                PROFILE_PERIOD => decoded.period = uint(&profile, PROFILE_PERIOD),
+                PROFILE_FUNCTION => {
+                    let function = fields(value.bytes());
+                    let name = string(uint(&function, FUNCTION_NAME));
+                    assert_eq!(string(uint(&function, FUNCTION_SYSTEM_NAME)), name);
+                    function_names.insert(uint(&function, FUNCTION_ID), name.clone());
+                    decoded.functions.push((name, string(uint(&function, FUNCTION_FILENAME)), uint(&function, FUNCTION_START_LINE)));
+                },
+                PROFILE_LOCATION => {
+                    let location = fields(value.bytes());
+                    let (_, line) = location.iter().find(|(field, _)| *field == LOCATION_LINE).unwrap();
+                    let line = fields(line.bytes());
+                    location_functions.insert(uint(&location, LOCATION_ID), uint(&line, LINE_FUNCTION_ID));
+                },
+                _ => {},
+            }
+        }
+        for (field, value) in &profile {
+            if *field != PROFILE_SAMPLE {
+                continue;
+            }
+            let sample = fields(value.bytes());
+            let packed = |field| sample.iter()
+                .find(|(f, _)| *f == field)
+                .map_or(Vec::new(), |(_, value)| varints(value.bytes()));
+            let names = packed(SAMPLE_LOCATION_ID).iter()
+                .map(|location| function_names[&location_functions[location]].clone())
+                .collect();
+            decoded.samples.push((names, packed(SAMPLE_VALUE)));
+        }
+        decoded
+    }
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn pair(a: &str, b: &str) -> (String, String) {
+        (a.to_string(), b.to_string())
+    }
+
+    fn functions() -> Vec<(String, String, u64)> {
+        vec![
+            ("Main.go".to_string(), "Main.hs".to_string(), 12),
+            ("MAIN.MAIN".to_string(), "<built-in>".to_string(), 0),
+            ("Data.List.sum".to_string(), "libraries/base/Data/List.hs".to_string(), 5),
+        ]
+    }
+
+    #[test]
+    fn cpu() {
+        let profile = write_pprof_cpu(&profiles(), Vec::new()).unwrap();
+        assert_eq!(decode(&profile), Decoded {
+            sample_types: vec![pair("samples", "count"), pair("cpu", "nanoseconds")],
+            period_type: Some(pair("cpu", "nanoseconds")),
+            period: 1_000_000,
+            functions: functions(),
+            samples: vec![
+                (strings(&["Main.go", "MAIN.MAIN"]), vec![2, 2_000_000]),
+                (strings(&["Data.List.sum", "Main.go", "MAIN.MAIN"]), vec![1, 1_000_000]),
+            ],
+        });
+    }
+
+    #[test]
+    fn heap() {
+        let profile = write_pprof_heap(&profiles(), Vec::new()).unwrap();
+        assert_eq!(decode(&profile), Decoded {
+            sample_types: vec![pair("inuse_space", "bytes")],
+            period_type: Some(pair("space", "bytes")),
+            period: 0,
+            functions: functions(),
+            samples: vec![
+                (strings(&["Main.go", "MAIN.MAIN"]), vec![100]),
+                (strings(&["Data.List.sum", "Main.go", "MAIN.MAIN"]), vec![50]),
+            ],
+        });
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::json;
+use crate::parse::{Context, EventlogParser};
+use crate::types::{CapNo, Timestamp};
+
+/// A cost centre, from HEAP_PROF_COST_CENTRE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostCentre {
+    pub label: String,
+    pub module: String,
+    /// Where the cost centre is in the source, eg `Main.hs:12:1-30`
+    pub srcloc: String,
+    pub is_caf: bool,
+}
+
+impl CostCentre {
+    /// The cost centre qualified by its module, eg `Main.go`.
+    pub fn name(&self) -> String {
+        format!("{}.{}", self.module, self.label)
+    }
+
+    /// The file and line of the source location, if it has them.
+    pub fn file_line(&self) -> Option<(&str, u64)> {
+        let mut parts = self.srcloc.rsplitn(3, ':');
+        let _columns = parts.next()?;
+        let line = parts.next()?.parse().ok()?;
+        let file = parts.next()?;
+        Some((file, line))
+    }
+}
+
+/// One heap census of a cost centre heap profile.
+#[derive(Debug, Clone, Default)]
+pub struct Census {
+    pub time: Timestamp,
+    /// Residency in bytes of each cost centre stack, innermost cost centre first
+    pub samples: Vec<(Vec<u32>, u64)>,
+}
+
+impl Census {
+    pub fn residency(&self) -> u64 {
+        self.samples.iter().map(|(_, residency)| residency).sum()
+    }
+}
+
+/// The cost centre profiles of a profiled program run with `-l`, see [`ProfSamples`].
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    pub cost_centres: HashMap<u32, CostCentre>,
+    /// Nanoseconds between time profile samples, from PROF_BEGIN
+    pub tick_interval: Option<u64>,
+    /// Number of time profile samples of each cost centre stack, innermost cost centre first
+    pub cpu: HashMap<Vec<u32>, u64>,
+    /// Number of time profile samples taken on each capability
+    pub cpu_caps: HashMap<CapNo, u64>,
+    /// Censuses of a `-hc` heap profile
+    pub censuses: Vec<Census>,
+}
+
+impl Profiles {
+    /// The census with the most residency.
+    pub fn peak_census(&self) -> Option<&Census> {
+        self.censuses.iter().max_by_key(|census| census.residency())
+    }
+
+    /// The time profile samples with their weight, in nanoseconds when the tick interval is
+    /// known, in samples otherwise, heaviest first.
+    pub fn cpu_stacks(&self) -> Vec<(&[u32], u64)> {
+        let weight = self.tick_interval.unwrap_or(1);
+        let mut stacks: Vec<(&[u32], u64)> = self.cpu.iter()
+            .map(|(stack, count)| (stack.as_slice(), count * weight))
+            .collect();
+        stacks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        stacks
+    }
+
+    /// The name of a cost centre, its id if it is not known.
+    pub fn frame_name(&self, ccid: u32) -> String {
+        match self.cost_centres.get(&ccid) {
+            Some(cc) => cc.name(),
+            None => format!("<cost centre {ccid}>"),
+        }
+    }
+}
+
+/// Collects the cost centres, time profile samples and cost centre heap profile of a profiled
+/// program.
+///
+/// Call [`ProfSamples::finish`] after parsing to get the [`Profiles`].
+#[derive(Default)]
+pub struct ProfSamples {
+    profiles: Profiles,
+    /// A heap census has begun and has not ended
+    open: bool,
+}
+
+impl ProfSamples {
+    pub fn new() -> Self {
+        ProfSamples::default()
+    }
+
+    pub fn finish(self) -> Profiles {
+        self.profiles
+    }
+}
+
+impl EventlogParser for ProfSamples {
+    fn event_heap_prof_cost_centre(&mut self, _ctx: &Context, _ccid: u32, _label: Vec<u8>, _module: Vec<u8>, _srcloc: Vec<u8>, _flags: u8) {
+        self.profiles.cost_centres.insert(_ccid, CostCentre {
+            label: String::from_utf8_lossy(&_label).into_owned(),
+            module: String::from_utf8_lossy(&_module).into_owned(),
+            srcloc: String::from_utf8_lossy(&_srcloc).into_owned(),
+            is_caf: _flags & 1 != 0,
+        });
+    }
+
+    fn event_prof_begin(&mut self, _ctx: &Context, _tick_interval: u64) {
+        self.profiles.tick_interval = Some(_tick_interval);
+    }
+
+    fn event_prof_sample_cost_centre(&mut self, _ctx: &Context, _capno: CapNo, _ticks: u64, _stack: Vec<u32>) {
+        *self.profiles.cpu.entry(_stack).or_default() += 1;
+        *self.profiles.cpu_caps.entry(_capno).or_default() += 1;
+    }
+
+    fn event_heap_prof_sample_begin(&mut self, _ctx: &Context, _era: u64) {
+        self.profiles.censuses.push(Census { time: _ctx.time, samples: Vec::new() });
+        self.open = true;
+    }
+    fn event_heap_prof_sample_end(&mut self, _ctx: &Context, _era: u64) {
+        self.open = false;
+    }
+
+    fn event_heap_prof_sample_cost_centre(&mut self, _ctx: &Context, _profile: u8, _residency: u64, _stack: Vec<u32>) {
+        if !self.open {
+            self.profiles.censuses.push(Census { time: _ctx.time, samples: Vec::new() });
+            self.open = true;
+        }
+        self.profiles.censuses.last_mut().unwrap().samples.push((_stack, _residency));
+    }
+}
+
+/// Write stacks in the folded format of `flamegraph.pl`, one `outer;...;inner weight` line per
+/// stack. The stacks are innermost cost centre first.
+pub fn write_folded<'a, W: Write, I: IntoIterator<Item = (&'a [u32], u64)>>(profiles: &Profiles, stacks: I, mut out: W) -> io::Result<W> {
+    for (stack, weight) in stacks {
+        let frames: Vec<String> = stack.iter().rev()
+            .map(|ccid| profiles.frame_name(*ccid).replace([';', ' '], "_"))
+            .collect();
+        writeln!(out, "{} {weight}", frames.join(";"))?;
+    }
+    out.flush()?;
+    Ok(out)
+}
+
+/// Write the time profile, and the peak heap census, in speedscope's JSON format, for
+/// <https://www.speedscope.app>.
+pub fn write_speedscope<W: Write>(profiles: &Profiles, name: &str, mut out: W) -> io::Result<W> {
+    // frames in the order of their cost centre ids
+    let mut ccids: Vec<u32> = profiles.cost_centres.keys().copied().collect();
+    let stacks = profiles.cpu.keys().chain(profiles.censuses.iter().flat_map(|census| census.samples.iter().map(|(stack, _)| stack)));
+    ccids.extend(stacks.flatten().filter(|ccid| !profiles.cost_centres.contains_key(ccid)));
+    ccids.sort();
+    ccids.dedup();
+    let frame: HashMap<u32, usize> = ccids.iter().enumerate().map(|(i, ccid)| (*ccid, i)).collect();
+
+    let frames: Vec<String> = ccids.iter()
+        .map(|ccid| {
+            let cc = profiles.cost_centres.get(ccid);
+            match cc.and_then(|cc| cc.file_line()) {
+                Some((file, line)) => format!("{{\"name\":{},\"file\":{},\"line\":{line}}}",
+                    json::string(&profiles.frame_name(*ccid)), json::string(file)),
+                None => format!("{{\"name\":{}}}", json::string(&profiles.frame_name(*ccid))),
+            }
+        })
+        .collect();
+
+    let profile = |name: &str, unit: &str, stacks: &[(&[u32], u64)]| -> String {
+        let samples: Vec<String> = stacks.iter()
+            .map(|(stack, _)| {
+                let frames: Vec<String> = stack.iter().rev().map(|ccid| frame[ccid].to_string()).collect();
+                format!("[{}]", frames.join(","))
+            })
+            .collect();
+        let weights: Vec<String> = stacks.iter().map(|(_, weight)| weight.to_string()).collect();
+        let total: u64 = stacks.iter().map(|(_, weight)| weight).sum();
+        format!("{{\"type\":\"sampled\",\"name\":{},\"unit\":\"{unit}\",\"startValue\":0,\"endValue\":{total},\"samples\":[{}],\"weights\":[{}]}}",
+            json::string(name), samples.join(","), weights.join(","))
+    };
+
+    let mut profile_list = Vec::new();
+    if !profiles.cpu.is_empty() {
+        let unit = if profiles.tick_interval.is_some() { "nanoseconds" } else { "none" };
+        profile_list.push(profile("cpu", unit, &profiles.cpu_stacks()));
+    }
+    if let Some(census) = profiles.peak_census() {
+        let stacks: Vec<(&[u32], u64)> = census.samples.iter()
+            .map(|(stack, residency)| (stack.as_slice(), *residency))
+            .collect();
+        profile_list.push(profile(&format!("heap at {}", census.time), "bytes", &stacks));
+    }
+
+    writeln!(out, "{{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\"exporter\":\"ev\",\"name\":{},\"activeProfileIndex\":0,\"shared\":{{\"frames\":[{}]}},\"profiles\":[{}]}}",
+        json::string(name), frames.join(","), profile_list.join(","))?;
+    out.flush()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    fn stack(payload: Payload, stack: &[u32]) -> Payload {
+        stack.iter().fold(payload.u8(stack.len() as u8), |payload, ccid| payload.u32(*ccid))
+    }
+
+    /// A profile of `go` in MAIN calling `sum`: two 1ms ticks in `go` and one in `sum`, and two
+    /// heap censuses.
+    pub(crate) fn profiles() -> Profiles {
+        let second = 1_000_000_000;
+        let eventlog = write(|w| {
+            w.event(161, Timestamp(0), &Payload::new().u32(1).string("MAIN").string("MAIN").string("<built-in>").u8(0))?;
+            w.event(161, Timestamp(0), &Payload::new().u32(2).string("go").string("Main").string("Main.hs:12:1-30").u8(0))?;
+            w.event(161, Timestamp(0), &Payload::new().u32(3).string("sum").string("Data.List").string("libraries/base/Data/List.hs:5:3").u8(0))?;
+            w.event(168, Timestamp(0), &Payload::new().u64(1_000_000))?;
+            for (capno, cost_centres) in [(0, &[2, 1][..]), (1, &[3, 2, 1]), (0, &[2, 1])] {
+                w.event(167, Timestamp(10), &stack(Payload::new().u32(capno).u64(1), cost_centres))?;
+            }
+            w.event(162, Timestamp(second), &Payload::new().u64(1))?;
+            w.event(163, Timestamp(second), &stack(Payload::new().u8(1).u64(100), &[2, 1]))?;
+            w.event(163, Timestamp(second), &stack(Payload::new().u8(1).u64(50), &[3, 2, 1]))?;
+            w.event(165, Timestamp(second), &Payload::new().u64(1))?;
+            w.event(162, Timestamp(2 * second), &Payload::new().u64(2))?;
+            w.event(163, Timestamp(2 * second), &stack(Payload::new().u8(1).u64(10), &[2, 1]))?;
+            w.event(165, Timestamp(2 * second), &Payload::new().u64(2))?;
+            Ok(())
+        });
+
+        let mut samples = ProfSamples::new();
+        parse_reader(&eventlog[..], &mut samples).unwrap();
+        samples.finish()
+    }
+
+    #[test]
+    fn samples() {
+        let profiles = profiles();
+        assert_eq!(profiles.cpu_stacks(), [(&[2, 1][..], 2_000_000), (&[3, 2, 1][..], 1_000_000)]);
+        assert_eq!(profiles.cpu_caps, HashMap::from([(CapNo(0), 2), (CapNo(1), 1)]));
+        assert_eq!(profiles.censuses.len(), 2);
+        assert_eq!(profiles.peak_census().map(|census| census.time), Some(Timestamp(1_000_000_000)));
+        assert_eq!(profiles.cost_centres[&2].file_line(), Some(("Main.hs", 12)));
+        assert_eq!(profiles.cost_centres[&1].file_line(), None);
+        assert_eq!(profiles.frame_name(9), "<cost centre 9>");
+    }
+
+    #[test]
+    fn folded() {
+        let profiles = profiles();
+        let folded = write_folded(&profiles, profiles.cpu_stacks(), Vec::new()).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "\
+MAIN.MAIN;Main.go 2000000
+MAIN.MAIN;Main.go;Data.List.sum 1000000
+");
+    }
+
+    #[test]
+    fn speedscope() {
+        let json = write_speedscope(&profiles(), "prog", Vec::new()).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), concat!(
+            r#"{"$schema":"https://www.speedscope.app/file-format-schema.json","exporter":"ev","name":"prog","activeProfileIndex":0,"#,
+            r#""shared":{"frames":[{"name":"MAIN.MAIN"},{"name":"Main.go","file":"Main.hs","line":12},"#,
+            r#"{"name":"Data.List.sum","file":"libraries/base/Data/List.hs","line":5}]},"#,
+            r#""profiles":[{"type":"sampled","name":"cpu","unit":"nanoseconds","startValue":0,"endValue":3000000,"#,
+            r#""samples":[[0,1],[0,1,2]],"weights":[2000000,1000000]},"#,
+            r#"{"type":"sampled","name":"heap at 1.000000000s","unit":"bytes","startValue":0,"endValue":150,"#,
+            r#""samples":[[0,1],[0,1,2]],"weights":[100,50]}]}"#,
+            "\n",
+        ));
+    }
+}
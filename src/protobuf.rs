@@ -0,0 +1,169 @@
+/// A protobuf message being encoded, fields are written in the order they are added.
+#[derive(Default)]
+pub(crate) struct Message {
+    buf: Vec<u8>,
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+impl Message {
+    pub fn new() -> Self {
+        Message::default()
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        varint(&mut self.buf, (field as u64) << 3 | wire_type as u64);
+    }
+
+    /// An unsigned integer, bool or enum field.
+    pub fn uint(&mut self, field: u32, value: u64) -> &mut Self {
+        self.key(field, 0);
+        varint(&mut self.buf, value);
+        self
+    }
+
+    /// An `int64` field, negative numbers take ten bytes.
+    pub fn int(&mut self, field: u32, value: i64) -> &mut Self {
+        self.uint(field, value as u64)
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+        self.key(field, 2);
+        varint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    /// A packed repeated unsigned integer field.
+    pub fn packed(&mut self, field: u32, values: &[u64]) -> &mut Self {
+        let mut buf = Vec::new();
+        for value in values {
+            varint(&mut buf, *value);
+        }
+        self.bytes(field, &buf)
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) -> &mut Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn message(&mut self, field: u32, value: &Message) -> &mut Self {
+        self.bytes(field, &value.buf)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The value of a decoded field.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) enum Value {
+        Varint(u64),
+        Bytes(Vec<u8>),
+    }
+
+    impl Value {
+        pub(crate) fn bytes(&self) -> &[u8] {
+            match self {
+                Value::Bytes(bytes) => bytes,
+                Value::Varint(_) => panic!("not a length delimited field"),
+            }
+        }
+    }
+
+    fn read_varint(buf: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..).step_by(7) {
+            let (byte, rest) = buf.split_first().expect("truncated varint");
+            *buf = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    /// Decode the fields of a message, in the order they were written.
+    pub(crate) fn fields(mut buf: &[u8]) -> Vec<(u32, Value)> {
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf);
+            let value = match key & 7 {
+                0 => Value::Varint(read_varint(&mut buf)),
+                2 => {
+                    let len = read_varint(&mut buf) as usize;
+                    let (value, rest) = buf.split_at(len);
+                    buf = rest;
+                    Value::Bytes(value.to_vec())
+                },
+                wire_type => panic!("unexpected wire type {wire_type}"),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        fields
+    }
+
+    /// Decode the values of a packed repeated field.
+    pub(crate) fn varints(mut buf: &[u8]) -> Vec<u64> {
+        let mut values = Vec::new();
+        while !buf.is_empty() {
+            values.push(read_varint(&mut buf));
+        }
+        values
+    }
+
+    /// Show a message as `{field:value ...}`. The length delimited fields at the paths in
+    /// `messages`, eg `"1.2"` for field 2 of the message in field 1, are shown as messages, the
+    /// others as strings.
+    pub(crate) fn describe(buf: &[u8], messages: &[&str]) -> String {
+        fn describe_at(buf: &[u8], path: &str, messages: &[&str]) -> String {
+            let fields: Vec<String> = fields(buf).into_iter()
+                .map(|(field, value)| {
+                    let path = if path.is_empty() { field.to_string() } else { format!("{path}.{field}") };
+                    match value {
+                        Value::Varint(n) => format!("{field}:{n}"),
+                        Value::Bytes(bytes) if messages.contains(&path.as_str()) => {
+                            format!("{field}:{}", describe_at(&bytes, &path, messages))
+                        },
+                        Value::Bytes(bytes) => format!("{field}:{:?}", String::from_utf8_lossy(&bytes)),
+                    }
+                })
+                .collect();
+            format!("{{{}}}", fields.join(" "))
+        }
+        describe_at(buf, "", messages)
+    }
+
+    #[test]
+    fn encoding() {
+        let mut inner = Message::new();
+        inner.string(1, "hi");
+        let mut message = Message::new();
+        message.uint(1, 300)
+            .int(2, -1)
+            .packed(3, &[1, 128])
+            .message(4, &inner);
+
+        assert_eq!(&message.as_bytes()[..3], [0x08, 0xac, 0x02]);
+        assert_eq!(fields(message.as_bytes()), [
+            (1, Value::Varint(300)),
+            (2, Value::Varint(u64::MAX)),
+            (3, Value::Bytes(vec![1, 0x80, 0x01])),
+            (4, Value::Bytes(vec![0x0a, 2, b'h', b'i'])),
+        ]);
+        // negative numbers take ten bytes
+        assert_eq!(Message::new().int(1, -1).as_bytes().len(), 11);
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::{self, Write};
+
+use regex::bytes::Regex;
+
+use crate::encode::EventlogWriter;
+use crate::parse::{Context, EventType, EventlogParser, BLOCK_MARKER};
+use crate::types::{CapNo, CapsetId, ThreadId, Timestamp};
+
+/// What to do with a string from the eventlog.
+#[derive(Debug, Clone, Default)]
+pub enum Rewrite {
+    /// Leave the string as it is
+    #[default]
+    Keep,
+    /// Replace the string with a hash of it, so equal strings stay equal
+    Hash,
+    /// Replace the string with the empty string
+    Drop,
+    /// Replace all matches of the regex, see [`Regex::replace_all`] for the replacement syntax
+    Replace(Regex, Vec<u8>),
+}
+
+impl Rewrite {
+    /// Rewrite a string. A NUL put in by a replacement is written as `\0`, as most strings are
+    /// NUL terminated.
+    pub fn apply(&self, string: &[u8]) -> Vec<u8> {
+        match self {
+            Rewrite::Keep => string.to_vec(),
+            Rewrite::Hash => format!("{:016x}", fnv1a(string)).into_bytes(),
+            Rewrite::Drop => Vec::new(),
+            Rewrite::Replace(regex, replacement) => {
+                let replaced = regex.replace_all(string, &replacement[..]);
+                let mut escaped = Vec::with_capacity(replaced.len());
+                for b in replaced.iter() {
+                    match b {
+                        0 => escaped.extend(b"\\0"),
+                        b => escaped.push(*b),
+                    }
+                }
+                escaped
+            },
+        }
+    }
+
+    /// Rewrite every string in a list of NUL separated strings.
+    fn apply_list(&self, strings: &[u8]) -> Vec<u8> {
+        if let Rewrite::Drop = self {
+            return Vec::new();
+        }
+        // every string is followed by a NUL
+        let (strings, terminated) = match strings.strip_suffix(&[0]) {
+            Some(strings) => (strings, true),
+            None => (strings, false),
+        };
+        let mut rewritten = strings.split(|b| *b == 0)
+            .map(|string| self.apply(string))
+            .collect::<Vec<_>>()
+            .join(&0);
+        if terminated {
+            rewritten.push(0);
+        }
+        rewritten
+    }
+
+    fn is_keep(&self) -> bool {
+        matches!(self, Rewrite::Keep)
+    }
+}
+
+/// 64 bit FNV-1a, used so the hashes are the same between runs and versions.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// How to rewrite each kind of string payload.
+#[derive(Debug, Clone, Default)]
+pub struct Redaction {
+    /// Each of the arguments in PROGRAM_ARGS
+    pub program_args: Rewrite,
+    /// Each of the variables in PROGRAM_ENV
+    pub program_env: Rewrite,
+    /// USER_MSG and USER_MARKER
+    pub user_messages: Rewrite,
+    /// THREAD_LABEL
+    pub thread_labels: Rewrite,
+    /// The label, module and source location of HEAP_PROF_COST_CENTRE
+    pub cost_centres: Rewrite,
+    /// The filters of HEAP_PROF_BEGIN and the labels of HEAP_PROF_SAMPLE_STRING
+    pub heap_profile: Rewrite,
+}
+
+/// Writes the parsed eventlog with string payloads rewritten according to a [`Redaction`].
+///
+/// Everything else is copied unchanged, except for the block sizes which are updated to match
+/// the rewritten events.
+pub struct Redact<W: Write> {
+    redaction: Redaction,
+    writer: EventlogWriter<W>,
+    error: Option<io::Error>,
+    /// Offset of the end of the current block
+    block_end: u64,
+    replacement: Option<Vec<u8>>,
+}
+
+impl<W: Write> Redact<W> {
+    pub fn new(redaction: Redaction, out: W) -> Self {
+        Redact {
+            redaction,
+            writer: EventlogWriter::new(out),
+            error: None,
+            block_end: 0,
+            replacement: None,
+        }
+    }
+
+    /// Finish writing the eventlog, returning the first error encountered.
+    pub fn finish(self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.finish()
+    }
+
+    fn check(&mut self, res: io::Result<()>) {
+        if let Err(err) = res {
+            self.error.get_or_insert(err);
+        }
+    }
+}
+
+impl<W: Write> EventlogParser for Redact<W> {
+    fn header(&mut self, event_types: &[(u16, EventType)]) {
+        let res = self.writer.header(event_types);
+        self.check(res);
+    }
+
+    fn event_start(&mut self, _ctx: &Context) {
+        self.replacement = None;
+    }
+
+    fn event_end(&mut self, ctx: &Context, payload: &[u8]) {
+        if self.error.is_some() || ctx.id == BLOCK_MARKER {
+            return;
+        }
+
+        if ctx.offset >= self.block_end {
+            let res = self.writer.end_block();
+            self.check(res);
+        }
+
+        let res = match self.replacement.take() {
+            Some(replacement) => self.writer.event(ctx.id, ctx.time, &replacement),
+            None => self.writer.event(ctx.id, ctx.time, payload),
+        };
+        self.check(res);
+    }
+
+    fn event_block_marker(&mut self, _ctx: &Context, _block_size: u32, _time_end: Timestamp, _capno: Option<CapNo>) {
+        let res = self.writer.begin_block(_ctx.time, _time_end, _capno);
+        self.check(res);
+        self.block_end = _ctx.offset + _block_size as u64;
+    }
+
+    fn event_program_args(&mut self, _ctx: &Context, _capset: CapsetId, _args: Vec<u8>) {
+        if !self.redaction.program_args.is_keep() {
+            let mut payload = _capset.0.to_be_bytes().to_vec();
+            payload.extend(self.redaction.program_args.apply_list(&_args));
+            self.replacement = Some(payload);
+        }
+    }
+    fn event_program_env(&mut self, _ctx: &Context, _capset: CapsetId, _env: Vec<u8>) {
+        if !self.redaction.program_env.is_keep() {
+            let mut payload = _capset.0.to_be_bytes().to_vec();
+            payload.extend(self.redaction.program_env.apply_list(&_env));
+            self.replacement = Some(payload);
+        }
+    }
+
+    fn event_user_msg(&mut self, _ctx: &Context, _bytes: Vec<u8>) {
+        if !self.redaction.user_messages.is_keep() {
+            self.replacement = Some(self.redaction.user_messages.apply(&_bytes));
+        }
+    }
+    fn event_user_marker(&mut self, _ctx: &Context, _marker: Vec<u8>) {
+        if !self.redaction.user_messages.is_keep() {
+            self.replacement = Some(self.redaction.user_messages.apply(&_marker));
+        }
+    }
+
+    fn event_thread_label(&mut self, _ctx: &Context, _threadid: ThreadId, _label: Vec<u8>) {
+        if !self.redaction.thread_labels.is_keep() {
+            let mut payload = _threadid.0.to_be_bytes().to_vec();
+            payload.extend(self.redaction.thread_labels.apply(&_label));
+            self.replacement = Some(payload);
+        }
+    }
+
+    fn event_heap_prof_begin(&mut self, _ctx: &Context, profile: u8, sampling_period: u64, breakdown: u32, module: Vec<u8>, closure_descr: Vec<u8>, type_descr: Vec<u8>, cost_centre: Vec<u8>, cost_centre_stack: Vec<u8>, retainer: Vec<u8>, biography: Vec<u8>) {
+        if !self.redaction.heap_profile.is_keep() {
+            let mut payload = vec![profile];
+            payload.extend(sampling_period.to_be_bytes());
+            payload.extend(breakdown.to_be_bytes());
+            for string in [module, closure_descr, type_descr, cost_centre, cost_centre_stack, retainer, biography] {
+                // an empty filter is one that was not given
+                if !string.is_empty() {
+                    payload.extend(self.redaction.heap_profile.apply(&string));
+                }
+                payload.push(0);
+            }
+            self.replacement = Some(payload);
+        }
+    }
+
+    fn event_heap_prof_sample_string(&mut self, _ctx: &Context, profile: u8, residency: u64, label: Vec<u8>) {
+        if !self.redaction.heap_profile.is_keep() {
+            let mut payload = vec![profile];
+            payload.extend(residency.to_be_bytes());
+            payload.extend(self.redaction.heap_profile.apply(&label));
+            payload.push(0);
+            self.replacement = Some(payload);
+        }
+    }
+
+    fn event_heap_prof_cost_centre(&mut self, _ctx: &Context, _ccid: u32, _label: Vec<u8>, _module: Vec<u8>, _srcloc: Vec<u8>, _flags: u8) {
+        if !self.redaction.cost_centres.is_keep() {
+            let mut payload = _ccid.to_be_bytes().to_vec();
+            for string in [_label, _module, _srcloc] {
+                payload.extend(self.redaction.cost_centres.apply(&string));
+                payload.push(0);
+            }
+            payload.push(_flags);
+            self.replacement = Some(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{parse, write};
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn hashes_cost_centres() {
+        let eventlog = write(|w| w.event(161, Timestamp(0), b"\0\0\0\x07main\0Main\0Main.hs:1:1\0\x01"));
+        let redaction = Redaction {
+            cost_centres: Rewrite::Hash,
+            ..Redaction::default()
+        };
+        let mut redact = Redact::new(redaction, Vec::new());
+        parse_reader(&eventlog[..], &mut redact).unwrap();
+        let redacted = parse(&redact.finish().unwrap());
+
+        let mut expected = b"\0\0\0\x07".to_vec();
+        for string in [&b"main"[..], b"Main", b"Main.hs:1:1"] {
+            expected.extend(format!("{:016x}\0", fnv1a(string)).into_bytes());
+        }
+        expected.push(1);
+        assert_eq!(redacted.events, [(161, Timestamp(0), None, expected)]);
+    }
+
+    #[test]
+    fn escapes_nul() {
+        let rewrite = Rewrite::Replace(Regex::new("a").unwrap(), b"\0".to_vec());
+        assert_eq!(rewrite.apply(b"cat"), b"c\\0t");
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use crate::parse::{Context, EventlogParser, SparkCounters};
+use crate::types::{CapNo, Timestamp};
+
+/// The spark counters of one capability over time.
+#[derive(Debug, Clone, Default)]
+pub struct CapSparks {
+    /// The counters since the program started, at each SPARK_COUNTERS
+    pub counters: Vec<(Timestamp, SparkCounters)>,
+}
+
+impl CapSparks {
+    /// The change in the counters since the previous SPARK_COUNTERS, for each SPARK_COUNTERS.
+    pub fn deltas(&self) -> Vec<(Timestamp, SparkCounters)> {
+        let mut previous = SparkCounters::default();
+        self.counters.iter()
+            .map(|(time, counters)| {
+                let delta = counters.since(&previous);
+                previous = *counters;
+                (*time, delta)
+            })
+            .collect()
+    }
+
+    /// The last counters of the capability.
+    pub fn totals(&self) -> SparkCounters {
+        self.counters.last().map(|(_, counters)| *counters).unwrap_or_default()
+    }
+}
+
+/// Collects the SPARK_COUNTERS of every capability.
+///
+/// The RTS writes the counters of a capability to its own block, the events can be fed in file
+/// order.
+#[derive(Default)]
+pub struct Sparks {
+    caps: BTreeMap<CapNo, CapSparks>,
+}
+
+impl Sparks {
+    pub fn new() -> Self {
+        Sparks::default()
+    }
+
+    pub fn finish(self) -> BTreeMap<CapNo, CapSparks> {
+        self.caps
+    }
+}
+
+/// The totals of all capabilities together, the numbers `+RTS -s` shows.
+pub fn totals(caps: &BTreeMap<CapNo, CapSparks>) -> SparkCounters {
+    caps.values()
+        .map(CapSparks::totals)
+        .fold(SparkCounters::default(), |total, counters| total + counters)
+}
+
+impl EventlogParser for Sparks {
+    fn event_spark_counters(&mut self, _ctx: &Context, _counters: SparkCounters) {
+        if let Some(capno) = _ctx.capno {
+            self.caps.entry(capno).or_default().counters.push((_ctx.time, _counters));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    fn counters(numbers: [u64; 7]) -> SparkCounters {
+        let [created, dud, overflowed, converted, gcd, fizzled, remaining] = numbers;
+        SparkCounters { created, dud, overflowed, converted, gcd, fizzled, remaining }
+    }
+
+    #[test]
+    fn deltas_and_totals() {
+        let eventlog = write(|w| {
+            for (time, capno, numbers) in [
+                (10, 0, [10, 1, 0, 5, 1, 2, 1]),
+                (15, 1, [4, 0, 0, 4, 0, 0, 0]),
+                (20, 0, [20, 2, 1, 12, 3, 2, 2]),
+            ] {
+                let payload = numbers.iter().fold(Payload::new(), |payload, n| payload.u64(*n));
+                w.begin_block(Timestamp(time), Timestamp(time), Some(CapNo(capno)))?;
+                w.event(34, Timestamp(time), &payload)?;
+            }
+            Ok(())
+        });
+
+        let mut sparks = Sparks::new();
+        parse_reader(&eventlog[..], &mut sparks).unwrap();
+        let caps = sparks.finish();
+
+        assert_eq!(caps[&CapNo(0)].deltas(), [
+            (Timestamp(10), counters([10, 1, 0, 5, 1, 2, 1])),
+            // the remaining sparks are not a count
+            (Timestamp(20), counters([10, 1, 1, 7, 2, 0, 2])),
+        ]);
+        let total = totals(&caps);
+        assert_eq!(total, counters([24, 2, 1, 16, 3, 2, 2]));
+        assert_eq!(total.to_string(), "SPARKS: 27 (16 converted, 1 overflowed, 2 dud, 3 GC'd, 2 fizzled)");
+    }
+}
@@ -0,0 +1,316 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::blocked::BlockReason;
+use crate::export::{Record, Records};
+use crate::gc::GcPauses;
+use crate::markers::MarkerSpans;
+use crate::order::RawEvent;
+use crate::threads::{ThreadState, Threads};
+use crate::timeline::{Activity, Timeline};
+
+const SCHEMA: &str = "
+CREATE TABLE events (
+    time INTEGER NOT NULL,
+    cap INTEGER,
+    id INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    fields TEXT NOT NULL
+);
+CREATE TABLE threads (
+    thread INTEGER PRIMARY KEY,
+    label TEXT,
+    created INTEGER,
+    finished INTEGER
+);
+CREATE TABLE thread_intervals (
+    thread INTEGER NOT NULL,
+    start INTEGER NOT NULL,
+    end INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    cap INTEGER,
+    status TEXT,
+    reason TEXT,
+    blocked_on INTEGER
+);
+CREATE TABLE cap_intervals (
+    cap INTEGER NOT NULL,
+    start INTEGER NOT NULL,
+    end INTEGER NOT NULL,
+    activity TEXT NOT NULL,
+    thread INTEGER
+);
+CREATE TABLE gc_pauses (
+    start INTEGER NOT NULL,
+    end INTEGER NOT NULL,
+    pause INTEGER NOT NULL,
+    requested INTEGER,
+    parallel INTEGER,
+    sync INTEGER,
+    gen INTEGER,
+    copied INTEGER,
+    slop INTEGER,
+    fragmentation INTEGER,
+    threads INTEGER,
+    max_copied INTEGER,
+    total_copied INTEGER,
+    balanced_copied INTEGER
+);
+CREATE TABLE marker_spans (
+    name TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    end INTEGER NOT NULL,
+    cap INTEGER
+);
+";
+
+const INDEXES: &str = "
+CREATE INDEX events_time ON events (time);
+CREATE INDEX events_event ON events (event, time);
+CREATE INDEX thread_intervals_thread ON thread_intervals (thread, start);
+CREATE INDEX thread_intervals_start ON thread_intervals (start, end);
+CREATE INDEX cap_intervals_cap ON cap_intervals (cap, start);
+CREATE INDEX gc_pauses_start ON gc_pauses (start);
+CREATE INDEX marker_spans_name ON marker_spans (name, start);
+CREATE INDEX marker_spans_start ON marker_spans (start, end);
+";
+
+/// Loads an eventlog into a SQLite database.
+///
+/// Every event is a row of `events`, with its fields as a JSON object in `fields` (see
+/// [`crate::export::Record`]). The derived tables are `threads` and `thread_intervals` (see
+/// [`Threads`]), `cap_intervals` (see [`Timeline`]), `gc_pauses` (see [`GcPauses`]) and
+/// `marker_spans` (see [`MarkerSpans`]). Times are in nanoseconds.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]) with [`SqliteExport::add`],
+/// then call [`SqliteExport::finish`]. Everything is written in one transaction.
+pub struct SqliteExport {
+    conn: Connection,
+    error: Option<rusqlite::Error>,
+    threads: Threads,
+    timeline: Timeline,
+    gc: GcPauses,
+    markers: MarkerSpans,
+}
+
+impl SqliteExport {
+    /// Create the tables in a new database at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF; BEGIN;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteExport {
+            conn,
+            error: None,
+            threads: Threads::new(),
+            timeline: Timeline::new(),
+            gc: GcPauses::new(),
+            markers: MarkerSpans::new(),
+        })
+    }
+
+    fn check(&mut self, res: rusqlite::Result<()>) {
+        if let Err(err) = res {
+            self.error.get_or_insert(err);
+        }
+    }
+
+    pub fn add(&mut self, event: &RawEvent) {
+        event.decode(&mut self.threads);
+        event.decode(&mut self.timeline);
+        event.decode(&mut self.gc);
+        event.decode(&mut self.markers);
+
+        if self.error.is_some() {
+            return;
+        }
+        let mut records = Vec::new();
+        event.decode(&mut Records::new(|record: Record| records.push(record)));
+        for record in records {
+            let res = self.insert_event(&record);
+            self.check(res);
+        }
+    }
+
+    fn insert_event(&self, record: &Record) -> rusqlite::Result<()> {
+        let fields: Vec<String> = record.fields.iter()
+            .map(|(name, value)| format!("\"{name}\":{}", value.json()))
+            .collect();
+        self.conn
+            .prepare_cached("INSERT INTO events VALUES (?, ?, ?, ?, ?)")?
+            .execute(params![
+                record.ctx.time.0,
+                record.ctx.capno.map(|capno| capno.0),
+                record.ctx.id,
+                record.event,
+                format!("{{{}}}", fields.join(",")),
+            ])?;
+        Ok(())
+    }
+
+    /// Write the derived tables, create the indexes and commit, returning the first error
+    /// encountered.
+    pub fn finish(self) -> rusqlite::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let conn = self.conn;
+
+        {
+            let mut thread = conn.prepare("INSERT INTO threads VALUES (?, ?, ?, ?)")?;
+            let mut interval = conn.prepare("INSERT INTO thread_intervals VALUES (?, ?, ?, ?, ?, ?, ?, ?)")?;
+            for (threadid, t) in self.threads.finish() {
+                thread.execute(params![
+                    threadid.0,
+                    t.label,
+                    t.created.map(|time| time.0),
+                    t.finished.map(|time| time.0),
+                ])?;
+                for i in &t.intervals {
+                    let (state, capno, status) = match i.state {
+                        ThreadState::Running(capno) => ("running", Some(capno.0), None),
+                        ThreadState::Runnable => ("runnable", None, None),
+                        ThreadState::Blocked(status) => ("blocked", None, Some(status)),
+                    };
+                    interval.execute(params![
+                        threadid.0,
+                        i.start.0,
+                        i.end.0,
+                        state,
+                        capno,
+                        status.map(|status| status.to_string()),
+                        status.map(|status| BlockReason::of(status).to_string()),
+                        status.and_then(|status| status.blocked_on()).map(|blocker| blocker.0),
+                    ])?;
+                }
+            }
+
+            let mut interval = conn.prepare("INSERT INTO cap_intervals VALUES (?, ?, ?, ?, ?)")?;
+            for (capno, intervals) in self.timeline.finish() {
+                for i in intervals {
+                    let (activity, threadid) = match i.activity {
+                        Activity::Running(threadid) => ("running", Some(threadid.0)),
+                        Activity::Gc => ("gc", None),
+                        Activity::Idle => ("idle", None),
+                    };
+                    interval.execute(params![capno.0, i.start.0, i.end.0, activity, threadid])?;
+                }
+            }
+
+            let mut pause = conn.prepare("INSERT INTO gc_pauses VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+            for gc in self.gc.finish() {
+                let stats = gc.stats.as_ref();
+                pause.execute(params![
+                    gc.start.0,
+                    gc.end.0,
+                    gc.pause().as_nanos() as u64,
+                    gc.requested.map(|time| time.0),
+                    gc.parallel,
+                    gc.sync().map(|sync| sync.as_nanos() as u64),
+                    stats.map(|stats| stats.gen),
+                    stats.map(|stats| stats.copied),
+                    stats.map(|stats| stats.slop),
+                    stats.map(|stats| stats.fragmentation),
+                    stats.map(|stats| stats.threads),
+                    stats.map(|stats| stats.max_copied),
+                    stats.map(|stats| stats.total_copied),
+                    stats.map(|stats| stats.balanced_copied),
+                ])?;
+            }
+
+            let mut span = conn.prepare("INSERT INTO marker_spans VALUES (?, ?, ?, ?)")?;
+            for s in self.markers.finish() {
+                span.execute(params![s.name, s.start.0, s.end.0, s.capno.map(|capno| capno.0)])?;
+            }
+        }
+
+        conn.execute_batch(INDEXES)?;
+        conn.execute_batch("COMMIT;")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{event_types, Payload};
+    use crate::encode::EventlogWriter;
+    use crate::order::TimeOrder;
+    use crate::parse::{parse_reader, EventSize, EventType};
+    use crate::types::{CapNo, Timestamp};
+
+    /// Thread 1 runs on cap 0 from 10 to 20, stops for a GC from 20 to 30, and finishes at 40,
+    /// inside a `work` marker span.
+    fn eventlog() -> Vec<u8> {
+        let mut event_types = event_types();
+        event_types.push((58, EventType { size: EventSize::Variable, descr: "USER_MARKER".to_string(), extra: Vec::new() }));
+        let mut w = EventlogWriter::new(Vec::new());
+        w.header(&event_types).unwrap();
+        w.begin_block(Timestamp(0), Timestamp(40), Some(CapNo(0))).unwrap();
+        for (id, time, payload) in [
+            (0, 0, Payload::new().u32(1)),
+            (1, 10, Payload::new().u32(1)),
+            (58, 10, Payload::new().bytes(b"begin:work")),
+            // HeapOverflow
+            (2, 20, Payload::new().u32(1).u16(1).u32(0)),
+            (9, 20, Payload::new()),
+            (10, 30, Payload::new()),
+            (1, 30, Payload::new().u32(1)),
+            // ThreadFinished
+            (2, 40, Payload::new().u32(1).u16(5).u32(0)),
+            (58, 40, Payload::new().bytes(b"end:work")),
+        ] {
+            w.event(id, Timestamp(time), &payload).unwrap();
+        }
+        w.finish().unwrap()
+    }
+
+    #[test]
+    fn tables() {
+        let path = std::env::temp_dir().join(format!("ev-sqlite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut db = SqliteExport::create(&path).unwrap();
+        let mut order = TimeOrder::new(|event: RawEvent| db.add(&event));
+        parse_reader(&eventlog()[..], &mut order).unwrap();
+        order.finish();
+        db.finish().unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let count = |table: &str| -> u64 {
+            conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |row| row.get(0)).unwrap()
+        };
+        // TimeOrder leaves out the block marker
+        assert_eq!(count("events"), 9);
+        assert_eq!(count("threads"), 1);
+        assert_eq!(count("gc_pauses"), 1);
+        assert_eq!(count("marker_spans"), 1);
+
+        let fields: String = conn.query_row("SELECT fields FROM events WHERE time = 40 AND event = 'thread_stop'", [], |row| row.get(0)).unwrap();
+        assert_eq!(fields, r#"{"thread":1,"status":"ThreadFinished"}"#);
+        let thread: (Option<u64>, Option<u64>) = conn.query_row("SELECT created, finished FROM threads WHERE thread = 1", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!(thread, (Some(0), Some(40)));
+        let intervals: Vec<(u64, u64, String)> = conn
+            .prepare("SELECT start, end, state FROM thread_intervals WHERE thread = 1 ORDER BY start").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(intervals, [
+            (0, 10, "runnable".to_string()),
+            (10, 20, "running".to_string()),
+            (20, 30, "runnable".to_string()),
+            (30, 40, "running".to_string()),
+        ]);
+        let gc: u64 = conn.query_row("SELECT sum(end - start) FROM cap_intervals WHERE activity = 'gc'", [], |row| row.get(0)).unwrap();
+        assert_eq!(gc, 10);
+        let pause: (u64, u64, u64) = conn.query_row("SELECT start, end, pause FROM gc_pauses", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap();
+        assert_eq!(pause, (20, 30, 10));
+        let span: (String, u64, u64, Option<u16>) = conn.query_row("SELECT name, start, end, cap FROM marker_spans", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+        assert_eq!(span, ("work".to_string(), 10, 40, Some(0)));
+        let indexes: u64 = conn.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'index'", [], |row| row.get(0)).unwrap();
+        assert_eq!(indexes, 8);
+
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
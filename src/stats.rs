@@ -0,0 +1,81 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::parse::{Context, EventSize, EventType, EventlogParser};
+use crate::types::{CapNo, Timestamp};
+
+/// How many events of a type there are, and how much of the eventlog they take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EventCount {
+    pub count: u64,
+    /// Bytes, with the event headers
+    pub bytes: u64,
+}
+
+/// What is in an eventlog, see [`Stats`].
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub event_types: BTreeMap<u16, EventType>,
+    /// The events of each event type id
+    pub counts: BTreeMap<u16, EventCount>,
+    /// Time of the earliest and the latest event
+    pub first: Option<Timestamp>,
+    pub last: Option<Timestamp>,
+    /// Capabilities with blocks of events
+    pub caps: BTreeSet<CapNo>,
+}
+
+impl Summary {
+    pub fn total(&self) -> EventCount {
+        self.counts.values().fold(EventCount::default(), |total, count| EventCount {
+            count: total.count + count.count,
+            bytes: total.bytes + count.bytes,
+        })
+    }
+
+    /// The description of an event type from the header.
+    pub fn name(&self, id: u16) -> &str {
+        self.event_types.get(&id).map(|et| et.descr.as_str()).unwrap_or("unknown")
+    }
+}
+
+/// Counts the events of an eventlog by type.
+///
+/// Uses [`EventlogParser::event_end`], so parse the eventlog with it directly rather than
+/// through [`crate::order::TimeOrder`]. Call [`Stats::finish`] after parsing to get the
+/// [`Summary`].
+#[derive(Default)]
+pub struct Stats {
+    summary: Summary,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    pub fn finish(self) -> Summary {
+        self.summary
+    }
+}
+
+impl EventlogParser for Stats {
+    fn header(&mut self, event_types: &[(u16, EventType)]) {
+        self.summary.event_types = event_types.iter().cloned().collect();
+    }
+
+    fn event_end(&mut self, _ctx: &Context, _payload: &[u8]) {
+        let summary = &mut self.summary;
+        // id and time, and the size of variable sized events
+        let header = match summary.event_types.get(&_ctx.id).map(|et| et.size) {
+            Some(EventSize::Variable) => 12,
+            _ => 10,
+        };
+        let count = summary.counts.entry(_ctx.id).or_default();
+        count.count += 1;
+        count.bytes += header + _ctx.size as u64;
+
+        summary.first = Some(summary.first.map_or(_ctx.time, |first| first.min(_ctx.time)));
+        summary.last = Some(summary.last.map_or(_ctx.time, |last| last.max(_ctx.time)));
+        summary.caps.extend(_ctx.capno);
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::Write;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 450.0;
+const MARGIN_LEFT: f64 = 80.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 50.0;
+
+const COLOURS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+
+pub(crate) struct Series<'a> {
+    pub name: &'a str,
+    pub points: Vec<(f64, f64)>,
+}
+
+/// A line chart, written out as SVG with [`Chart::render`].
+pub(crate) struct Chart<'a> {
+    pub title: &'a str,
+    pub x_label: &'a str,
+    pub y_label: &'a str,
+    /// Use a logarithmic x axis, the x values must be positive
+    pub log_x: bool,
+    /// Lowest and highest value on the y axis, taken from the data if `None`
+    pub y_range: Option<(f64, f64)>,
+    pub x_format: fn(f64) -> String,
+    pub y_format: fn(f64) -> String,
+    pub series: Vec<Series<'a>>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Bounds of the values, widened if they are all the same.
+fn range<I: Iterator<Item = f64>>(values: I) -> (f64, f64) {
+    let (lo, hi) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if lo > hi {
+        (0.0, 1.0)
+    } else if lo == hi {
+        (lo - 0.5, hi + 0.5)
+    } else {
+        (lo, hi)
+    }
+}
+
+impl Chart<'_> {
+    pub fn render(&self) -> String {
+        let x = |v: f64| if self.log_x { v.log10() } else { v };
+
+        let points = || self.series.iter().flat_map(|series| series.points.iter());
+        let (x_lo, x_hi) = range(points().map(|(px, _)| x(*px)));
+        let (y_lo, y_hi) = self.y_range.unwrap_or_else(|| range(points().map(|(_, py)| *py)));
+
+        let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+        let plot_height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        let sx = |v: f64| MARGIN_LEFT + (x(v) - x_lo) / (x_hi - x_lo) * plot_width;
+        let sy = |v: f64| MARGIN_TOP + (1.0 - (v - y_lo) / (y_hi - y_lo)) * plot_height;
+
+        let mut svg = String::new();
+        let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" font-family="sans-serif" font-size="12">"#);
+        let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+        let _ = writeln!(svg, r#"<text x="{}" y="24" text-anchor="middle" font-size="16">{}</text>"#,
+            WIDTH / 2.0, escape(self.title));
+
+        // axes
+        let _ = writeln!(svg, r#"<path d="M{MARGIN_LEFT},{MARGIN_TOP}V{}H{}" fill="none" stroke="black"/>"#,
+            HEIGHT - MARGIN_BOTTOM, WIDTH - MARGIN_RIGHT);
+        let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+            MARGIN_LEFT + plot_width / 2.0, HEIGHT - 10.0, escape(self.x_label));
+        let _ = writeln!(svg, r#"<text x="16" y="{}" text-anchor="middle" transform="rotate(-90 16 {})">{}</text>"#,
+            MARGIN_TOP + plot_height / 2.0, MARGIN_TOP + plot_height / 2.0, escape(self.y_label));
+
+        // ticks, at every power of ten on a log axis
+        let x_ticks: Vec<f64> = if self.log_x {
+            (x_lo.ceil() as i32..=x_hi.floor() as i32).map(|e| 10f64.powi(e)).collect()
+        } else {
+            (0..=4).map(|i| x_lo + (x_hi - x_lo) * i as f64 / 4.0).collect()
+        };
+        for tick in x_ticks {
+            let _ = writeln!(svg, r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#,
+                sx(tick), HEIGHT - MARGIN_BOTTOM + 16.0, escape(&(self.x_format)(tick)));
+        }
+        for i in 0..=4 {
+            let tick = y_lo + (y_hi - y_lo) * i as f64 / 4.0;
+            let _ = writeln!(svg, r##"<path d="M{MARGIN_LEFT},{:.1}H{}" stroke="#ddd"/>"##,
+                sy(tick), WIDTH - MARGIN_RIGHT);
+            let _ = writeln!(svg, r#"<text x="{}" y="{:.1}" text-anchor="end">{}</text>"#,
+                MARGIN_LEFT - 6.0, sy(tick) + 4.0, escape(&(self.y_format)(tick)));
+        }
+
+        for (i, series) in self.series.iter().enumerate() {
+            let colour = COLOURS[i % COLOURS.len()];
+            let path: Vec<String> = series.points.iter()
+                .map(|(px, py)| format!("{:.1},{:.1}", sx(*px), sy(*py)))
+                .collect();
+            if !path.is_empty() {
+                let _ = writeln!(svg, r#"<path d="M{}" fill="none" stroke="{colour}" stroke-width="1.5"/>"#,
+                    path.join("L"));
+            }
+            let _ = writeln!(svg, r#"<text x="{}" y="{}" fill="{colour}">{}</text>"#,
+                MARGIN_LEFT + 10.0, MARGIN_TOP + 14.0 + 14.0 * i as f64, escape(series.name));
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::parse::{Context, EventlogParser, ThreadStopStatus};
+use crate::types::{CapNo, ThreadId, Timestamp};
+
+/// What a Haskell thread is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThreadState {
+    Running(CapNo),
+    /// Waiting to be run
+    Runnable,
+    /// Waiting for something, with the reason from the STOP_THREAD that blocked it
+    Blocked(ThreadStopStatus),
+}
+
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThreadState::Running(capno) => write!(f, "running on {capno}"),
+            ThreadState::Runnable => write!(f, "runnable"),
+            ThreadState::Blocked(status) => match status.blocked_on() {
+                Some(threadid) => write!(f, "blocked {status} by {threadid}"),
+                None => write!(f, "blocked {status}"),
+            },
+        }
+    }
+}
+
+/// A thread in one state from `start` up to `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateInterval {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub state: ThreadState,
+}
+
+/// The life of a Haskell thread.
+#[derive(Debug, Clone, Default)]
+pub struct Thread {
+    /// The last label given with THREAD_LABEL
+    pub label: Option<String>,
+    /// Time of CREATE_THREAD, `None` if the thread was created before the eventlog started
+    pub created: Option<Timestamp>,
+    /// Time of the STOP_THREAD with ThreadFinished
+    pub finished: Option<Timestamp>,
+    /// What the thread was doing, in time order
+    pub intervals: Vec<StateInterval>,
+}
+
+impl Thread {
+    /// What the thread was doing at `time`, `None` if it did not exist then.
+    pub fn state_at(&self, time: Timestamp) -> Option<ThreadState> {
+        let i = self.intervals.partition_point(|interval| interval.end <= time);
+        self.intervals.get(i)
+            .filter(|interval| interval.start <= time)
+            .map(|interval| interval.state)
+    }
+}
+
+/// A thread that has not finished.
+struct Current {
+    state: ThreadState,
+    since: Timestamp,
+}
+
+/// Follows the state of every Haskell thread over time.
+///
+/// A thread is runnable from CREATE_THREAD, running from RUN_THREAD, and after STOP_THREAD it is
+/// blocked when it blocked or made a foreign call, finished when it finished, and runnable
+/// otherwise. THREAD_WAKEUP, THREAD_RUNNABLE and MIGRATE_THREAD make a thread runnable. Threads
+/// that were created before the eventlog started begin with their first event.
+///
+/// Feed the events in time order (see [`crate::order::TimeOrder`]), a thread is woken up and
+/// migrated from the block of another capability than the one it runs on.
+///
+/// Call [`Threads::finish`] after the last event to get the threads.
+#[derive(Default)]
+pub struct Threads {
+    threads: BTreeMap<ThreadId, Thread>,
+    current: BTreeMap<ThreadId, Current>,
+    /// Time of the last thread event
+    last: Timestamp,
+}
+
+impl Threads {
+    pub fn new() -> Self {
+        Threads::default()
+    }
+
+    /// End the open intervals at the time of the last thread event, and return every thread.
+    pub fn finish(mut self) -> BTreeMap<ThreadId, Thread> {
+        let threads: Vec<ThreadId> = self.current.keys().copied().collect();
+        for threadid in threads {
+            self.close(threadid, self.last);
+        }
+        self.threads
+    }
+
+    /// End the current interval of `threadid` at `time`.
+    fn close(&mut self, threadid: ThreadId, time: Timestamp) {
+        self.last = self.last.max(time);
+        let Some(current) = self.current.remove(&threadid) else {
+            return;
+        };
+        if time > current.since {
+            self.threads.entry(threadid).or_default().intervals.push(StateInterval {
+                start: current.since,
+                end: time,
+                state: current.state,
+            });
+        }
+    }
+
+    /// Switch `threadid` to `state` at `time`.
+    fn switch(&mut self, threadid: ThreadId, time: Timestamp, state: ThreadState) {
+        self.last = self.last.max(time);
+        if let Some(current) = self.current.get(&threadid) {
+            if current.state == state {
+                return;
+            }
+        }
+        self.close(threadid, time);
+        self.threads.entry(threadid).or_default();
+        self.current.insert(threadid, Current { state, since: time });
+    }
+}
+
+impl EventlogParser for Threads {
+    fn event_thread_create(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.threads.entry(_threadid).or_default().created = Some(_ctx.time);
+        self.switch(_threadid, _ctx.time, ThreadState::Runnable);
+    }
+
+    fn event_thread_run(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        let state = match _ctx.capno {
+            Some(capno) => ThreadState::Running(capno),
+            None => ThreadState::Runnable,
+        };
+        self.switch(_threadid, _ctx.time, state);
+    }
+
+    fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, _status: ThreadStopStatus) {
+        if _status == ThreadStopStatus::ThreadFinished {
+            self.close(_threadid, _ctx.time);
+            self.threads.entry(_threadid).or_default().finished = Some(_ctx.time);
+        } else if _status.is_blocked() || _status == ThreadStopStatus::ForeignCall {
+            self.switch(_threadid, _ctx.time, ThreadState::Blocked(_status));
+        } else {
+            self.switch(_threadid, _ctx.time, ThreadState::Runnable);
+        }
+    }
+
+    fn event_thread_runnable(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        self.switch(_threadid, _ctx.time, ThreadState::Runnable);
+    }
+    fn event_thread_wakeup(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.switch(_threadid, _ctx.time, ThreadState::Runnable);
+    }
+    fn event_thread_migrate(&mut self, _ctx: &Context, _threadid: ThreadId, _capno: CapNo) {
+        self.switch(_threadid, _ctx.time, ThreadState::Runnable);
+    }
+
+    fn event_thread_label(&mut self, _ctx: &Context, _threadid: ThreadId, _label: Vec<u8>) {
+        let label = String::from_utf8_lossy(&_label).into_owned();
+        self.threads.entry(_threadid).or_default().label = Some(label);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn intervals() {
+        let eventlog = write(|w| {
+            w.begin_block(Timestamp(0), Timestamp(0), Some(CapNo(0)))?;
+            w.event(0, Timestamp(0), &Payload::new().u32(1))?;
+            w.event(44, Timestamp(0), &[&Payload::new().u32(1)[..], b"worker"].concat())?;
+            w.begin_block(Timestamp(5), Timestamp(5), Some(CapNo(1)))?;
+            w.event(1, Timestamp(5), &Payload::new().u32(2))?;
+            w.begin_block(Timestamp(10), Timestamp(10), Some(CapNo(0)))?;
+            w.event(1, Timestamp(10), &Payload::new().u32(1))?;
+            // BlockedOnMVar
+            w.event(2, Timestamp(20), &Payload::new().u32(1).u16(7).u32(0))?;
+            w.event(8, Timestamp(30), &Payload::new().u32(1).u16(1))?;
+            w.begin_block(Timestamp(35), Timestamp(35), Some(CapNo(1)))?;
+            // ThreadYielding
+            w.event(2, Timestamp(35), &Payload::new().u32(2).u16(3).u32(0))?;
+            w.event(1, Timestamp(40), &Payload::new().u32(1))?;
+            // ThreadFinished
+            w.event(2, Timestamp(50), &Payload::new().u32(1).u16(5).u32(0))?;
+            Ok(())
+        });
+
+        let mut threads = Threads::new();
+        parse_reader(&eventlog[..], &mut threads).unwrap();
+        let threads = threads.finish();
+
+        let interval = |start, end, state| StateInterval { start: Timestamp(start), end: Timestamp(end), state };
+        let thread = &threads[&ThreadId(1)];
+        assert_eq!(thread.label.as_deref(), Some("worker"));
+        assert_eq!(thread.created, Some(Timestamp(0)));
+        assert_eq!(thread.finished, Some(Timestamp(50)));
+        assert_eq!(thread.intervals, [
+            interval(0, 10, ThreadState::Runnable),
+            interval(10, 20, ThreadState::Running(CapNo(0))),
+            interval(20, 30, ThreadState::Blocked(ThreadStopStatus::BlockedOnMVar)),
+            interval(30, 40, ThreadState::Runnable),
+            interval(40, 50, ThreadState::Running(CapNo(1))),
+        ]);
+        assert_eq!(thread.state_at(Timestamp(20)), Some(ThreadState::Blocked(ThreadStopStatus::BlockedOnMVar)));
+        assert_eq!(thread.state_at(Timestamp(50)), None);
+
+        // created before the eventlog started, and still runnable at the end
+        let thread = &threads[&ThreadId(2)];
+        assert_eq!(thread.created, None);
+        assert_eq!(thread.finished, None);
+        assert_eq!(thread.intervals, [
+            interval(5, 35, ThreadState::Running(CapNo(1))),
+            interval(35, 50, ThreadState::Runnable),
+        ]);
+    }
+}
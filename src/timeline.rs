@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::parse::{Context, EventlogParser, ThreadStopStatus};
+use crate::types::{CapNo, ThreadId, Timestamp};
+
+/// What a capability is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Activity {
+    Running(ThreadId),
+    Gc,
+    Idle,
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Activity::Running(threadid) => write!(f, "running {threadid}"),
+            Activity::Gc => write!(f, "gc"),
+            Activity::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+/// A capability doing one thing from `start` up to `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub activity: Activity,
+}
+
+/// A capability that is currently enabled.
+struct Current {
+    activity: Activity,
+    since: Timestamp,
+}
+
+/// Reconstructs what every capability is doing over time.
+///
+/// A capability runs a thread from RUN_THREAD to STOP_THREAD, is in GC from GC_START to GC_END,
+/// and is idle otherwise. During a parallel GC a capability is idle between GC_IDLE and GC_WORK,
+/// and after GC_DONE. A capability only has intervals while it exists and is enabled, between
+/// CAP_CREATE or CAP_ENABLE and CAP_DELETE or CAP_DISABLE, capabilities that are not created
+/// (older versions of GHC do not write CAP_CREATE) start with their first event.
+///
+/// The capability of the scheduler and GC events is the capability of their block. The
+/// capability events can be written to the block of another capability, feed the events in time
+/// order (see [`crate::order::TimeOrder`]) to get them in the right place.
+///
+/// Call [`Timeline::finish`] after the last event to get the intervals.
+#[derive(Default)]
+pub struct Timeline {
+    current: BTreeMap<CapNo, Option<Current>>,
+    intervals: BTreeMap<CapNo, Vec<Interval>>,
+    /// Time of the last scheduler, GC or capability event
+    last: Timestamp,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline::default()
+    }
+
+    /// End the open intervals at the time of the last scheduler, GC or capability event, and
+    /// return the intervals of every capability in time order.
+    pub fn finish(mut self) -> BTreeMap<CapNo, Vec<Interval>> {
+        let caps: Vec<CapNo> = self.current.keys().copied().collect();
+        for capno in caps {
+            self.close(capno, self.last);
+        }
+        self.intervals
+    }
+
+    /// End the current interval of `capno` at `time`.
+    fn close(&mut self, capno: CapNo, time: Timestamp) {
+        self.last = self.last.max(time);
+        let Some(Some(current)) = self.current.insert(capno, None) else {
+            return;
+        };
+        if time > current.since {
+            self.intervals.entry(capno).or_default().push(Interval {
+                start: current.since,
+                end: time,
+                activity: current.activity,
+            });
+        }
+    }
+
+    /// Switch `capno` to `activity` at `time`, bringing the capability up if it is new.
+    fn switch(&mut self, capno: CapNo, time: Timestamp, activity: Activity) {
+        self.last = self.last.max(time);
+        match self.current.get(&capno) {
+            // disabled or deleted
+            Some(None) => return,
+            Some(Some(current)) if current.activity == activity => return,
+            _ => {},
+        }
+        self.close(capno, time);
+        self.current.insert(capno, Some(Current { activity, since: time }));
+    }
+
+    fn start(&mut self, capno: CapNo, time: Timestamp) {
+        self.last = self.last.max(time);
+        if !matches!(self.current.get(&capno), Some(Some(_))) {
+            self.current.insert(capno, Some(Current { activity: Activity::Idle, since: time }));
+        }
+    }
+}
+
+impl EventlogParser for Timeline {
+    fn event_cap_create(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.start(_capno, _ctx.time);
+    }
+    fn event_cap_enable(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.start(_capno, _ctx.time);
+    }
+    fn event_cap_delete(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.close(_capno, _ctx.time);
+    }
+    fn event_cap_disable(&mut self, _ctx: &Context, _capno: CapNo) {
+        self.close(_capno, _ctx.time);
+    }
+
+    fn event_thread_run(&mut self, _ctx: &Context, _threadid: ThreadId) {
+        if let Some(capno) = _ctx.capno {
+            self.switch(capno, _ctx.time, Activity::Running(_threadid));
+        }
+    }
+    fn event_thread_stop(&mut self, _ctx: &Context, _threadid: ThreadId, _status: ThreadStopStatus) {
+        if let Some(capno) = _ctx.capno {
+            self.switch(capno, _ctx.time, Activity::Idle);
+        }
+    }
+
+    fn event_gc_start(&mut self, _ctx: &Context) {
+        if let Some(capno) = _ctx.capno {
+            self.switch(capno, _ctx.time, Activity::Gc);
+        }
+    }
+    fn event_gc_work(&mut self, _ctx: &Context) {
+        if let Some(capno) = _ctx.capno {
+            self.switch(capno, _ctx.time, Activity::Gc);
+        }
+    }
+    fn event_gc_idle(&mut self, _ctx: &Context) {
+        if let Some(capno) = _ctx.capno {
+            self.switch(capno, _ctx.time, Activity::Idle);
+        }
+    }
+    fn event_gc_done(&mut self, _ctx: &Context) {
+        if let Some(capno) = _ctx.capno {
+            self.switch(capno, _ctx.time, Activity::Idle);
+        }
+    }
+    fn event_gc_end(&mut self, _ctx: &Context) {
+        if let Some(capno) = _ctx.capno {
+            self.switch(capno, _ctx.time, Activity::Idle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::tests::{write, Payload};
+    use crate::parse::parse_reader;
+
+    #[test]
+    fn intervals() {
+        let eventlog = write(|w| {
+            w.event(45, Timestamp(0), &Payload::new().u16(0))?;
+            w.begin_block(Timestamp(10), Timestamp(10), Some(CapNo(0)))?;
+            w.event(1, Timestamp(10), &Payload::new().u32(1))?;
+            w.begin_block(Timestamp(15), Timestamp(15), Some(CapNo(1)))?;
+            w.event(1, Timestamp(15), &Payload::new().u32(2))?;
+            w.begin_block(Timestamp(20), Timestamp(20), Some(CapNo(0)))?;
+            // ThreadYielding
+            w.event(2, Timestamp(20), &Payload::new().u32(1).u16(3).u32(0))?;
+            w.event(9, Timestamp(30), &[])?;
+            w.event(10, Timestamp(40), &[])?;
+            w.event(1, Timestamp(50), &Payload::new().u32(1))?;
+            w.end_block()?;
+            w.event(47, Timestamp(60), &Payload::new().u16(0))?;
+            // disabled, so left out
+            w.begin_block(Timestamp(65), Timestamp(65), Some(CapNo(0)))?;
+            w.event(9, Timestamp(65), &[])?;
+            w.begin_block(Timestamp(70), Timestamp(70), Some(CapNo(1)))?;
+            w.event(9, Timestamp(70), &[])?;
+            Ok(())
+        });
+
+        let mut timeline = Timeline::new();
+        parse_reader(&eventlog[..], &mut timeline).unwrap();
+        let intervals = timeline.finish();
+
+        let interval = |start, end, activity| Interval { start: Timestamp(start), end: Timestamp(end), activity };
+        assert_eq!(intervals[&CapNo(0)], [
+            interval(0, 10, Activity::Idle),
+            interval(10, 20, Activity::Running(ThreadId(1))),
+            interval(20, 30, Activity::Idle),
+            interval(30, 40, Activity::Gc),
+            interval(40, 50, Activity::Idle),
+            interval(50, 60, Activity::Running(ThreadId(1))),
+        ]);
+        // capability 1 was never created, it starts with its first event
+        assert_eq!(intervals[&CapNo(1)], [
+            interval(15, 70, Activity::Running(ThreadId(2))),
+        ]);
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A Haskell thread id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ThreadId(pub u32);
+
+/// A capability number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CapNo(pub u16);
+
+/// A capability set id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CapsetId(pub u32);
+
+/// An RTS task id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct TaskId(pub u64);
+
+/// Time since the start of the eventlog, in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp(pub u64);
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ti:{}", self.0)
+    }
+}
+
+impl fmt::Display for CapNo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "c:{}", self.0)
+    }
+}
+
+impl fmt::Display for CapsetId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cs:{}", self.0)
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ta:{}", self.0)
+    }
+}
+
+/// Parse an id, with or without the prefix it is shown with.
+fn parse_id<T: FromStr>(s: &str, prefix: &str) -> Result<T, String> {
+    s.strip_prefix(prefix).unwrap_or(s)
+        .parse()
+        .map_err(|_| format!("bad id: {s}"))
+}
+
+impl FromStr for ThreadId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_id(s, "ti:").map(ThreadId)
+    }
+}
+
+impl FromStr for CapNo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_id(s, "c:").map(CapNo)
+    }
+}
+
+impl FromStr for CapsetId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_id(s, "cs:").map(CapsetId)
+    }
+}
+
+impl FromStr for TaskId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_id(s, "ta:").map(TaskId)
+    }
+}
+
+impl Timestamp {
+    pub fn as_nanos(self) -> u64 {
+        self.0
+    }
+
+    /// The time since `earlier`, or zero if `earlier` is later.
+    pub fn saturating_duration_since(self, earlier: Timestamp) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+}
+
+/// Shown as seconds with all nine digits of nanoseconds, eg `1.000250000s`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:09}s", self.0 / 1_000_000_000, self.0 % 1_000_000_000)
+    }
+}
+
+/// Parses nanoseconds, or a number with one of the suffixes `ns`, `us`, `ms` or `s`.
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (num, scale) = if let Some(num) = s.strip_suffix("ns") {
+            (num, 1)
+        } else if let Some(num) = s.strip_suffix("us") {
+            (num, 1_000)
+        } else if let Some(num) = s.strip_suffix("ms") {
+            (num, 1_000_000)
+        } else if let Some(num) = s.strip_suffix('s') {
+            (num, 1_000_000_000)
+        } else {
+            (s, 1)
+        };
+
+        let nanos = match num.parse::<u64>() {
+            Ok(num) => num.checked_mul(scale),
+            Err(_) => num.parse::<f64>().ok()
+                .map(|num| num * scale as f64)
+                // `as` saturates, so infinite, negative and too large times are rejected first
+                .filter(|nanos| (0.0..u64::MAX as f64).contains(nanos))
+                .map(|nanos| nanos as u64),
+        };
+        nanos.map(Timestamp).ok_or_else(|| format!("bad time: {s}"))
+    }
+}
+
+fn duration_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Saturates at the largest timestamp.
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, rhs: Duration) -> Timestamp {
+        Timestamp(self.0.saturating_add(duration_nanos(rhs)))
+    }
+}
+
+/// Saturates at zero.
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, rhs: Duration) -> Timestamp {
+        Timestamp(self.0.saturating_sub(duration_nanos(rhs)))
+    }
+}
+
+/// Saturates at zero, like [`std::time::Instant`].
+impl Sub for Timestamp {
+    type Output = Duration;
+
+    fn sub(self, rhs: Timestamp) -> Duration {
+        self.saturating_duration_since(rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_units() {
+        let cases = [
+            ("1500", 1500),
+            ("1500ns", 1500),
+            ("2us", 2_000),
+            ("3ms", 3_000_000),
+            ("4s", 4_000_000_000),
+            ("1.5s", 1_500_000_000),
+            ("0.25ms", 250_000),
+            ("1e3us", 1_000_000),
+        ];
+        for (s, nanos) in cases {
+            assert_eq!(s.parse::<Timestamp>(), Ok(Timestamp(nanos)), "{s}");
+        }
+
+        let bad = [
+            "", "s", "-1s", "-0.5s", "1xs", "1 ms", "99999999999s", "99999999999.0s", "1e30s", "inf",
+            "infs", "NaN",
+        ];
+        for s in bad {
+            assert!(s.parse::<Timestamp>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn timestamp_arithmetic_saturates() {
+        assert_eq!(Timestamp(5) - Duration::from_nanos(10), Timestamp(0));
+        assert_eq!(Timestamp(u64::MAX - 1) + Duration::from_nanos(10), Timestamp(u64::MAX));
+        assert_eq!(Timestamp(0) + Duration::MAX, Timestamp(u64::MAX));
+        assert_eq!(Timestamp(5) - Timestamp(10), Duration::ZERO);
+    }
+}